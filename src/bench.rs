@@ -0,0 +1,138 @@
+use std::time::{Duration, Instant};
+
+use reqwest::Client;
+use serde::Serialize;
+
+use crate::{api_client, print_json, Config, OutputFormat};
+
+/// Pause between requests, to stay well clear of Cloudflare's rate limits.
+const REQUEST_SPACING: Duration = Duration::from_millis(200);
+
+#[derive(Serialize)]
+struct Latency {
+    endpoint: String,
+    requests: u32,
+    failures: u32,
+    min_ms: u128,
+    avg_ms: u128,
+    max_ms: u128,
+    p95_ms: u128,
+    /// The last error, if any request failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Sends `count` requests to each read-only endpoint an update cycle relies
+/// on (token verification and the zone list, per account) and reports their
+/// latency. Returns 0 if every request succeeded and 1 otherwise.
+pub async fn run(config: &Config, count: u32, output: OutputFormat) -> i32 {
+    let client = api_client(config);
+    let mut results = Vec::new();
+
+    for account in config.accounts() {
+        let label = account.label();
+        let endpoints = [
+            (
+                format!("token verify ({})", label),
+                account.api("/user/tokens/verify"),
+            ),
+            (format!("zone list ({})", label), account.api("/zones")),
+        ];
+
+        for (endpoint, url) in endpoints {
+            results.push(measure(&client, &account.api_token, endpoint, &url, count).await);
+        }
+    }
+
+    match output {
+        OutputFormat::Text => {
+            println!(
+                "{:<40} {:>7} {:>8} {:>8} {:>8} {:>8}",
+                "endpoint", "ok", "min ms", "avg ms", "max ms", "p95 ms"
+            );
+            for result in &results {
+                println!(
+                    "{:<40} {:>7} {:>8} {:>8} {:>8} {:>8}",
+                    result.endpoint,
+                    format!("{}/{}", result.requests - result.failures, result.requests),
+                    result.min_ms,
+                    result.avg_ms,
+                    result.max_ms,
+                    result.p95_ms
+                );
+            }
+            for result in results.iter().filter(|result| result.error.is_some()) {
+                println!(
+                    "{}: {}",
+                    result.endpoint,
+                    result.error.as_deref().unwrap_or_default()
+                );
+            }
+        }
+        OutputFormat::Json => print_json(&results),
+    }
+
+    if results.iter().all(|result| result.failures == 0) {
+        0
+    } else {
+        1
+    }
+}
+
+/// Times `count` GET requests to `url`. Failed requests are timed too, as a
+/// slow failure is just as relevant when picking timeouts.
+async fn measure(
+    client: &Client,
+    api_token: &str,
+    endpoint: String,
+    url: &str,
+    count: u32,
+) -> Latency {
+    let mut samples = Vec::new();
+    let mut failures = 0;
+    let mut error = None;
+
+    for i in 0..count {
+        if i > 0 {
+            tokio::time::sleep(REQUEST_SPACING).await;
+        }
+
+        let started = Instant::now();
+        let result = async {
+            client
+                .get(url)
+                .header("Authorization", format!("Bearer {}", api_token))
+                .send()
+                .await?
+                .error_for_status()?
+                .bytes()
+                .await
+        }
+        .await;
+        samples.push(started.elapsed().as_millis());
+
+        if let Err(e) = result {
+            failures += 1;
+            error = Some(e.to_string());
+        }
+    }
+
+    samples.sort_unstable();
+    let total: u128 = samples.iter().sum();
+    let p95 = samples
+        .len()
+        .saturating_mul(95)
+        .div_ceil(100)
+        .saturating_sub(1);
+
+    Latency {
+        endpoint,
+        requests: count,
+        failures,
+        min_ms: samples.first().copied().unwrap_or_default(),
+        avg_ms: total / samples.len().max(1) as u128,
+        max_ms: samples.last().copied().unwrap_or_default(),
+        p95_ms: samples.get(p95).copied().unwrap_or_default(),
+        error,
+    }
+}
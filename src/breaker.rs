@@ -0,0 +1,150 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Longest a record is ever skipped for, however often it keeps failing.
+const MAX_COOLDOWN: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Default)]
+struct Entry {
+    failures: u32,
+    trips: u32,
+    open_until: Option<Instant>,
+}
+
+/// A breaker that isn't closed.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    /// The record is skipped until its cooldown is over.
+    Open,
+    /// The cooldown is over: the next attempt closes the breaker on success
+    /// and opens it again on failure.
+    HalfOpen,
+}
+
+impl BreakerState {
+    /// The state of a tripped breaker with `remaining` cooldown left.
+    pub fn with_remaining(remaining: Duration) -> Self {
+        if remaining.is_zero() {
+            BreakerState::HalfOpen
+        } else {
+            BreakerState::Open
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BreakerState::Open => "open",
+            BreakerState::HalfOpen => "half_open",
+        }
+    }
+}
+
+/// Skips records that keep failing so they don't slow down every cycle.
+/// After `threshold` consecutive failures a record is left alone for
+/// `cooldown`; each further failure once it is retried doubles the cooldown.
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl CircuitBreaker {
+    /// A `threshold` of 0 disables the breaker.
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        CircuitBreaker {
+            threshold,
+            cooldown,
+            entries: Mutex::default(),
+        }
+    }
+
+    /// How much longer `key` is skipped for at `now`, if its breaker is open.
+    pub fn open_for(&self, key: &str, now: Instant) -> Option<Duration> {
+        let entries = self.entries.lock().unwrap();
+        let open_until = entries.get(key)?.open_until?;
+        open_until
+            .checked_duration_since(now)
+            .filter(|remaining| !remaining.is_zero())
+    }
+
+    /// Records a failure of `key` at `now`, returning the cooldown if this
+    /// opened its breaker.
+    pub fn failure(&self, key: &str, now: Instant) -> Option<Duration> {
+        if self.threshold == 0 {
+            return None;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.entry(key.to_string()).or_default();
+        entry.failures += 1;
+        if entry.failures < self.threshold {
+            return None;
+        }
+
+        let cooldown = self
+            .cooldown
+            .checked_mul(1 << entry.trips.min(16))
+            .unwrap_or(MAX_COOLDOWN)
+            .min(MAX_COOLDOWN);
+        entry.trips += 1;
+        entry.open_until = Some(now + cooldown);
+        Some(cooldown)
+    }
+
+    /// Every key whose breaker has opened and not closed again, with the
+    /// cooldown left at `now` (zero once it is half-open).
+    pub fn tripped(&self, now: Instant) -> BTreeMap<String, Duration> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(key, entry)| {
+                let open_until = entry.open_until?;
+                Some((key.clone(), open_until.saturating_duration_since(now)))
+            })
+            .collect()
+    }
+
+    /// Closes the breaker of `key` after a success.
+    pub fn success(&self, key: &str) {
+        self.entries.lock().unwrap().remove(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn breakers_open_then_go_half_open_after_the_cooldown() {
+        let breaker = CircuitBreaker::new(2, Duration::from_secs(60));
+        let start = Instant::now();
+
+        assert_eq!(breaker.failure("home.example.com", start), None);
+        assert!(breaker.tripped(start).is_empty());
+        assert_eq!(
+            breaker.failure("home.example.com", start),
+            Some(Duration::from_secs(60))
+        );
+
+        let tripped = breaker.tripped(start + Duration::from_secs(20));
+        let remaining = tripped["home.example.com"];
+        assert_eq!(remaining, Duration::from_secs(40));
+        assert_eq!(BreakerState::with_remaining(remaining), BreakerState::Open);
+
+        let tripped = breaker.tripped(start + Duration::from_secs(60));
+        assert_eq!(
+            BreakerState::with_remaining(tripped["home.example.com"]),
+            BreakerState::HalfOpen
+        );
+
+        breaker.success("home.example.com");
+        assert!(breaker.tripped(start + Duration::from_secs(60)).is_empty());
+    }
+}
@@ -0,0 +1,214 @@
+use std::error::Error;
+
+use reqwest::Client;
+use serde::Serialize;
+use tracing::{error, info, warn};
+
+use crate::{
+    api_client, find_records, print_json, record_zone_id, select_records, Account,
+    CloudflareResponse, Config, DnsRecord, DnsRecordInfo, OutputFormat, TypeMismatch,
+};
+
+#[derive(Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum CleanupStatus {
+    /// Would be deleted, but `--yes` was not given.
+    Pending,
+    Deleted,
+    NotFound,
+    Error {
+        error: String,
+    },
+}
+
+#[derive(Serialize)]
+struct CleanupResult {
+    record: String,
+    record_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    #[serde(flatten)]
+    status: CleanupStatus,
+}
+
+/// Deletes every configured record from Cloudflare, or only lists them when
+/// `confirmed` is false. Without `owned_comment_marker` nothing is deleted
+/// unless `force` is set too. Returns the process exit code: 0 when
+/// everything was deleted (or already gone), 1 when confirmation is missing
+/// and 3 when any record could not be deleted.
+pub async fn run(config: &Config, confirmed: bool, force: bool, output: OutputFormat) -> i32 {
+    let marker = config.owned_comment_marker.as_deref();
+    let refused = confirmed && !may_delete(marker, force);
+    if refused {
+        warn!("Without owned_comment_marker every matching record would be deleted, including ones created by hand; pass --force to delete them anyway");
+    }
+    let confirmed = confirmed && !refused;
+    let mut results = Vec::new();
+
+    for account in config.accounts() {
+        let client = api_client(config);
+
+        for record in &account.dns_records {
+            results.extend(cleanup_record(&client, &account, record, marker, confirmed).await);
+        }
+    }
+
+    match output {
+        OutputFormat::Text => {
+            for result in &results {
+                let line = match &result.status {
+                    CleanupStatus::Pending => "would be deleted".to_string(),
+                    CleanupStatus::Deleted => "deleted".to_string(),
+                    CleanupStatus::NotFound => "not found".to_string(),
+                    CleanupStatus::Error { error } => format!("error: {}", error),
+                };
+                println!("{} ({}): {}", result.record, result.record_type, line);
+            }
+
+            if refused {
+                println!("Nothing was deleted; without owned_comment_marker re-run with --yes --force to delete these records");
+            } else if !confirmed {
+                println!("Nothing was deleted; re-run with --yes to delete these records");
+            }
+        }
+        OutputFormat::Json => print_json(&results),
+    }
+
+    if results
+        .iter()
+        .any(|r| matches!(r.status, CleanupStatus::Error { .. }))
+    {
+        3
+    } else if !confirmed {
+        1
+    } else {
+        0
+    }
+}
+
+/// Whether confirmed deletions may go ahead. Without an ownership marker
+/// every record matching a configured name is deleted, so that takes
+/// `force` as well.
+fn may_delete(marker: Option<&str>, force: bool) -> bool {
+    marker.is_some() || force
+}
+
+async fn cleanup_record(
+    client: &Client,
+    account: &Account,
+    record: &DnsRecord,
+    marker: Option<&str>,
+    confirmed: bool,
+) -> Vec<CleanupResult> {
+    let result = |id: Option<String>, status| CleanupResult {
+        record: record.dns_name.clone(),
+        record_type: record.record_type.to_string(),
+        id,
+        status,
+    };
+
+    let failed = |e: Box<dyn Error>| {
+        vec![result(
+            None,
+            CleanupStatus::Error {
+                error: e.to_string(),
+            },
+        )]
+    };
+
+    let (zone_id, matches) = match find_managed(client, account, record, marker).await {
+        Ok(found) => found,
+        // Only records of other types exist under this name: nothing to delete.
+        Err(e) if e.is::<TypeMismatch>() => return vec![result(None, CleanupStatus::NotFound)],
+        Err(e) => return failed(e),
+    };
+    if matches.is_empty() {
+        return vec![result(None, CleanupStatus::NotFound)];
+    }
+    let matches = match select_records(record, matches) {
+        Ok(matches) => matches,
+        Err(e) => return failed(e),
+    };
+
+    let mut results = Vec::new();
+    for info in matches {
+        let status = if !confirmed {
+            CleanupStatus::Pending
+        } else {
+            match delete_dns_record(client, account, &zone_id, &info.id).await {
+                Ok(()) => {
+                    info!("Deleted DNS record {} ({})", record.dns_name, info.id);
+                    CleanupStatus::Deleted
+                }
+                Err(e) => {
+                    error!("Failed to delete DNS record {}: {}", record.dns_name, e);
+                    CleanupStatus::Error {
+                        error: e.to_string(),
+                    }
+                }
+            }
+        };
+
+        results.push(result(Some(info.id), status));
+    }
+
+    results
+}
+
+/// Looks up the zone of `record` and the Cloudflare records this tool
+/// manages for it.
+async fn find_managed(
+    client: &Client,
+    account: &Account,
+    record: &DnsRecord,
+    marker: Option<&str>,
+) -> Result<(String, Vec<DnsRecordInfo>), Box<dyn Error>> {
+    let domain = record.zone().ok_or("invalid domain name")?;
+    let zone_id = record_zone_id(client, account, record, &domain).await?;
+    let matches = find_records(
+        client,
+        account,
+        &zone_id,
+        record.cloudflare_name(),
+        record.record_type,
+        marker,
+    )
+    .await?;
+
+    Ok((zone_id, matches))
+}
+
+async fn delete_dns_record(
+    client: &Client,
+    account: &Account,
+    zone_id: &str,
+    record_id: &str,
+) -> Result<(), Box<dyn Error>> {
+    let url = account.api(&format!("/zones/{}/dns_records/{}", zone_id, record_id));
+
+    let response: CloudflareResponse = client
+        .delete(&url)
+        .header("Authorization", format!("Bearer {}", account.api_token))
+        .send()
+        .await?
+        .json()
+        .await?;
+
+    if response.success {
+        Ok(())
+    } else {
+        Err(format!("Cloudflare API error: {:?}", response.errors).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deleting_without_a_marker_needs_force() {
+        assert!(may_delete(Some("managed-by-ddns"), false));
+        assert!(!may_delete(None, false));
+        assert!(may_delete(None, true));
+    }
+}
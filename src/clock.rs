@@ -0,0 +1,226 @@
+use std::{
+    future::Future,
+    hash::{BuildHasher, RandomState},
+    pin::Pin,
+    time::{Duration, Instant, SystemTime},
+};
+
+use chrono::{DateTime, Local, Utc};
+use tracing::warn;
+
+/// Source of time for the update loop, so schedules, debouncing, active
+/// hours and backoff can be driven deterministically instead of by the
+/// wall clock.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+
+    /// Monotonic time, for measuring durations.
+    fn instant(&self) -> Instant;
+
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>>;
+
+    fn utc(&self) -> DateTime<Utc> {
+        self.now().into()
+    }
+
+    fn local(&self) -> DateTime<Local> {
+        self.now().into()
+    }
+}
+
+/// Source of randomness for jitter.
+pub trait Rng: Send + Sync {
+    /// A value in `0..max`; `max` must not be 0.
+    fn below(&self, max: u64) -> u64;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// Randomness from the standard library's randomly seeded hasher, which is
+/// plenty for spreading out requests.
+pub struct SystemRng;
+
+impl Rng for SystemRng {
+    fn below(&self, max: u64) -> u64 {
+        RandomState::new().hash_one(Instant::now()) % max
+    }
+}
+
+/// Longest single sleep while waiting for the next cycle, so a suspend or
+/// clock change is noticed soon after it happens.
+const SLEEP_SLICE: Duration = Duration::from_secs(60);
+
+/// Difference between wall-clock and monotonic progress over one slice that
+/// counts as a time jump rather than scheduling noise.
+const JUMP_TOLERANCE: Duration = Duration::from_secs(30);
+
+/// Sleeps for `delay` measured on the monotonic clock. Returns true if the
+/// wait was cut short because the wall clock jumped ahead meanwhile (the
+/// machine was suspended, or the clock was set forward). A backwards jump is
+/// logged and otherwise ignored.
+pub async fn sleep_until_due(clock: &dyn Clock, delay: Duration) -> bool {
+    let deadline = clock.instant() + delay;
+
+    loop {
+        let now = clock.instant();
+        let Some(remaining) = deadline
+            .checked_duration_since(now)
+            .filter(|d| !d.is_zero())
+        else {
+            return false;
+        };
+
+        let slice = remaining.min(SLEEP_SLICE);
+        let wall_before = clock.now();
+        clock.sleep(slice).await;
+        let monotonic = clock.instant().duration_since(now);
+
+        match clock.now().duration_since(wall_before) {
+            Ok(wall) if wall > monotonic + JUMP_TOLERANCE => {
+                warn!(
+                    "Wall clock moved {}s during a {}s wait (suspend or clock change)",
+                    wall.as_secs(),
+                    monotonic.as_secs()
+                );
+                return true;
+            }
+            Err(e) if e.duration() > JUMP_TOLERANCE => {
+                warn!(
+                    "Wall clock jumped back by {}s, keeping the current schedule",
+                    e.duration().as_secs()
+                );
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Deterministic clock and randomness for tests.
+#[cfg(test)]
+pub mod fake {
+    use super::*;
+
+    /// A clock that only moves when told to. Sleeping returns at once and
+    /// advances both clocks by the requested duration.
+    pub struct FakeClock {
+        state: std::sync::Mutex<FakeState>,
+    }
+
+    struct FakeState {
+        wall: SystemTime,
+        monotonic: Instant,
+        /// Extra wall-clock time added by the next sleep, like a suspend.
+        wall_jump: Duration,
+        slept: Vec<Duration>,
+    }
+
+    impl FakeClock {
+        /// A clock whose wall time starts at `wall`.
+        pub fn at(wall: SystemTime) -> Self {
+            FakeClock {
+                state: std::sync::Mutex::new(FakeState {
+                    wall,
+                    monotonic: Instant::now(),
+                    wall_jump: Duration::ZERO,
+                    slept: Vec::new(),
+                }),
+            }
+        }
+
+        pub fn advance(&self, duration: Duration) {
+            let mut state = self.state.lock().unwrap();
+            state.wall += duration;
+            state.monotonic += duration;
+        }
+
+        /// Moves the wall clock by an extra `duration` during the next sleep.
+        pub fn jump_on_next_sleep(&self, duration: Duration) {
+            self.state.lock().unwrap().wall_jump = duration;
+        }
+
+        /// Every duration slept so far, in order.
+        pub fn slept(&self) -> Vec<Duration> {
+            self.state.lock().unwrap().slept.clone()
+        }
+    }
+
+    impl Default for FakeClock {
+        fn default() -> Self {
+            FakeClock::at(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> SystemTime {
+            self.state.lock().unwrap().wall
+        }
+
+        fn instant(&self) -> Instant {
+            self.state.lock().unwrap().monotonic
+        }
+
+        fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+            let mut state = self.state.lock().unwrap();
+            let jump = std::mem::take(&mut state.wall_jump);
+            state.wall += duration + jump;
+            state.monotonic += duration;
+            state.slept.push(duration);
+            Box::pin(std::future::ready(()))
+        }
+    }
+
+    /// Always returns the same value, reduced to the requested range.
+    pub struct FakeRng(pub u64);
+
+    impl Rng for FakeRng {
+        fn below(&self, max: u64) -> u64 {
+            self.0 % max
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fake::FakeClock, *};
+
+    #[tokio::test]
+    async fn waits_the_full_delay_without_a_jump() {
+        let clock = FakeClock::default();
+        let start = clock.instant();
+
+        assert!(!sleep_until_due(&clock, Duration::from_secs(150)).await);
+        assert_eq!(clock.instant() - start, Duration::from_secs(150));
+    }
+
+    #[tokio::test]
+    async fn a_forward_jump_cuts_the_wait_short() {
+        let clock = FakeClock::default();
+        let start = clock.instant();
+        clock.jump_on_next_sleep(Duration::from_secs(3600));
+
+        assert!(sleep_until_due(&clock, Duration::from_secs(300)).await);
+        assert_eq!(clock.instant() - start, SLEEP_SLICE);
+    }
+
+    #[tokio::test]
+    async fn a_small_drift_is_not_a_jump() {
+        let clock = FakeClock::default();
+        clock.jump_on_next_sleep(JUMP_TOLERANCE / 2);
+
+        assert!(!sleep_until_due(&clock, Duration::from_secs(90)).await);
+    }
+}
@@ -0,0 +1,111 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use tracing::{error, info};
+
+/// How often a still-repeating error is logged again.
+const REPEAT_LOG_INTERVAL: Duration = Duration::from_secs(60);
+
+struct Entry {
+    message: String,
+    repeats: u64,
+    last_logged: Instant,
+}
+
+/// Collapses errors that repeat every cycle (e.g. during an outage) into a
+/// periodic "repeated N times" line instead of logging each occurrence.
+#[derive(Default)]
+pub struct ErrorCoalescer {
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl ErrorCoalescer {
+    /// Logs `message` for the error source `key`, unless it is identical to
+    /// the last message for that source and was logged less than a minute
+    /// before `now`.
+    pub fn error(&self, key: &str, message: String, now: Instant) {
+        let mut entries = self.entries.lock().unwrap();
+
+        match entries.get_mut(key) {
+            Some(entry) if entry.message == message => {
+                entry.repeats += 1;
+                if now.duration_since(entry.last_logged) >= REPEAT_LOG_INTERVAL {
+                    error!("{} (repeated {} times)", message, entry.repeats);
+                    entry.repeats = 0;
+                    entry.last_logged = now;
+                }
+            }
+            _ => {
+                error!("{}", message);
+                entries.insert(
+                    key.to_string(),
+                    Entry {
+                        message,
+                        repeats: 0,
+                        last_logged: now,
+                    },
+                );
+            }
+        }
+    }
+
+    /// Marks the error source `key` as recovered, flushing any repeats that
+    /// were not logged yet.
+    pub fn clear(&self, key: &str) {
+        let Some(entry) = self.entries.lock().unwrap().remove(key) else {
+            return;
+        };
+
+        if entry.repeats > 0 {
+            info!(
+                "Recovered from: {} (repeated {} more times)",
+                entry.message, entry.repeats
+            );
+        } else {
+            info!("Recovered from: {}", entry.message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{fake::FakeClock, Clock};
+
+    fn repeats(errors: &ErrorCoalescer, key: &str) -> Option<u64> {
+        errors.entries.lock().unwrap().get(key).map(|e| e.repeats)
+    }
+
+    #[test]
+    fn repeats_are_counted_until_the_interval_passes() {
+        let clock = FakeClock::default();
+        let errors = ErrorCoalescer::default();
+
+        errors.error("ip", "down".to_string(), clock.instant());
+        clock.advance(Duration::from_secs(30));
+        errors.error("ip", "down".to_string(), clock.instant());
+        errors.error("ip", "down".to_string(), clock.instant());
+        assert_eq!(repeats(&errors, "ip"), Some(2));
+
+        clock.advance(REPEAT_LOG_INTERVAL);
+        errors.error("ip", "down".to_string(), clock.instant());
+        assert_eq!(repeats(&errors, "ip"), Some(0));
+    }
+
+    #[test]
+    fn a_new_message_is_logged_at_once() {
+        let clock = FakeClock::default();
+        let errors = ErrorCoalescer::default();
+
+        errors.error("ip", "down".to_string(), clock.instant());
+        errors.error("ip", "down".to_string(), clock.instant());
+        errors.error("ip", "timed out".to_string(), clock.instant());
+        assert_eq!(repeats(&errors, "ip"), Some(0));
+
+        errors.clear("ip");
+        assert_eq!(repeats(&errors, "ip"), None);
+    }
+}
@@ -0,0 +1,135 @@
+use std::{error::Error, process::Output, time::Duration};
+
+use tokio::process::Command;
+
+use crate::RecordType;
+
+/// Runs `command` through `sh -c` and returns its trimmed stdout, which must
+/// be a single line holding content for `record_type`: an address for A and
+/// AAAA records, any text for TXT records. The command is killed if it runs
+/// longer than `timeout`.
+pub async fn content(
+    command: &str,
+    record_type: RecordType,
+    timeout: Duration,
+) -> Result<String, Box<dyn Error>> {
+    let output = run("content_command", command, &[], timeout).await?;
+
+    let stdout =
+        String::from_utf8(output.stdout).map_err(|_| "content_command printed invalid UTF-8")?;
+    let content = stdout.trim();
+    if content.is_empty() {
+        return Err("content_command printed nothing".into());
+    }
+    if content.lines().count() > 1 {
+        return Err("content_command printed more than one line".into());
+    }
+    if !record_type.accepts_content(content) {
+        let expected = match record_type {
+            RecordType::A => "an IPv4 address",
+            RecordType::Aaaa => "an IPv6 address",
+            RecordType::Txt => "at most 2048 characters",
+        };
+        let preview: String = content.chars().take(100).collect();
+        return Err(format!(
+            "content_command printed {:?}, but must print {} for a {} record",
+            preview, expected, record_type
+        )
+        .into());
+    }
+
+    Ok(content.to_string())
+}
+
+/// Runs a `pre_update_command` or `post_update_command` (named by `name`)
+/// through `sh -c` with `env` added to its environment. Its output is
+/// ignored; only the exit status matters.
+pub async fn hook(
+    name: &str,
+    command: &str,
+    env: &[(&str, &str)],
+    timeout: Duration,
+) -> Result<(), Box<dyn Error>> {
+    run(name, command, env, timeout).await.map(|_| ())
+}
+
+/// Runs `command` and returns its output if it exited with status 0.
+async fn run(
+    name: &str,
+    command: &str,
+    env: &[(&str, &str)],
+    timeout: Duration,
+) -> Result<Output, Box<dyn Error>> {
+    let output = tokio::time::timeout(
+        timeout,
+        Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .envs(env.iter().copied())
+            .kill_on_drop(true)
+            .output(),
+    )
+    .await
+    .map_err(|_| format!("{} timed out after {}s", name, timeout.as_secs()))??;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let mut message = format!("{} failed with {}", name, output.status);
+        if !stderr.trim().is_empty() {
+            message = format!("{}: {}", message, stderr.trim());
+        }
+        return Err(message.into());
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[tokio::test]
+    async fn content_is_the_trimmed_address_printed() {
+        let ipv4 = content("echo '  9.9.9.9  '", RecordType::A, TIMEOUT).await;
+        assert_eq!(ipv4.unwrap(), "9.9.9.9");
+
+        let ipv6 = content("echo 2620:fe::9", RecordType::Aaaa, TIMEOUT).await;
+        assert_eq!(ipv6.unwrap(), "2620:fe::9");
+
+        let txt = content("echo 'v=spf1 ip4:9.9.9.9 -all'", RecordType::Txt, TIMEOUT).await;
+        assert_eq!(txt.unwrap(), "v=spf1 ip4:9.9.9.9 -all");
+    }
+
+    #[tokio::test]
+    async fn content_other_than_an_address_of_the_record_type_is_rejected() {
+        let error = content("echo v=spf1 -all", RecordType::A, TIMEOUT)
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("must print an IPv4 address"), "{}", error);
+
+        let error = content("echo 9.9.9.9", RecordType::Aaaa, TIMEOUT)
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("must print an IPv6 address"), "{}", error);
+
+        let error = content("printf '9.9.9.9\\n1.1.1.1'", RecordType::A, TIMEOUT)
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("more than one line"), "{}", error);
+
+        let error = content(
+            "head -c 3000 /dev/zero | tr '\\0' x",
+            RecordType::Txt,
+            TIMEOUT,
+        )
+        .await
+        .unwrap_err()
+        .to_string();
+        assert!(error.contains("at most 2048 characters"), "{}", error);
+    }
+}
@@ -0,0 +1,193 @@
+use std::{
+    collections::BTreeSet,
+    error::Error,
+    io::{stdout, IsTerminal},
+};
+
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api_client, check_success, clock::SystemClock, find_records, get_public_ip, ip_client,
+    print_json, query_ip_provider, record_zone_id, select_records, Account, Config, DnsRecord,
+    IpSource, OutputFormat, ZoneResponse,
+};
+
+#[derive(Serialize)]
+struct Check {
+    check: String,
+    ok: bool,
+    detail: String,
+}
+
+#[derive(Deserialize)]
+struct TokenVerifyResponse {
+    success: bool,
+    #[serde(default)]
+    errors: Vec<serde_json::Value>,
+    result: Option<TokenStatus>,
+}
+
+#[derive(Deserialize)]
+struct TokenStatus {
+    status: String,
+}
+
+/// Runs read-only checks of everything an update cycle depends on: each
+/// token, the zones it can see, every IP provider and the lookup of every
+/// configured record. Returns 0 if all checks pass and 1 otherwise.
+pub async fn run(config: &Config, output: OutputFormat) -> i32 {
+    let client = api_client(config);
+    let mut checks = Vec::new();
+    let mut push = |check: String, result: Result<String, Box<dyn Error>>| {
+        let (ok, detail) = match result {
+            Ok(detail) => (true, detail),
+            Err(e) => (false, e.to_string()),
+        };
+        checks.push(Check { check, ok, detail });
+    };
+
+    let accounts = config.accounts();
+    for account in &accounts {
+        let label = account.label();
+        push(
+            format!("token ({})", label),
+            verify_token(&client, account).await,
+        );
+        push(
+            format!("zones ({})", label),
+            list_zones(&client, account).await,
+        );
+    }
+
+    let families: BTreeSet<_> = accounts
+        .iter()
+        .flat_map(|a| &a.dns_records)
+        .map(|r| r.record_type)
+        .collect();
+    let ip_client = ip_client(config);
+    for record_type in families {
+        if let (IpSource::File, Some(path)) = (config.ip_source, &config.ip_file) {
+            let result = get_public_ip(config, &SystemClock, record_type)
+                .await
+                .map(|(ip, _)| ip);
+            push(format!("{} from {}", record_type, path), result);
+            continue;
+        }
+
+        for provider in config.ip_providers(record_type) {
+            let result = query_ip_provider(config, &ip_client, record_type, &provider).await;
+            push(format!("{} via {}", record_type, provider.url), result);
+        }
+    }
+
+    for account in &accounts {
+        for record in &account.dns_records {
+            push(
+                format!("record {} ({})", record.dns_name, record.record_type),
+                resolve_record(&client, account, record, config).await,
+            );
+        }
+    }
+
+    match output {
+        OutputFormat::Text => {
+            let color = stdout().is_terminal();
+            for check in &checks {
+                let status = match (check.ok, color) {
+                    (true, true) => "\x1b[32m OK \x1b[0m",
+                    (false, true) => "\x1b[31mFAIL\x1b[0m",
+                    (true, false) => " OK ",
+                    (false, false) => "FAIL",
+                };
+                println!("[{}] {}: {}", status, check.check, check.detail);
+            }
+        }
+        OutputFormat::Json => print_json(&checks),
+    }
+
+    if checks.iter().all(|check| check.ok) {
+        0
+    } else {
+        1
+    }
+}
+
+async fn verify_token(client: &Client, account: &Account) -> Result<String, Box<dyn Error>> {
+    let response: TokenVerifyResponse = client
+        .get(account.api("/user/tokens/verify"))
+        .header("Authorization", format!("Bearer {}", account.api_token))
+        .send()
+        .await?
+        .json()
+        .await?;
+
+    match response.result {
+        Some(token) if response.success && token.status == "active" => {
+            Ok("valid and active".to_string())
+        }
+        Some(token) if response.success => Err(format!("token is {}", token.status).into()),
+        _ => Err(format!("Cloudflare API error: {:?}", response.errors).into()),
+    }
+}
+
+async fn list_zones(client: &Client, account: &Account) -> Result<String, Box<dyn Error>> {
+    let mut request = client
+        .get(account.api("/zones"))
+        .query(&[("per_page", "50")]);
+    if let Some(account_id) = &account.account_id {
+        request = request.query(&[("account.id", account_id)]);
+    }
+
+    let response: ZoneResponse = request
+        .header("Authorization", format!("Bearer {}", account.api_token))
+        .send()
+        .await?
+        .json()
+        .await?;
+
+    check_success(response.success, &response.errors)?;
+    if response.result.is_empty() {
+        return Err("the token can't see any zones".into());
+    }
+
+    let names: Vec<_> = response
+        .result
+        .iter()
+        .map(|zone| zone.name.as_str())
+        .collect();
+    Ok(names.join(", "))
+}
+
+/// Looks up the zone and Cloudflare record(s) of `record` the same way an
+/// update cycle would, without changing anything.
+async fn resolve_record(
+    client: &Client,
+    account: &Account,
+    record: &DnsRecord,
+    config: &Config,
+) -> Result<String, Box<dyn Error>> {
+    let domain = record.zone().ok_or("invalid domain name")?;
+    let zone_id = record_zone_id(client, account, record, &domain).await?;
+
+    let matches = find_records(
+        client,
+        account,
+        &zone_id,
+        record.cloudflare_name(),
+        record.record_type,
+        config.owned_comment_marker.as_deref(),
+    )
+    .await?;
+    if matches.is_empty() {
+        return Err(format!("no matching record in zone {}", domain).into());
+    }
+
+    let matches = select_records(record, matches)?;
+    let contents: Vec<_> = matches.iter().map(|info| info.content.as_str()).collect();
+    Ok(format!(
+        "found in zone {}, currently {}",
+        domain,
+        contents.join(", ")
+    ))
+}
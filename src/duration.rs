@@ -0,0 +1,89 @@
+use std::fmt;
+
+use serde::de::{self, Deserializer, Visitor};
+
+/// Deserializes a number of seconds given either as a bare integer or as a
+/// duration string such as `"90s"`, `"5m"` or `"1h 30m"`.
+pub fn deserialize<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_any(SecondsVisitor)
+}
+
+/// Parses a duration string, or a bare number of seconds, into seconds.
+/// Durations that aren't whole seconds, such as `"500ms"`, are rejected
+/// rather than rounded down.
+pub fn parse(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Ok(secs);
+    }
+
+    let duration = humantime::parse_duration(value)
+        .map_err(|e| format!("invalid duration {:?}: {}", value, e))?;
+    if duration.subsec_nanos() != 0 {
+        return Err(format!(
+            "invalid duration {:?}: must be a whole number of seconds",
+            value
+        ));
+    }
+    Ok(duration.as_secs())
+}
+
+struct SecondsVisitor;
+
+impl Visitor<'_> for SecondsVisitor {
+    type Value = u64;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a number of seconds or a duration such as \"5m\"")
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<u64, E> {
+        Ok(value)
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<u64, E> {
+        u64::try_from(value).map_err(|_| E::custom("duration must not be negative"))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<u64, E> {
+        parse(value).map_err(E::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_seconds_and_duration_strings() {
+        assert_eq!(parse("300"), Ok(300));
+        assert_eq!(parse("90s"), Ok(90));
+        assert_eq!(parse("2m"), Ok(120));
+        assert_eq!(parse("1h 30m"), Ok(5400));
+    }
+
+    #[test]
+    fn rejects_sub_second_durations() {
+        assert!(parse("500ms").is_err());
+        assert!(parse("1500ms").is_err());
+        assert_eq!(parse("2000ms"), Ok(2));
+    }
+
+    #[test]
+    fn deserializes_integers_and_strings() {
+        #[derive(serde::Deserialize)]
+        struct Interval {
+            #[serde(deserialize_with = "deserialize")]
+            every: u64,
+        }
+
+        let every = |toml| toml::from_str::<Interval>(toml).map(|i| i.every);
+        assert_eq!(every("every = 300").unwrap(), 300);
+        assert_eq!(every("every = \"2m\"").unwrap(), 120);
+        assert!(every("every = -5").is_err());
+        assert!(every("every = \"500ms\"").is_err());
+    }
+}
@@ -0,0 +1,85 @@
+use std::{fs, io, path::Path};
+
+use serde::Serialize;
+use tokio::{
+    io::AsyncWriteExt,
+    net::{UnixListener, UnixStream},
+    sync::broadcast,
+};
+use tracing::{debug, error, info, warn};
+
+/// Events kept for a client that reads slower than cycles happen.
+const BUFFER: usize = 16;
+
+/// A Unix socket that local processes connect to in order to receive one
+/// JSON line per update cycle.
+pub struct EventSocket {
+    sender: broadcast::Sender<String>,
+}
+
+impl EventSocket {
+    /// Binds `path`, replacing a socket left behind by an earlier run, and
+    /// starts accepting clients in the background.
+    pub fn listen(path: &str) -> io::Result<Self> {
+        if Path::new(path).exists() {
+            fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        info!("Publishing update events on {}", path);
+
+        let (sender, _) = broadcast::channel(BUFFER);
+        tokio::spawn(accept(listener, sender.clone()));
+
+        Ok(EventSocket { sender })
+    }
+
+    /// Sends `event` to every connected client. Without clients the event is
+    /// just dropped.
+    pub fn publish(&self, event: &impl Serialize) {
+        match serde_json::to_string(event) {
+            Ok(line) => {
+                // Only fails when nobody is connected.
+                let _ = self.sender.send(line);
+            }
+            Err(e) => error!("Failed to serialize event: {:?}", e),
+        }
+    }
+}
+
+async fn accept(listener: UnixListener, sender: broadcast::Sender<String>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                debug!("Event client connected");
+                tokio::spawn(forward(stream, sender.subscribe()));
+            }
+            Err(e) => {
+                error!("Event socket stopped accepting clients: {:?}", e);
+                return;
+            }
+        }
+    }
+}
+
+/// Writes events to one client until it disconnects.
+async fn forward(mut stream: UnixStream, mut events: broadcast::Receiver<String>) {
+    loop {
+        let line = match events.recv().await {
+            Ok(line) => line,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                warn!("Event client is too slow, skipped {} events", skipped);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+
+        let written = async {
+            stream.write_all(line.as_bytes()).await?;
+            stream.write_all(b"\n").await
+        };
+        if written.await.is_err() {
+            debug!("Event client disconnected");
+            return;
+        }
+    }
+}
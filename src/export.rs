@@ -0,0 +1,160 @@
+use std::error::Error;
+
+use reqwest::Client;
+use serde::Serialize;
+use tracing::{error, info};
+
+use crate::{
+    api_client, check_success, get_zone_id, print_json, state, Account, Config, DnsRecordInfo,
+    DnsRecordResponse, OutputFormat,
+};
+
+/// Records requested per page.
+const PAGE_SIZE: u32 = 5000;
+
+#[derive(Serialize)]
+struct ExportSummary<'a> {
+    domain: &'a str,
+    out: &'a str,
+    records: usize,
+}
+
+/// Writes every record of the zone `domain` to `out` as pretty-printed JSON,
+/// using the first configured account that can see the zone. Nothing in
+/// Cloudflare is changed. Returns 0 on success and 3 on failure.
+pub async fn run(config: &Config, domain: &str, out: &str, output: OutputFormat) -> i32 {
+    let client = api_client(config);
+
+    let records = match export_zone(&client, config, domain).await {
+        Ok(records) => records,
+        Err(e) => {
+            error!("Failed to export {}, nothing was written: {}", domain, e);
+            return 3;
+        }
+    };
+
+    let result = serde_json::to_string_pretty(&records)
+        .map_err(Into::into)
+        .and_then(|data| state::write_atomic(out, &data));
+    if let Err(e) = result {
+        error!("Failed to write {}: {}", out, e);
+        return 3;
+    }
+
+    info!(
+        "Exported {} records of {} to {}",
+        records.len(),
+        domain,
+        out
+    );
+    if let OutputFormat::Json = output {
+        print_json(&ExportSummary {
+            domain,
+            out,
+            records: records.len(),
+        });
+    }
+
+    0
+}
+
+async fn export_zone(
+    client: &Client,
+    config: &Config,
+    domain: &str,
+) -> Result<Vec<DnsRecordInfo>, Box<dyn Error>> {
+    let mut last_error: Box<dyn Error> = "no account with an API token is configured".into();
+
+    for account in config.accounts() {
+        let zone_id = match get_zone_id(client, &account, domain).await {
+            Ok(zone_id) => zone_id,
+            Err(e) => {
+                last_error = e;
+                continue;
+            }
+        };
+
+        return list_zone_records(client, &account, &zone_id).await;
+    }
+
+    Err(last_error)
+}
+
+/// Fetches all records of a zone, following pagination.
+async fn list_zone_records(
+    client: &Client,
+    account: &Account,
+    zone_id: &str,
+) -> Result<Vec<DnsRecordInfo>, Box<dyn Error>> {
+    let url = account.api(&format!("/zones/{}/dns_records", zone_id));
+    let mut records = Vec::new();
+    let mut page = 1;
+
+    loop {
+        let response: DnsRecordResponse = client
+            .get(&url)
+            .query(&[("page", page), ("per_page", PAGE_SIZE)])
+            .header("Authorization", format!("Bearer {}", account.api_token))
+            .header("Content-Type", "application/json")
+            .send()
+            .await?
+            .json()
+            .await?;
+        check_success(response.success, &response.errors)?;
+
+        records.extend(response.result);
+
+        let total_pages = response.result_info.map_or(1, |info| info.total_pages);
+        if page >= total_pages {
+            return Ok(records);
+        }
+        page += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+
+    use super::*;
+    use crate::mock_api::MockApi;
+
+    #[tokio::test]
+    async fn no_backup_is_written_when_records_cannot_be_listed() {
+        let api = MockApi::start(|_, path| match path {
+            "/zones" => (
+                StatusCode::OK,
+                r#"{"success": true, "result": [{"id": "zone", "name": "example.com"}]}"#
+                    .to_string(),
+            ),
+            _ => (
+                StatusCode::FORBIDDEN,
+                r#"{"success": false, "errors": [{"code": 10000, "message": "Authentication error"}], "result": null}"#
+                    .to_string(),
+            ),
+        })
+        .await;
+        let config: Config = toml::from_str(&format!(
+            r#"
+            api_token = "token"
+            api_url = "{}"
+            "#,
+            api.url
+        ))
+        .unwrap();
+        let out = std::env::temp_dir().join(format!("ddns-export-{}.json", std::process::id()));
+        let out = out.to_str().unwrap();
+        let _ = std::fs::remove_file(out);
+
+        assert_eq!(
+            run(&config, "example.com", out, OutputFormat::Text).await,
+            3
+        );
+        assert!(!std::path::Path::new(out).exists());
+
+        let listing = &api.received()[1];
+        assert_eq!(listing.path, "/zones/zone/dns_records");
+        assert_eq!(listing.query.as_deref(), Some("page=1&per_page=5000"));
+        assert_eq!(listing.headers["authorization"], "Bearer token");
+    }
+}
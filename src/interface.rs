@@ -0,0 +1,270 @@
+use std::{
+    error::Error,
+    fs,
+    net::{IpAddr, Ipv6Addr},
+};
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::ip;
+
+/// Where Linux lists the IPv6 addresses of every interface.
+const IF_INET6: &str = "/proc/net/if_inet6";
+
+// Address flags from linux/if_addr.h.
+const IFA_F_TEMPORARY: u32 = 0x01;
+const IFA_F_DADFAILED: u32 = 0x08;
+const IFA_F_DEPRECATED: u32 = 0x20;
+const IFA_F_TENTATIVE: u32 = 0x40;
+
+/// Which global address of `ipv6_interface` to publish when it has several,
+/// as it does with privacy extensions.
+#[derive(Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Ipv6Selection {
+    /// The first address that isn't deprecated, falling back to a
+    /// deprecated one if that's all there is.
+    #[default]
+    Preferred,
+    /// The first address that isn't a temporary privacy address, i.e. the
+    /// EUI-64 or stable-privacy one.
+    Stable,
+    /// The first address, in the kernel's order.
+    First,
+}
+
+/// One global address of an interface.
+struct InterfaceAddress {
+    address: Ipv6Addr,
+    flags: u32,
+}
+
+impl InterfaceAddress {
+    fn temporary(&self) -> bool {
+        self.flags & IFA_F_TEMPORARY != 0
+    }
+
+    fn deprecated(&self) -> bool {
+        self.flags & IFA_F_DEPRECATED != 0
+    }
+}
+
+/// Picks the address to publish from `interfaces`, listed by preference.
+/// Addresses inside `scope` win over all others; among the rest the first
+/// interface with a usable address is used, and `selection` picks one of
+/// its addresses. Returns the address and the interface it came from.
+pub fn ipv6_address(
+    interfaces: &[String],
+    scope: Option<(Ipv6Addr, u8)>,
+    selection: Ipv6Selection,
+) -> Result<(Ipv6Addr, &str), Box<dyn Error>> {
+    let contents = fs::read_to_string(IF_INET6).map_err(|e| {
+        format!(
+            "Failed to read {} (ipv6_interface needs Linux): {}",
+            IF_INET6, e
+        )
+    })?;
+    choose(&contents, interfaces, scope, selection)
+}
+
+/// [`ipv6_address`] over the `/proc/net/if_inet6` `contents`.
+fn choose<'a>(
+    contents: &str,
+    interfaces: &'a [String],
+    scope: Option<(Ipv6Addr, u8)>,
+    selection: Ipv6Selection,
+) -> Result<(Ipv6Addr, &'a str), Box<dyn Error>> {
+    let mut candidates: Vec<_> = interfaces
+        .iter()
+        .map(|name| (name.as_str(), global_addresses(contents, name)))
+        .collect();
+    if let Some(scope) = scope {
+        let in_scope = |candidate: &InterfaceAddress| ip::ipv6_in_prefix(candidate.address, scope);
+        if candidates
+            .iter()
+            .any(|(_, addresses)| addresses.iter().any(in_scope))
+        {
+            for (_, addresses) in &mut candidates {
+                addresses.retain(in_scope);
+            }
+        }
+    }
+
+    let Some((name, addresses)) = candidates
+        .into_iter()
+        .find(|(_, addresses)| !addresses.is_empty())
+    else {
+        return Err(format!("No usable global IPv6 address on {}", interfaces.join(", ")).into());
+    };
+
+    select(&addresses, selection)
+        .map(|candidate| (candidate.address, name))
+        .ok_or_else(|| format!("{} only has temporary IPv6 addresses, none is stable", name).into())
+}
+
+/// Deserializes `ipv6_interface` as one interface name or a list of them.
+pub fn deserialize_names<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Names {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match Names::deserialize(deserializer)? {
+        Names::One(name) => vec![name],
+        Names::Many(names) => names,
+    })
+}
+
+/// Parses `/proc/net/if_inet6` lines of the form
+/// `address ifindex prefix_len scope flags name`, keeping the public,
+/// usable addresses of `interface` in the kernel's order.
+fn global_addresses(contents: &str, interface: &str) -> Vec<InterfaceAddress> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [hex, _, _, _, flags, name] = fields[..] else {
+                return None;
+            };
+            if name != interface || hex.len() != 32 {
+                return None;
+            }
+
+            let address = Ipv6Addr::from(u128::from_str_radix(hex, 16).ok()?);
+            let flags = u32::from_str_radix(flags, 16).ok()?;
+            let usable =
+                flags & (IFA_F_TENTATIVE | IFA_F_DADFAILED) == 0 && is_global(address.into());
+            usable.then_some(InterfaceAddress { address, flags })
+        })
+        .collect()
+}
+
+/// Whether `address` can be published: link-local, unique local,
+/// loopback and other non-public addresses can't.
+fn is_global(address: IpAddr) -> bool {
+    ip::non_public_reason(address).is_none()
+}
+
+/// The address among `addresses` that `selection` asks for.
+fn select(addresses: &[InterfaceAddress], selection: Ipv6Selection) -> Option<&InterfaceAddress> {
+    match selection {
+        Ipv6Selection::Preferred => addresses
+            .iter()
+            .find(|candidate| !candidate.deprecated())
+            .or(addresses.first()),
+        Ipv6Selection::Stable => addresses.iter().find(|candidate| !candidate.temporary()),
+        Ipv6Selection::First => addresses.first(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NATIVE: &str = "2620:fe::9";
+    const TEMPORARY: &str = "2620:fe::1234";
+    const TUNNEL: &str = "2001:470:1f0b::9";
+
+    /// A `/proc/net/if_inet6` line for `address` on `interface`.
+    fn line(address: &str, flags: u32, interface: &str) -> String {
+        let address: Ipv6Addr = address.parse().unwrap();
+        format!(
+            "{:032x} 02 40 00 {:02x} {}",
+            u128::from(address),
+            flags,
+            interface
+        )
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn only_global_addresses_are_published() {
+        let addresses: Vec<IpAddr> = ["fe80::1", "fd12:3456::1", "::1", NATIVE]
+            .iter()
+            .map(|address| address.parse().unwrap())
+            .collect();
+
+        let global: Vec<IpAddr> = addresses.into_iter().filter(|a| is_global(*a)).collect();
+        assert_eq!(global, [NATIVE.parse::<IpAddr>().unwrap()]);
+    }
+
+    #[test]
+    fn non_global_and_unready_addresses_on_the_interface_are_skipped() {
+        let contents = [
+            line("fe80::1", 0x80, "eth0"),
+            line("fd12:3456::1", 0, "eth0"),
+            line("::1", 0x80, "lo"),
+            line("2620:fe::5", IFA_F_TENTATIVE, "eth0"),
+            line(NATIVE, 0, "eth0"),
+        ]
+        .join("\n");
+
+        let interfaces = names(&["lo", "eth0"]);
+        let (address, name) = choose(&contents, &interfaces, None, Ipv6Selection::First).unwrap();
+        assert_eq!(address.to_string(), NATIVE);
+        assert_eq!(name, "eth0");
+
+        let interfaces = names(&["lo"]);
+        assert!(choose(&contents, &interfaces, None, Ipv6Selection::Preferred).is_err());
+    }
+
+    #[test]
+    fn selection_picks_among_several_global_addresses() {
+        let contents = [
+            line(TEMPORARY, IFA_F_TEMPORARY | IFA_F_DEPRECATED, "eth0"),
+            line(NATIVE, 0, "eth0"),
+        ]
+        .join("\n");
+        let interfaces = names(&["eth0"]);
+        let pick = |selection| {
+            let (address, _) = choose(&contents, &interfaces, None, selection).unwrap();
+            address.to_string()
+        };
+
+        assert_eq!(pick(Ipv6Selection::First), TEMPORARY);
+        assert_eq!(pick(Ipv6Selection::Preferred), NATIVE);
+        assert_eq!(pick(Ipv6Selection::Stable), NATIVE);
+
+        let contents = line(TEMPORARY, IFA_F_TEMPORARY, "eth0");
+        assert!(choose(&contents, &interfaces, None, Ipv6Selection::Stable).is_err());
+    }
+
+    #[test]
+    fn scope_picks_the_tunnel_over_the_native_address() {
+        let contents = [line(NATIVE, 0, "eth0"), line(TUNNEL, 0, "he-ipv6")].join("\n");
+        let interfaces = names(&["eth0", "he-ipv6"]);
+
+        let (address, name) =
+            choose(&contents, &interfaces, None, Ipv6Selection::Preferred).unwrap();
+        assert_eq!((address.to_string().as_str(), name), (NATIVE, "eth0"));
+
+        let scope = ip::parse_ipv6_prefix("2001:470::/32").unwrap();
+        let (address, name) = choose(
+            &contents,
+            &interfaces,
+            Some(scope),
+            Ipv6Selection::Preferred,
+        )
+        .unwrap();
+        assert_eq!((address.to_string().as_str(), name), (TUNNEL, "he-ipv6"));
+
+        // A scope nothing is inside of falls back to the interface order.
+        let scope = ip::parse_ipv6_prefix("2a00::/16").unwrap();
+        let (address, _) = choose(
+            &contents,
+            &interfaces,
+            Some(scope),
+            Ipv6Selection::Preferred,
+        )
+        .unwrap();
+        assert_eq!(address.to_string(), NATIVE);
+    }
+}
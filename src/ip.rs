@@ -0,0 +1,163 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Canonical form of an address, so `::ffff:1.2.3.4` and `1.2.3.4`, or
+/// differently compressed IPv6 spellings, compare equal. Anything that isn't
+/// an IP is returned unchanged.
+pub fn normalize(address: &str) -> String {
+    match address.trim().parse::<IpAddr>() {
+        Ok(ip) => ip.to_canonical().to_string(),
+        Err(_) => address.to_string(),
+    }
+}
+
+/// Whether two record contents hold the same address once normalized.
+pub fn same_address(a: &str, b: &str) -> bool {
+    normalize(a) == normalize(b)
+}
+
+/// Why `ip` can't be a host's public address, or `None` if it can. Private,
+/// loopback, link-local, unspecified, multicast and documentation ranges are
+/// rejected for both families, plus ULAs (`fc00::/7`) for IPv6.
+pub fn non_public_reason(ip: IpAddr) -> Option<&'static str> {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => non_public_v4(ip),
+        IpAddr::V6(ip) => non_public_v6(ip),
+    }
+}
+
+fn non_public_v4(ip: Ipv4Addr) -> Option<&'static str> {
+    let [a, b, ..] = ip.octets();
+
+    if ip.is_unspecified() {
+        Some("unspecified")
+    } else if ip.is_loopback() {
+        Some("loopback")
+    } else if ip.is_private() {
+        Some("private")
+    } else if a == 100 && (64..128).contains(&b) {
+        Some("carrier-grade NAT")
+    } else if ip.is_link_local() {
+        Some("link-local")
+    } else if ip.is_documentation() {
+        Some("documentation")
+    } else if ip.is_multicast() || ip.is_broadcast() || a >= 240 {
+        Some("multicast or reserved")
+    } else {
+        None
+    }
+}
+
+fn non_public_v6(ip: Ipv6Addr) -> Option<&'static str> {
+    let first = ip.segments()[0];
+
+    if ip.is_unspecified() {
+        Some("unspecified")
+    } else if ip.is_loopback() {
+        Some("loopback")
+    } else if first & 0xfe00 == 0xfc00 {
+        Some("unique local")
+    } else if first & 0xffc0 == 0xfe80 {
+        Some("link-local")
+    } else if ip.is_multicast() {
+        Some("multicast")
+    } else if first == 0x2001 && ip.segments()[1] == 0x0db8 {
+        Some("documentation")
+    } else {
+        None
+    }
+}
+
+/// Combines the network part of `detected` with the interface identifier in
+/// `suffix`, splitting the address after `prefix_len` bits.
+pub fn combine_ipv6_prefix(
+    detected: Ipv6Addr,
+    suffix: Ipv6Addr,
+    prefix_len: u8,
+) -> Result<Ipv6Addr, String> {
+    let mask = prefix_mask(prefix_len)?;
+    let suffix = u128::from(suffix);
+
+    if suffix & mask != 0 {
+        return Err(format!(
+            "IPv6 suffix {} has bits set inside the /{} prefix",
+            Ipv6Addr::from(suffix),
+            prefix_len
+        ));
+    }
+
+    Ok(Ipv6Addr::from((u128::from(detected) & mask) | suffix))
+}
+
+/// Parses an IPv6 prefix such as `2001:470::/32`.
+pub fn parse_ipv6_prefix(prefix: &str) -> Result<(Ipv6Addr, u8), String> {
+    let invalid = || format!("Invalid IPv6 prefix: {:?}", prefix);
+    let (network, len) = prefix.split_once('/').ok_or_else(invalid)?;
+    let network: Ipv6Addr = network.parse().map_err(|_| invalid())?;
+    let len: u8 = len.parse().map_err(|_| invalid())?;
+    prefix_mask(len)?;
+    Ok((network, len))
+}
+
+/// Whether `address` lies inside the `(network, prefix_len)` prefix.
+pub fn ipv6_in_prefix(address: Ipv6Addr, (network, prefix_len): (Ipv6Addr, u8)) -> bool {
+    let mask = prefix_mask(prefix_len).unwrap_or(u128::MAX);
+    u128::from(address) & mask == u128::from(network) & mask
+}
+
+fn prefix_mask(prefix_len: u8) -> Result<u128, String> {
+    match prefix_len {
+        0 => Ok(0),
+        1..=128 => Ok(u128::MAX << (128 - u32::from(prefix_len))),
+        _ => Err(format!("Invalid IPv6 prefix length: {}", prefix_len)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ipv4_mapped_addresses_normalize_to_ipv4() {
+        assert_eq!(normalize("::ffff:9.9.9.9"), "9.9.9.9");
+        assert!(same_address("::ffff:9.9.9.9", "9.9.9.9"));
+    }
+
+    #[test]
+    fn ipv6_spellings_normalize_to_the_compressed_form() {
+        assert_eq!(
+            normalize("2620:00fe:0000:0000:0000:0000:0000:0009"),
+            "2620:fe::9"
+        );
+        assert_eq!(normalize(" 2620:FE:0:0::9 "), "2620:fe::9");
+        assert!(same_address("2620:fe::9", "2620:fe:0:0:0:0:0:9"));
+        assert!(!same_address("2620:fe::9", "2620:fe::fe"));
+    }
+
+    #[test]
+    fn non_addresses_are_left_alone() {
+        assert_eq!(normalize("home.example.com"), "home.example.com");
+    }
+
+    fn reason(ip: &str) -> Option<&'static str> {
+        non_public_reason(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn classifies_ipv6_ranges() {
+        assert_eq!(reason("::"), Some("unspecified"));
+        assert_eq!(reason("::1"), Some("loopback"));
+        assert_eq!(reason("fd12:3456::1"), Some("unique local"));
+        assert_eq!(reason("fc00::1"), Some("unique local"));
+        assert_eq!(reason("fe80::1"), Some("link-local"));
+        assert_eq!(reason("ff02::1"), Some("multicast"));
+        assert_eq!(reason("2001:db8::1"), Some("documentation"));
+        assert_eq!(reason("2620:fe::9"), None);
+        assert_eq!(reason("2a00:1450:4001::200e"), None);
+    }
+
+    #[test]
+    fn ipv4_mapped_addresses_are_classified_as_ipv4() {
+        assert_eq!(reason("::ffff:192.168.1.1"), Some("private"));
+        assert_eq!(reason("::ffff:9.9.9.9"), None);
+    }
+}
@@ -1,279 +1,6139 @@
-use std::{collections::HashMap, error::Error, fs};
+mod bench;
+mod breaker;
+mod cleanup;
+mod clock;
+mod coalesce;
+mod command;
+mod diagnose;
+mod duration;
+mod events;
+mod export;
+mod interface;
+mod ip;
+mod mask;
+mod metrics;
+#[cfg(test)]
+mod mock_api;
+mod notify;
+mod outage;
+mod pattern;
+mod provider;
+mod reconcile;
+mod records;
+mod rotate;
+mod schedule;
+mod server;
+mod shutdown;
+mod state;
+mod stun;
+mod timezone;
+mod tls;
+mod webhook;
 
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    error::Error,
+    fmt, fs,
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use breaker::{BreakerState, CircuitBreaker};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use clock::{Clock, Rng, SystemClock, SystemRng};
+use coalesce::ErrorCoalescer;
+use events::EventSocket;
+use interface::Ipv6Selection;
+use mask::MaskingMakeWriter;
+use notify::{NotificationConfig, Notifier, StartupEvent, UpdateEvent};
+use outage::OutageState;
+use provider::{IpProvider, ProviderSource, ResponseFormat};
+use regex::Regex;
 use reqwest::Client;
+use rotate::RotatingWriter;
+use schedule::ActiveHours;
 use serde::{Deserialize, Serialize};
-use tracing::{info, error};
-use tracing_subscriber;
+use server::{HealthConfig, MetricsConfig};
+use state::{
+    JsonFileStore, LastIps, RecordError, RecordErrors, RecordIds, StateBackend, StateFormat,
+    StateStore,
+};
+use timezone::LogTimer;
+use tls::ServerTlsConfig;
+use tokio::{
+    signal::unix::{signal, Signal, SignalKind},
+    sync::Notify,
+};
+use tracing::{debug, error, info, warn};
+use tracing_subscriber::{
+    filter::{EnvFilter, LevelFilter},
+    layer::SubscriberExt,
+    reload,
+    util::SubscriberInitExt,
+    Layer, Registry,
+};
 
 const LAST_IP_FILE: &str = "last_ips.json";
+const PAUSED_FILE: &str = "paused_records.json";
+/// Records whose circuit breaker is open or half-open, for `state show`.
+const BREAKERS_FILE: &str = "circuit_breakers.json";
+/// Longest content Cloudflare accepts for a TXT record.
+const MAX_TXT_CONTENT_LEN: usize = 2048;
+/// Longest error message kept as a record's last error.
+const MAX_RECORD_ERROR_LEN: usize = 500;
+const STATE_DB_FILE: &str = "state.db";
+const REDACTED: &str = "<redacted>";
+/// Longest wait between attempts to read config.toml with `--wait-for-config`.
+const MAX_CONFIG_RETRY_DELAY: Duration = Duration::from_secs(60);
+/// Shortest interval accepted from `--interval`, to avoid hammering the IP
+/// providers and the Cloudflare API while testing.
+const MIN_INTERVAL_OVERRIDE_SECS: u64 = 5;
 
-#[derive(Deserialize)]
+/// Times a `safe_update` reads the record again after a conflict.
+const SAFE_UPDATE_RETRIES: u32 = 3;
+
+/// Version with the commit and build time, for `--version`.
+const VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    " (",
+    env!("GIT_COMMIT"),
+    ", built ",
+    env!("BUILD_TIMESTAMP"),
+    ")"
+);
+
+#[derive(Parser)]
+#[command(
+    about = "Updates Cloudflare DNS records when the public IP changes",
+    version = VERSION
+)]
+struct Cli {
+    /// Run a single update cycle and exit with a status code
+    #[arg(long)]
+    once: bool,
+
+    /// Show what the first cycle would change before starting
+    #[arg(long)]
+    preview: bool,
+
+    /// Proceed with the changes shown by `--preview` without stopping
+    #[arg(long)]
+    yes: bool,
+
+    /// Keep retrying while config.toml can't be read instead of exiting
+    #[arg(long)]
+    wait_for_config: bool,
+
+    /// Check interval for this session, overriding `check_interval` (e.g. 10 or "30s")
+    #[arg(long, value_parser = duration::parse)]
+    interval: Option<u64>,
+
+    /// Output format for `--once` and `reconcile` results
+    #[arg(long, value_enum, global = true, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the effective configuration (secrets redacted) and exit
+    PrintConfig {
+        #[arg(long, value_enum, default_value_t = ConfigFormat::Toml)]
+        format: ConfigFormat,
+    },
+    /// Compare every configured record against the detected IP and report drift
+    Reconcile {
+        /// Update drifted records instead of only reporting them
+        #[arg(long)]
+        apply: bool,
+    },
+    /// Run one update cycle for a single record and exit with a status code
+    Update {
+        /// The dns_name of the record to update
+        #[arg(long)]
+        record: String,
+    },
+    /// Stop updating a record until it is resumed
+    Pause { record: String },
+    /// Resume updating a previously paused record
+    Resume { record: String },
+    /// Delete every configured record from Cloudflare
+    Cleanup {
+        /// Actually delete the records instead of only listing them
+        #[arg(long)]
+        yes: bool,
+        /// Delete even without owned_comment_marker, which removes every
+        /// matching record, including ones created by hand
+        #[arg(long, requires = "yes")]
+        force: bool,
+    },
+    /// Detect the public IP and print it without contacting Cloudflare
+    CheckIp {
+        /// Only check IPv4
+        #[arg(long, conflicts_with = "ipv6")]
+        ipv4: bool,
+        /// Only check IPv6
+        #[arg(long)]
+        ipv6: bool,
+    },
+    /// Check tokens, zones, IP providers and record lookups without changing anything
+    Diagnose,
+    /// Measure the latency of read-only Cloudflare API requests
+    Bench {
+        /// Requests to send to each endpoint
+        #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..=100))]
+        count: u32,
+    },
+    /// Render every notification with sample data and print it instead of sending it
+    PreviewNotify,
+    /// Print a shell completion script to stdout
+    Completions { shell: Shell },
+    /// Print the version, git commit and build time
+    Version,
+    /// Write every record of a zone to a JSON file as a backup
+    Export {
+        /// Zone to export, e.g. example.com
+        #[arg(long)]
+        domain: String,
+        /// File to write the records to
+        #[arg(long)]
+        out: String,
+    },
+    /// Recover from a damaged state file
+    State {
+        #[command(subcommand)]
+        action: StateAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum StateAction {
+    /// Back up the state file and replace it with an empty one
+    Reset {
+        /// Confirm that all cached IPs should be forgotten
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Rewrite the state file, dropping entries that can't be parsed
+    Repair,
+    /// Show the last pushed IP and the last error of every record
+    Show,
+}
+
+/// What to do when records can't be resolved at startup.
+#[derive(Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum StartupPolicy {
+    /// Exit with status 3.
+    FailFast,
+    /// Keep running and retry them every cycle.
+    #[default]
+    Resilient,
+}
+
+/// How a record is found to need an update.
+#[derive(Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum CheckStrategy {
+    /// Push whenever the detected IP differs from the cached one.
+    #[default]
+    Cache,
+    /// Like `cache`, but read the live record before pushing and skip the
+    /// update if it already holds the new IP.
+    CacheThenConfirm,
+}
+
+/// HTTP version used for the Cloudflare API.
+#[derive(Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum HttpVersion {
+    /// Negotiated with the server.
+    #[default]
+    Auto,
+    Http1,
+    Http2,
+}
+
+/// Where the public IP comes from.
+#[derive(Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum IpSource {
+    /// `ipv4_providers` / `ipv6_providers`.
+    #[default]
+    Http,
+    /// `ip_file`, written by another process such as a router hook.
+    File,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ConfigFormat {
+    Toml,
+    Json,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
 struct Config {
-    api_token: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    api_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    account_id: Option<String>,
+    /// Base URL of the Cloudflare API, e.g. to go through a proxy.
+    #[serde(default = "default_api_url")]
+    api_url: String,
+    #[serde(
+        default = "default_check_interval",
+        deserialize_with = "duration::deserialize"
+    )]
     check_interval: u64,
+    /// Cron expression to run cycles at fixed times instead of every
+    /// `check_interval`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    schedule: Option<String>,
+    /// Random delay of up to this long added to each scheduled run.
+    #[serde(default, deserialize_with = "duration::deserialize")]
+    schedule_jitter_secs: u64,
+    #[serde(default, deserialize_with = "duration::deserialize")]
+    startup_delay_secs: u64,
+    /// How long a SIGTERM drain may take before the process exits anyway.
+    #[serde(
+        default = "default_shutdown_timeout_secs",
+        deserialize_with = "duration::deserialize"
+    )]
+    shutdown_timeout_secs: u64,
+    /// Push records without a cached IP even if Cloudflare may already
+    /// match. When disabled, their live content is checked first.
+    #[serde(default = "default_force_on_first_run")]
+    force_on_first_run: bool,
+    #[serde(default)]
+    check_strategy: CheckStrategy,
+    /// Read each record right before updating it and make the update
+    /// conditional on it not having changed since.
+    #[serde(default)]
+    safe_update: bool,
+    /// Send updates as the record read from Cloudflare with only the fields
+    /// we manage replaced, keeping ones this tool doesn't model.
+    #[serde(default)]
+    preserve_unknown_fields: bool,
+    #[serde(default)]
+    startup_policy: StartupPolicy,
+    /// Give up resolving records at startup after this long (0 waits as
+    /// long as it takes). Records left unresolved are handled by
+    /// `startup_policy`.
+    #[serde(default, deserialize_with = "duration::deserialize")]
+    startup_timeout_secs: u64,
+    /// Exit cleanly after running for this long (0 runs forever).
+    #[serde(default, deserialize_with = "duration::deserialize")]
+    max_runtime_secs: u64,
+    /// Same as passing `--preview`.
+    #[serde(default)]
+    startup_preview: bool,
+    /// Same as passing `--yes`.
+    #[serde(default)]
+    assume_yes: bool,
+    #[serde(default)]
+    state_backend: StateBackend,
+    #[serde(default)]
+    state_format: StateFormat,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    state_db_path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    redis_url: Option<String>,
+    #[serde(default)]
+    ip_source: IpSource,
+    /// File holding the public IP(s), one per line, with `ip_source = "file"`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ip_file: Option<String>,
+    /// Treat `ip_file` as stale once it hasn't been written for this long
+    /// (0 never does).
+    #[serde(default, deserialize_with = "duration::deserialize")]
+    ip_file_max_age_secs: u64,
+    /// Read the IPv6 address from this local interface, or the first of
+    /// these with a usable address, instead of asking `ipv6_providers`.
+    #[serde(
+        default,
+        deserialize_with = "interface::deserialize_names",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    ipv6_interface: Vec<String>,
+    /// Prefer addresses of `ipv6_interface` inside this prefix, e.g. a
+    /// tunnel's `2001:470::/32`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ipv6_scope: Option<String>,
+    /// Which address of `ipv6_interface` to use when it has several.
+    #[serde(default)]
+    ipv6_address_selection: Ipv6Selection,
+    /// Services asked for the public IPv4 address, in order until one
+    /// answers. Defaults to ipify.
+    #[serde(
+        default,
+        deserialize_with = "provider::deserialize_list",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    ipv4_providers: Vec<IpProvider>,
+    /// Same as `ipv4_providers`, for IPv6.
+    #[serde(
+        default,
+        deserialize_with = "provider::deserialize_list",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    ipv6_providers: Vec<IpProvider>,
+    /// Allow records to run a `content_command`, and the update hooks.
+    #[serde(default)]
+    allow_command_hooks: bool,
+    /// Shell command run before a record is pointed at a new IP.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pre_update_command: Option<String>,
+    /// Shell command run after a record was pointed at a new IP, whether
+    /// the update worked or not.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    post_update_command: Option<String>,
+    /// How long a command hook may run before it is killed.
+    #[serde(
+        default = "default_command_timeout_secs",
+        deserialize_with = "duration::deserialize"
+    )]
+    command_timeout_secs: u64,
+    /// Keep the last known IP when detection fails instead of reporting
+    /// the records as failed.
+    #[serde(default)]
+    treat_ip_fetch_error_as_nochange: bool,
+    /// Accept invalid TLS certificates from IP providers, e.g. a self-hosted
+    /// echo service with a self-signed certificate. Never applies to the
+    /// Cloudflare API.
+    #[serde(default)]
+    ip_provider_insecure_tls: bool,
+    /// Largest response body accepted from IP providers and `records_url`.
+    #[serde(default = "default_max_response_bytes")]
+    max_response_bytes: usize,
+    /// Success log line, with `{record}`, `{old_ip}`, `{new_ip}`, `{type}`
+    /// and `{zone}` placeholders.
+    #[serde(default = "default_log_update_template")]
+    log_update_template: String,
+    /// Show public IPs in the log as `203.0.x.x` or their `/32`. Cloudflare
+    /// and the state still get the full address.
+    #[serde(default)]
+    mask_ip_in_logs: bool,
+    /// IANA zone for timestamps in logs, notifications and `state show`.
+    /// Defaults to UTC.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timezone: Option<String>,
+    /// Also write the log to this file, without colors.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    log_file: Option<String>,
+    /// Size at which `log_file` is rotated (0 never rotates it).
+    #[serde(default = "default_log_max_bytes")]
+    log_max_bytes: u64,
+    /// Rotated log files kept next to `log_file`.
+    #[serde(default = "default_max_rotated_files")]
+    max_rotated_files: u32,
+    /// Gzip rotated log files.
+    #[serde(default)]
+    compress_rotated_files: bool,
+    /// Only touch records whose comment contains this marker.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    owned_comment_marker: Option<String>,
+    /// Force HTTP/1.1 or HTTP/2 for the Cloudflare API, e.g. to work around
+    /// a middlebox that breaks one of them.
+    #[serde(default)]
+    http_version: HttpVersion,
+    /// Fixed addresses for hostnames used by the Cloudflare API client,
+    /// bypassing the system resolver (e.g. `"api.cloudflare.com"`).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    resolve_overrides: BTreeMap<String, String>,
+    #[serde(default = "default_update_retries")]
+    update_retries: u32,
+    #[serde(
+        default = "default_update_retry_delay_secs",
+        deserialize_with = "duration::deserialize"
+    )]
+    update_retry_delay_secs: u64,
+    /// Spread updates evenly so no more than this many are sent per minute
+    /// (0 sends them as fast as possible).
+    #[serde(default)]
+    max_updates_per_minute: u32,
+    /// Re-read each record after updating it to catch updates that
+    /// Cloudflare accepts but never applies.
+    #[serde(default)]
+    verify_updates: bool,
+    /// Consecutive ineffective updates of a record before it is reported.
+    #[serde(default = "default_ineffective_update_threshold")]
+    ineffective_update_threshold: u32,
+    /// Exit instead of retrying once the threshold is reached.
+    #[serde(default)]
+    abort_on_ineffective_updates: bool,
+    /// Consecutive failures after which a record is skipped for a while
+    /// (0 disables the circuit breaker).
+    #[serde(default)]
+    circuit_breaker_threshold: u32,
+    /// How long a record is skipped the first time; doubles on each
+    /// further failure.
+    #[serde(
+        default = "default_circuit_breaker_cooldown_secs",
+        deserialize_with = "duration::deserialize"
+    )]
+    circuit_breaker_cooldown_secs: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    webhook_listen_addr: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    webhook_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    webhook_tls: Option<ServerTlsConfig>,
+    #[serde(default)]
+    metrics: MetricsConfig,
+    #[serde(default)]
+    health: HealthConfig,
+    /// Unix socket to publish every cycle's report on as a JSON line.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    event_socket: Option<String>,
+    /// Default update window for records without their own.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    active_hours: Option<ActiveHours>,
+    #[serde(default)]
+    notifications: NotificationConfig,
+    #[serde(default)]
     dns_records: Vec<DnsRecord>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    record_groups: Vec<RecordGroup>,
+    /// URL of a JSON array of extra records for the top-level account,
+    /// fetched at startup and on SIGHUP.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    records_url: Option<String>,
+    #[serde(default)]
+    accounts: Vec<Account>,
 }
 
-#[derive(Deserialize)]
-struct ZoneResponse {
-    result: Vec<ZoneInfo>,
+fn default_api_url() -> String {
+    "https://api.cloudflare.com/client/v4".to_string()
 }
 
-#[derive(Deserialize)]
-struct ZoneInfo {
-    id: String,
-    name: String,
+fn default_check_interval() -> u64 {
+    300
 }
 
-#[derive(Deserialize)]
-struct DnsRecordInfo {
-    id: String,
-    name: String,
+fn default_shutdown_timeout_secs() -> u64 {
+    30
 }
 
-#[derive(Deserialize)]
-struct DnsRecordResponse {
-    result: Vec<DnsRecordInfo>,
+fn default_log_update_template() -> String {
+    "✅ Updated DNS record for {record} to {new_ip}".to_string()
 }
 
-#[derive(Deserialize, Serialize, Clone)]
-struct DnsRecord {
-    dns_name: String,
-    proxied: bool,
+fn default_command_timeout_secs() -> u64 {
+    10
 }
 
-#[derive(Deserialize)]
-struct IpResponse {
-    ip: String,
+fn default_log_max_bytes() -> u64 {
+    10 * 1024 * 1024
 }
 
-#[derive(Serialize)]
-struct DnsUpdateRequest {
-    r#type: String,
-    name: String,
-    content: String,
-    ttl: u32,
-    proxied: bool,
+fn default_max_rotated_files() -> u32 {
+    5
 }
 
-#[derive(Deserialize)]
-struct CloudflareResponse {
-    success: bool,
-    errors: Vec<serde_json::Value>,
+fn default_max_response_bytes() -> usize {
+    1024 * 1024
 }
 
-async fn get_public_ip() -> Result<String, reqwest::Error> {
-    let response: IpResponse = reqwest::get("https://api64.ipify.org?format=json")
-        .await?
-        .json()
-        .await?;
+fn default_force_on_first_run() -> bool {
+    true
+}
 
-    Ok(response.ip)
+fn default_update_retries() -> u32 {
+    2
 }
 
-async fn get_zone_id(
-    client: &Client,
-    api_token: &str,
-    domain: &str,
-) -> Result<String, Box<dyn Error>> {
-    let url = "https://api.cloudflare.com/client/v4/zones";
-    let response: ZoneResponse = client
-        .get(url)
-        .header("Authorization", format!("Bearer {}", api_token))
-        .header("Content-Type", "application/json")
-        .send()
-        .await?
-        .json()
-        .await?;
+fn default_update_retry_delay_secs() -> u64 {
+    5
+}
 
-    for zone in response.result {
-        if zone.name == domain {
-            return Ok(zone.id);
-        }
+fn default_ineffective_update_threshold() -> u32 {
+    3
+}
+
+fn default_circuit_breaker_cooldown_secs() -> u64 {
+    300
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+struct Account {
+    name: Option<String>,
+    api_token: String,
+    /// Only look up zones that belong to this Cloudflare account.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    account_id: Option<String>,
+    dns_records: Vec<DnsRecord>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    record_groups: Vec<RecordGroup>,
+    /// The config's `api_url`, filled in by [`Config::accounts`].
+    #[serde(skip)]
+    api_url: String,
+}
+
+impl Account {
+    fn label(&self) -> String {
+        self.name.clone().unwrap_or_else(|| "default".to_string())
     }
 
-    Err(format!("Zone ID not found for domain: {}", domain).into())
+    /// URL of the Cloudflare API endpoint `path`, e.g. `/zones`.
+    fn api(&self, path: &str) -> String {
+        format!("{}{}", self.api_url.trim_end_matches('/'), path)
+    }
 }
 
-async fn get_record_id(
-    client: &Client,
-    api_token: &str,
-    zone_id: &str,
-    dns_name: &str,
-) -> Result<String, Box<dyn Error>> {
-    let url = format!(
-        "https://api.cloudflare.com/client/v4/zones/{}/dns_records",
-        zone_id
-    );
-    let response: DnsRecordResponse = client
-        .get(&url)
-        .header("Authorization", format!("Bearer {}", api_token))
-        .header("Content-Type", "application/json")
-        .send()
-        .await?
-        .json()
-        .await?;
+impl Config {
+    /// Providers to ask for the public address of `record_type`'s family.
+    fn ip_providers(&self, record_type: RecordType) -> Vec<IpProvider> {
+        let configured = match record_type {
+            RecordType::A => &self.ipv4_providers,
+            RecordType::Aaaa => &self.ipv6_providers,
+            RecordType::Txt => return Vec::new(),
+        };
 
-    for record in response.result {
-        if record.name == dns_name {
-            return Ok(record.id);
+        if configured.is_empty() {
+            record_type
+                .ip_provider_url()
+                .map(IpProvider::new)
+                .into_iter()
+                .collect()
+        } else {
+            configured.clone()
         }
     }
 
-    Err(format!("DNS record ID not found for domain: {}", dns_name).into())
-}
+    /// Longest expected wait between cycles: `check_interval`, or with a
+    /// `schedule` its longest gap between runs plus the jitter.
+    fn longest_cycle_gap(&self, clock: &dyn Clock) -> Duration {
+        let interval = Duration::from_secs(self.check_interval);
+        let Some(expression) = &self.schedule else {
+            return interval;
+        };
 
-async fn update_dns_record(
-    client: &Client,
-    ip: &str,
-    config: &Config,
-    record: &DnsRecord,
-    zone_id: &str,
-    record_id: &str,
-) -> Result<(), Box<dyn Error>> {
-    let url = format!(
-        "https://api.cloudflare.com/client/v4/zones/{}/dns_records/{}",
-        zone_id, record_id
-    );
+        schedule::parse_cron(expression)
+            .ok()
+            .and_then(|cron| schedule::longest_gap(&cron, clock.local()))
+            .map_or(interval, |gap| {
+                gap + Duration::from_secs(self.schedule_jitter_secs)
+            })
+    }
 
-    let request_data = DnsUpdateRequest {
-        r#type: "A".to_string(),
-        name: record.dns_name.clone(),
-        content: ip.to_string(),
-        ttl: 1,
-        proxied: record.proxied,
-    };
+    /// How long to wait before the next cycle: until the next scheduled run
+    /// when `schedule` is set, otherwise `check_interval`.
+    fn next_delay(&self, clock: &dyn Clock, rng: &dyn Rng) -> Duration {
+        let interval = Duration::from_secs(self.check_interval);
+        let Some(expression) = &self.schedule else {
+            return interval;
+        };
 
-    let response: CloudflareResponse = client
-        .put(&url)
-        .header("Authorization", format!("Bearer {}", config.api_token))
-        .header("Content-Type", "application/json")
-        .json(&request_data)
-        .send()
-        .await?
-        .json()
-        .await?;
+        let next = schedule::parse_cron(expression)
+            .ok()
+            .and_then(|cron| schedule::delay_until_next(&cron, clock.local()));
+        match next {
+            Some(delay) => {
+                let delay = delay + schedule::jitter(self.schedule_jitter_secs, rng);
+                debug!("Next scheduled run in {}s", delay.as_secs());
+                delay
+            }
+            None => {
+                warn!(
+                    "Schedule {:?} has no upcoming runs, falling back to check_interval",
+                    expression
+                );
+                interval
+            }
+        }
+    }
 
-    if response.success {
-        info!("✅ Updated DNS record for {} to {}", record.dns_name, ip);
-        Ok(())
-    } else {
-        error!("Failed to update DNS record: {:?}", response.errors);
-        Err("Cloudflare API error".into())
+    /// Returns every account in the config, treating the top-level
+    /// `api_token`/`dns_records` pair as an unnamed account.
+    fn accounts(&self) -> Vec<Account> {
+        let mut accounts = Vec::new();
+
+        if let Some(api_token) = &self.api_token {
+            accounts.push(Account {
+                name: None,
+                api_token: api_token.clone(),
+                account_id: self.account_id.clone(),
+                dns_records: self.dns_records.clone(),
+                record_groups: self.record_groups.clone(),
+                api_url: String::new(),
+            });
+        }
+
+        accounts.extend(self.accounts.iter().cloned());
+        for account in &mut accounts {
+            account.api_url = self.api_url.clone();
+            account.dns_records = account
+                .dns_records
+                .iter()
+                .cloned()
+                .chain(account.record_groups.iter().flat_map(RecordGroup::records))
+                .flat_map(|record| record.expand())
+                .collect();
+        }
+        accounts
     }
-}
 
-fn read_last_ips() -> serde_json::Value {
-    fs::read_to_string(LAST_IP_FILE)
-        .ok()
-        .and_then(|data| serde_json::from_str(&data).ok())
-        .unwrap_or_else(|| serde_json::json!({}))
-}
+    /// Returns a copy of the config with every secret replaced by a placeholder.
+    fn redacted(&self) -> Config {
+        let mut config = self.clone();
 
-fn save_last_ips(ips: &serde_json::Value) {
-    fs::write(LAST_IP_FILE, serde_json::to_string_pretty(ips).unwrap()).ok();
-}
+        if config.api_token.is_some() {
+            config.api_token = Some(REDACTED.to_string());
+        }
 
-fn load_config() -> Result<Config, Box<dyn Error>> {
-    let config = fs::read_to_string("config.toml")?;
-    let config: Config = toml::from_str(&config)?;
+        for account in &mut config.accounts {
+            account.api_token = REDACTED.to_string();
+        }
 
-    Ok(config)
-}
+        if config.webhook_token.is_some() {
+            config.webhook_token = Some(REDACTED.to_string());
+        }
 
-#[tokio::main]
-async fn main() {
-    tracing_subscriber::fmt()
-    .with_max_level(tracing::Level::INFO)
-    .init();
+        // Redis URLs may embed a password.
+        if config.redis_url.is_some() {
+            config.redis_url = Some(REDACTED.to_string());
+        }
 
-    let config = match load_config() {
-        Ok(cfg) => cfg,
-        Err(e) => {
-            error!("Failed to load config: {:?}", e);
-            return;
+        // Other URLs may carry a token in their path or query.
+        if let Some(url) = &mut config.records_url {
+            *url = notify::redact_url(url);
         }
-    };
 
-    let client = Client::new();
-    let mut last_ips = read_last_ips();
+        if let Some(webhook) = &mut config.notifications.webhook {
+            webhook.url = notify::redact_url(&webhook.url);
+            for value in webhook.headers.values_mut() {
+                *value = REDACTED.to_string();
+            }
+        }
 
-    let mut zone_id_map = HashMap::new();
-    let mut record_id_map = HashMap::new();
+        for provider in config
+            .ipv4_providers
+            .iter_mut()
+            .chain(&mut config.ipv6_providers)
+        {
+            if provider.source.is_http() {
+                provider.url = notify::redact_url(&provider.url);
+            }
+            if provider.password.is_some() {
+                provider.password = Some(REDACTED.to_string());
+            }
+            for value in provider.headers.values_mut() {
+                *value = REDACTED.to_string();
+            }
+        }
 
-    for record in &config.dns_records {
-        let domain_parts: Vec<&str> = record.dns_name.split('.').collect();
-        if domain_parts.len() < 2 {
-            error!("Invalid domain name: {}", record.dns_name);
-            continue;
+        config
+    }
+
+    fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.check_interval == 0 {
+            return Err("check_interval must be at least one second".into());
         }
 
-        let domain = format!(
-            "{}.{}",
-            domain_parts[domain_parts.len() - 2],
-            domain_parts[domain_parts.len() - 1]
-        );
+        if self.api_token.is_none() && !self.dns_records.is_empty() {
+            return Err("dns_records are set but api_token is missing".into());
+        }
 
-        let zone_id = match get_zone_id(&client, &config.api_token, &domain).await {
-            Ok(id) => id,
-            Err(e) => {
-                error!("Failed to get zone ID for {}: {:?}", domain, e);
-                continue;
+        if self.api_token.is_none() && !self.record_groups.is_empty() {
+            return Err("record_groups are set but api_token is missing".into());
+        }
+
+        for group in self
+            .record_groups
+            .iter()
+            .chain(self.accounts.iter().flat_map(|a| &a.record_groups))
+        {
+            group.validate()?;
+        }
+
+        for record in self
+            .dns_records
+            .iter()
+            .chain(self.accounts.iter().flat_map(|a| &a.dns_records))
+        {
+            pattern::expand(&record.dns_name)?;
+        }
+
+        if self.api_token.is_none() && self.records_url.is_some() {
+            return Err("records_url requires a top-level api_token".into());
+        }
+
+        if self.api_token.is_none() && self.accounts.is_empty() {
+            return Err("No api_token or [[accounts]] configured".into());
+        }
+
+        if !self.api_url.starts_with("https://") && !self.api_url.starts_with("http://") {
+            return Err(format!("api_url must be an http(s) URL, got {:?}", self.api_url).into());
+        }
+
+        for account in self.accounts() {
+            let token = account.api_token.trim();
+            if token.is_empty() || token.contains(char::is_whitespace) {
+                return Err(
+                    format!("Account {} has an unusable api_token", account.label()).into(),
+                );
             }
-        };
 
-        zone_id_map.insert(record.dns_name.clone(), zone_id.clone());
+            // Cloudflare account IDs are 32 hex characters.
+            if let Some(id) = &account.account_id {
+                if id.len() != 32 || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(format!(
+                        "Account {} has an invalid account_id: {}",
+                        account.label(),
+                        id
+                    )
+                    .into());
+                }
+            }
+        }
 
-        let record_id =
-            match get_record_id(&client, &config.api_token, &zone_id, &record.dns_name).await {
-                Ok(id) => id,
-                Err(e) => {
-                    error!("Failed to get record ID for {}: {:?}", record.dns_name, e);
-                    continue;
+        if !self.allow_command_hooks {
+            for (name, command) in [
+                ("pre_update_command", &self.pre_update_command),
+                ("post_update_command", &self.post_update_command),
+            ] {
+                if command.is_some() {
+                    return Err(
+                        format!("{} is set but allow_command_hooks is not enabled", name).into(),
+                    );
                 }
-            };
+            }
+        }
 
-        record_id_map.insert(record.dns_name.clone(), record_id.clone());
-    }
+        if self.webhook_listen_addr.is_some()
+            && self
+                .webhook_token
+                .as_deref()
+                .is_none_or(|token| token.trim().is_empty())
+        {
+            return Err("webhook_listen_addr requires a non-empty webhook_token".into());
+        }
 
-    loop {
-        match get_public_ip().await {
-            Ok(current_ip) => {
-                for record in &config.dns_records {
-                    let last_ip = last_ips.get(&record.dns_name).and_then(|v| v.as_str());
+        if let Some(tls) = &self.webhook_tls {
+            tls.validate()?;
+        }
 
-                    if last_ip != Some(&current_ip) {
-                        info!(
-                            "IP has changed to {}, updating dns for {}...",
-                            current_ip, record.dns_name
-                        );
+        self.metrics.validate()?;
+        self.health.validate()?;
+
+        let listeners = [
+            ("webhook_listen_addr", &self.webhook_listen_addr),
+            ("metrics.listen_addr", &self.metrics.listen_addr),
+            ("health.listen_addr", &self.health.listen_addr),
+        ];
+        for (i, (name, addr)) in listeners.iter().enumerate() {
+            if let Some((other, _)) = listeners[i + 1..]
+                .iter()
+                .find(|(_, other)| addr.is_some() && other == addr)
+            {
+                return Err(format!("{} and {} can't use the same address", name, other).into());
+            }
+        }
+
+        if let Some(hours) = &self.active_hours {
+            hours.validate()?;
+        }
+
+        if let Some(zone) = &self.timezone {
+            timezone::parse(zone)?;
+        }
+
+        if let Some(expression) = &self.schedule {
+            schedule::parse_cron(expression)?;
+        }
+
+        if self
+            .owned_comment_marker
+            .as_deref()
+            .is_some_and(|marker| marker.trim().is_empty())
+        {
+            return Err("owned_comment_marker must not be empty".into());
+        }
+
+        for provider in self.ipv4_providers.iter().chain(&self.ipv6_providers) {
+            provider.validate()?;
+        }
+
+        if self.verify_updates && self.ineffective_update_threshold == 0 {
+            return Err("ineffective_update_threshold must be at least 1".into());
+        }
 
-                        if update_dns_record(
-                            &client,
-                            &current_ip,
-                            &config,
-                            record,
-                            &zone_id_map[&record.dns_name],
-                            &record_id_map[&record.dns_name],
+        for (host, address) in &self.resolve_overrides {
+            if !is_hostname(host) {
+                return Err(format!("Invalid hostname in resolve_overrides: {:?}", host).into());
+            }
+            if address.parse::<IpAddr>().is_err() {
+                return Err(format!(
+                    "Invalid IP address for {} in resolve_overrides: {:?}",
+                    host, address
+                )
+                .into());
+            }
+        }
+
+        if self.ip_source == IpSource::File && self.ip_file.is_none() {
+            return Err("ip_source = \"file\" requires ip_file".into());
+        }
+
+        if self.ip_source == IpSource::File && !self.ipv6_interface.is_empty() {
+            return Err("ipv6_interface can't be combined with ip_source = \"file\"".into());
+        }
+
+        if let Some(scope) = &self.ipv6_scope {
+            if self.ipv6_interface.is_empty() {
+                return Err("ipv6_scope requires ipv6_interface".into());
+            }
+            ip::parse_ipv6_prefix(scope).map_err(|e| format!("ipv6_scope: {}", e))?;
+        }
+
+        if let Some(webhook) = &self.notifications.webhook {
+            webhook.validate()?;
+        }
+
+        for account in self.accounts() {
+            for record in &account.dns_records {
+                if let Some(hours) = &record.active_hours {
+                    hours
+                        .validate()
+                        .map_err(|e| format!("{}: {}", record.dns_name, e))?;
+                }
+
+                if let Some(name) = &record.name_override {
+                    if !is_hostname(name) || zone_name_for(name).is_none() {
+                        return Err(format!(
+                            "name_override for {} is not a valid domain name: {:?}",
+                            record.dns_name, name
                         )
-                        .await
-                        .is_ok()
-                        {
-                            last_ips[&record.dns_name] = serde_json::json!(current_ip);
-                        }
-                    } else {
-                        info!(
-                            "IP has not changed for {}, skipping update",
+                        .into());
+                    }
+                }
+
+                if record.content_command.is_some() {
+                    if !self.allow_command_hooks {
+                        return Err(format!(
+                            "{} has a content_command but allow_command_hooks is not enabled",
                             record.dns_name
-                        );
+                        )
+                        .into());
+                    }
+                    if record.ipv6_suffix.is_some() {
+                        return Err(format!(
+                            "{} can't combine content_command with ipv6_suffix",
+                            record.dns_name
+                        )
+                        .into());
                     }
                 }
 
-                save_last_ips(&last_ips);
-            }
-            Err(e) => error!("Failed to get public IP: {:?}", e),
-        }
+                if record.record_type == RecordType::Txt {
+                    if record.content_command.is_none() {
+                        return Err(format!(
+                            "{} is a TXT record, which needs a content_command for its content",
+                            record.dns_name
+                        )
+                        .into());
+                    }
+                    if record.proxied {
+                        return Err(format!(
+                            "{} is a TXT record, which can't be proxied",
+                            record.dns_name
+                        )
+                        .into());
+                    }
+                }
+
+                for (from, to) in &record.content_map {
+                    if from != "*" && from.parse::<IpAddr>().is_err() {
+                        return Err(format!(
+                            "content_map of {} has {:?} as a key, which is neither an IP nor \"*\"",
+                            record.dns_name, from
+                        )
+                        .into());
+                    }
+                    if !record.record_type.accepts_content(to) {
+                        return Err(format!(
+                            "content_map of {} maps {} to {:?}, which is not valid for a {} record",
+                            record.dns_name, from, to, record.record_type
+                        )
+                        .into());
+                    }
+                }
+
+                if let Some(zone) = &record.zone_name {
+                    if !is_hostname(zone) || !in_zone(record.cloudflare_name(), zone) {
+                        return Err(format!(
+                            "{} is not in its zone_name {:?}",
+                            record.cloudflare_name(),
+                            zone
+                        )
+                        .into());
+                    }
+                }
+
+                if record.ttl != 1 && !(60..=86400).contains(&record.ttl) {
+                    return Err(format!(
+                        "ttl for {} must be 1 (automatic) or between 60 and 86400, got {}",
+                        record.dns_name, record.ttl
+                    )
+                    .into());
+                }
+
+                if record.ipv6_prefix_len.is_some() && record.ipv6_suffix.is_none() {
+                    return Err(format!(
+                        "ipv6_prefix_len is set for {} without an ipv6_suffix",
+                        record.dns_name
+                    )
+                    .into());
+                }
+
+                let Some(suffix) = &record.ipv6_suffix else {
+                    continue;
+                };
+
+                if record.record_type != RecordType::Aaaa {
+                    return Err(format!(
+                        "ipv6_suffix is only valid for AAAA records ({})",
+                        record.dns_name
+                    )
+                    .into());
+                }
+
+                let suffix: Ipv6Addr = suffix.parse().map_err(|_| {
+                    format!("Invalid ipv6_suffix for {}: {}", record.dns_name, suffix)
+                })?;
+                ip::combine_ipv6_prefix(
+                    Ipv6Addr::UNSPECIFIED,
+                    suffix,
+                    record.ipv6_prefix_len.unwrap_or(64),
+                )
+                .map_err(|e| format!("Invalid IPv6 settings for {}: {}", record.dns_name, e))?;
+            }
+        }
+
+        // Entries clash when they share a state key, or when they point at
+        // the same Cloudflare record, e.g. through `name_override`. Zones are
+        // only the same within one token's view.
+        let accounts = self.accounts();
+        let mut keys = HashSet::new();
+        let mut targets = HashSet::new();
+        let mut duplicates = Vec::new();
+        for account in &accounts {
+            for record in &account.dns_records {
+                let target = (
+                    account.api_token.as_str(),
+                    record.zone(),
+                    record.cloudflare_name().to_ascii_lowercase(),
+                    record.record_type,
+                );
+                // Both sets get the entry, so `|` rather than `||`.
+                let clash = !keys.insert(record.state_key()) | !targets.insert(target);
+                let label = format!("{} ({})", record.cloudflare_name(), record.record_type);
+                if clash && !duplicates.contains(&label) {
+                    duplicates.push(label);
+                }
+            }
+        }
+
+        if !duplicates.is_empty() {
+            return Err(format!(
+                "DNS records listed more than once in config: {}",
+                duplicates.join(", ")
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct ZoneResponse {
+    success: bool,
+    #[serde(default)]
+    errors: Vec<serde_json::Value>,
+    #[serde(default, deserialize_with = "null_as_empty")]
+    result: Vec<ZoneInfo>,
+}
+
+#[derive(Deserialize)]
+struct ZoneInfo {
+    id: String,
+    name: String,
+}
+
+#[derive(Deserialize, Serialize)]
+struct DnsRecordInfo {
+    id: String,
+    name: String,
+    #[serde(rename = "type")]
+    record_type: String,
+    content: String,
+    #[serde(default)]
+    comment: Option<String>,
+    /// Every other field Cloudflare returned (ttl, proxied, tags, ...).
+    #[serde(flatten)]
+    extra: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct DnsRecordResponse {
+    success: bool,
+    #[serde(default)]
+    errors: Vec<serde_json::Value>,
+    #[serde(default, deserialize_with = "null_as_empty")]
+    result: Vec<DnsRecordInfo>,
+    #[serde(default)]
+    result_info: Option<ResultInfo>,
+}
+
+#[derive(Deserialize)]
+struct ResultInfo {
+    #[serde(default)]
+    total_pages: u32,
+}
+
+/// Fails with Cloudflare's `errors` when a response says `success: false`,
+/// e.g. for a bad token or a missing permission, which also comes with an
+/// empty `result` that must not be taken for "not found".
+fn check_success(success: bool, errors: &[serde_json::Value]) -> Result<(), Box<dyn Error>> {
+    if success {
+        Ok(())
+    } else {
+        Err(format!("Cloudflare API error: {:?}", errors).into())
+    }
+}
+
+/// Cloudflare occasionally answers `success: true` with a null or missing
+/// `result`; treat that as an empty list so lookups report "not found".
+fn null_as_empty<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Ok(Option::<Vec<T>>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// Reads a `content_map` with its IP keys in canonical form, so that e.g.
+/// `2620:00fe::9` matches a detected `2620:fe::9`. Two keys spelling the same
+/// address are rejected.
+fn deserialize_content_map<'de, D>(deserializer: D) -> Result<BTreeMap<String, String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let mut map = BTreeMap::new();
+    for (from, to) in BTreeMap::<String, String>::deserialize(deserializer)? {
+        let key = match from.as_str() {
+            "*" => from.clone(),
+            _ => ip::normalize(&from),
+        };
+        if map.insert(key.clone(), to).is_some() {
+            return Err(serde::de::Error::custom(format!(
+                "content_map has more than one key for {}, including {:?}",
+                key, from
+            )));
+        }
+    }
+    Ok(map)
+}
+
+fn deserialize_regex<'de, D>(deserializer: D) -> Result<Option<Regex>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let pattern = String::deserialize(deserializer)?;
+    Regex::new(&pattern)
+        .map(Some)
+        .map_err(|e| serde::de::Error::custom(format!("invalid content_regex: {}", e)))
+}
+
+fn serialize_regex<S>(regex: &Option<Regex>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    match regex {
+        Some(regex) => serializer.serialize_str(regex.as_str()),
+        None => serializer.serialize_none(),
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+struct DnsRecord {
+    dns_name: String,
+    /// Name sent to Cloudflare when it should differ from `dns_name`, which
+    /// keeps identifying the record in the state and on the command line.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name_override: Option<String>,
+    /// Zone the record lives in, for when it isn't the last two labels of
+    /// its name (e.g. a delegated subzone).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    zone_name: Option<String>,
+    proxied: bool,
+    #[serde(default)]
+    record_type: RecordType,
+    #[serde(default)]
+    update_all_matches: bool,
+    #[serde(default)]
+    paused: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ipv6_suffix: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ipv6_prefix_len: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    active_hours: Option<ActiveHours>,
+    /// Push the record every cycle even when its IP hasn't changed.
+    #[serde(default)]
+    always_update: bool,
+    /// Tags added to the record on every update.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    /// Replace the record's tags with `tags` instead of keeping the ones it
+    /// already has.
+    #[serde(default)]
+    manage_tags: bool,
+    /// Shell command printing the content to push instead of the detected
+    /// IP. Requires `allow_command_hooks`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_command: Option<String>,
+    /// Detected IPs to publish as another address, e.g. a fixed tunnel
+    /// endpoint. The key `"*"` maps any IP without an entry of its own.
+    /// Keys are normalized when the config is read.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_content_map",
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    content_map: BTreeMap<String, String>,
+    /// Pattern the content must match before it is pushed, to catch an
+    /// error message printed by `content_command` or a provider. Compiled
+    /// when the config is read.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_regex",
+        serialize_with = "serialize_regex",
+        skip_serializing_if = "Option::is_none"
+    )]
+    content_regex: Option<Regex>,
+    /// Refuse to push again within this long of the last update.
+    #[serde(default, deserialize_with = "duration::deserialize")]
+    min_update_interval_secs: u64,
+    /// Most pushes of the record in any hour, refreshes from `always_update`
+    /// included (0 means no cap).
+    #[serde(default)]
+    max_updates_per_hour: u32,
+    /// TTL in seconds, with 1 meaning "automatic".
+    #[serde(default = "default_ttl")]
+    ttl: u32,
+    /// Manage several record types under this name, each with its own
+    /// settings. Overrides `record_type` when set.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    types: Vec<RecordTypeConfig>,
+}
+
+fn default_ttl() -> u32 {
+    1
+}
+
+/// Several names under one domain sharing a detected IP, each becoming its
+/// own [`DnsRecord`].
+#[derive(Deserialize, Serialize, Clone)]
+struct RecordGroup {
+    base_domain: String,
+    /// Labels below `base_domain`, with `"@"` for the domain itself.
+    names: Vec<String>,
+    #[serde(default)]
+    proxied: bool,
+    /// Per-name exceptions to `proxied`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    proxied_by_name: BTreeMap<String, bool>,
+    #[serde(default)]
+    record_type: RecordType,
+    #[serde(default = "default_ttl")]
+    ttl: u32,
+}
+
+impl RecordGroup {
+    fn full_name(&self, name: &str) -> String {
+        match name {
+            "@" => self.base_domain.clone(),
+            _ => format!("{}.{}", name, self.base_domain),
+        }
+    }
+
+    fn records(&self) -> Vec<DnsRecord> {
+        self.names
+            .iter()
+            .map(|name| DnsRecord {
+                dns_name: self.full_name(name),
+                name_override: None,
+                zone_name: None,
+                proxied: self
+                    .proxied_by_name
+                    .get(name)
+                    .copied()
+                    .unwrap_or(self.proxied),
+                record_type: self.record_type,
+                update_all_matches: false,
+                paused: false,
+                always_update: false,
+                tags: Vec::new(),
+                manage_tags: false,
+                content_command: None,
+                content_map: BTreeMap::new(),
+                content_regex: None,
+                ipv6_suffix: None,
+                ipv6_prefix_len: None,
+                active_hours: None,
+                min_update_interval_secs: 0,
+                max_updates_per_hour: 0,
+                ttl: self.ttl,
+                types: Vec::new(),
+            })
+            .collect()
+    }
+
+    /// Checks that every name is a valid label and stays in the base
+    /// domain's zone.
+    fn validate(&self) -> Result<(), String> {
+        let zone = zone_name_for(&self.base_domain)
+            .filter(|_| is_hostname(&self.base_domain))
+            .ok_or_else(|| {
+                format!(
+                    "Invalid base_domain in record group: {:?}",
+                    self.base_domain
+                )
+            })?;
+
+        for name in &self.names {
+            let full_name = self.full_name(name);
+            if name != "@" && !is_hostname(name) {
+                return Err(format!(
+                    "Invalid name {:?} in record group {}",
+                    name, self.base_domain
+                ));
+            }
+            if zone_name_for(&full_name).as_deref() != Some(zone.as_str()) {
+                return Err(format!("{} is not in zone {}", full_name, zone));
+            }
+        }
+
+        if let Some(name) = self
+            .proxied_by_name
+            .keys()
+            .find(|name| !self.names.contains(name))
+        {
+            return Err(format!(
+                "proxied_by_name of record group {} lists {:?}, which is not in names",
+                self.base_domain, name
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Settings for one record type of a [`DnsRecord`] listing several `types`.
+/// Anything left unset is inherited from the record.
+#[derive(Deserialize, Serialize, Clone)]
+struct RecordTypeConfig {
+    record_type: RecordType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    proxied: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ttl: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ipv6_suffix: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ipv6_prefix_len: Option<u8>,
+}
+
+impl DnsRecord {
+    /// Name of the record on Cloudflare.
+    fn cloudflare_name(&self) -> &str {
+        self.name_override.as_deref().unwrap_or(&self.dns_name)
+    }
+
+    /// Zone to look the record up in: `zone_name` if set, otherwise guessed
+    /// from its name.
+    fn zone(&self) -> Option<String> {
+        match &self.zone_name {
+            Some(zone) => Some(zone.clone()),
+            None => zone_name_for(self.cloudflare_name()),
+        }
+    }
+
+    /// Splits a record whose name holds a range pattern into one record per
+    /// name, and a record listing several `types` into one record per type,
+    /// so each is detected, compared and updated independently.
+    fn expand(&self) -> Vec<DnsRecord> {
+        // Patterns are checked by `validate`, so a bad one never gets here.
+        let names = pattern::expand(&self.dns_name).unwrap_or_else(|_| vec![self.dns_name.clone()]);
+        names
+            .into_iter()
+            .flat_map(|dns_name| {
+                DnsRecord {
+                    dns_name,
+                    ..self.clone()
+                }
+                .expand_types()
+            })
+            .collect()
+    }
+
+    fn expand_types(&self) -> Vec<DnsRecord> {
+        if self.types.is_empty() {
+            return vec![self.clone()];
+        }
+
+        self.types
+            .iter()
+            .map(|variant| DnsRecord {
+                record_type: variant.record_type,
+                proxied: variant.proxied.unwrap_or(self.proxied),
+                ttl: variant.ttl.unwrap_or(self.ttl),
+                // A suffix on the record only makes sense for its AAAA variant.
+                ipv6_suffix: variant.ipv6_suffix.clone().or_else(|| {
+                    (variant.record_type == RecordType::Aaaa)
+                        .then(|| self.ipv6_suffix.clone())
+                        .flatten()
+                }),
+                ipv6_prefix_len: variant
+                    .ipv6_prefix_len
+                    .or((variant.record_type == RecordType::Aaaa)
+                        .then_some(self.ipv6_prefix_len)
+                        .flatten()),
+                types: Vec::new(),
+                ..self.clone()
+            })
+            .collect()
+    }
+
+    /// TTL actually sent to Cloudflare. Proxied records always use automatic
+    /// TTL, whatever is configured.
+    fn effective_ttl(&self) -> u32 {
+        if self.proxied {
+            1
+        } else {
+            self.ttl
+        }
+    }
+
+    /// Key identifying the Cloudflare record(s) of this record: a name can
+    /// hold both an A and an AAAA record, each with its own IDs.
+    fn record_key(&self) -> RecordKey {
+        (self.dns_name.clone(), self.record_type)
+    }
+
+    /// Key under which the last pushed content is cached. A records keep
+    /// the bare name so state written before AAAA support stays valid.
+    fn state_key(&self) -> String {
+        match self.record_type {
+            RecordType::A => self.dns_name.clone(),
+            RecordType::Aaaa | RecordType::Txt => format!("{}/{}", self.dns_name, self.record_type),
+        }
+    }
+
+    /// Content to publish for this record given the detected public IP,
+    /// after looking it up in `content_map`. AAAA records with an
+    /// `ipv6_suffix` keep only the detected prefix and append their stable
+    /// interface identifier.
+    fn content_for(&self, ip: &str) -> Result<String, Box<dyn Error>> {
+        let ip = self
+            .content_map
+            .get(&ip::normalize(ip))
+            .or_else(|| self.content_map.get("*"))
+            .map_or(ip, String::as_str);
+
+        let Some(suffix) = &self.ipv6_suffix else {
+            return Ok(ip.to_string());
+        };
+
+        let detected: Ipv6Addr = ip.parse()?;
+        let suffix: Ipv6Addr = suffix.parse()?;
+        let combined =
+            ip::combine_ipv6_prefix(detected, suffix, self.ipv6_prefix_len.unwrap_or(64))?;
+
+        Ok(combined.to_string())
+    }
+
+    /// Checks `content` against `content_regex`, if the record has one.
+    fn check_content(&self, content: &str) -> Result<(), String> {
+        match &self.content_regex {
+            Some(regex) if !regex.is_match(content) => Err(format!(
+                "Content {:?} for {} does not match content_regex {:?}",
+                content,
+                self.dns_name,
+                regex.as_str()
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(
+    Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Default,
+)]
+enum RecordType {
+    #[default]
+    A,
+    #[serde(rename = "AAAA")]
+    Aaaa,
+    /// Free-form text, which always comes from a `content_command`.
+    #[serde(rename = "TXT")]
+    Txt,
+}
+
+impl RecordType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            RecordType::A => "A",
+            RecordType::Aaaa => "AAAA",
+            RecordType::Txt => "TXT",
+        }
+    }
+
+    /// Default endpoint reporting the public address of this record's
+    /// family, or `None` for types that don't hold an address.
+    fn ip_provider_url(&self) -> Option<&'static str> {
+        match self {
+            RecordType::A => Some("https://api4.ipify.org?format=json"),
+            RecordType::Aaaa => Some("https://api6.ipify.org?format=json"),
+            RecordType::Txt => None,
+        }
+    }
+
+    /// Whether `ip` belongs to the address family this record type holds.
+    fn accepts(&self, ip: &str) -> bool {
+        match ip.parse::<IpAddr>() {
+            Ok(IpAddr::V4(_)) => *self == RecordType::A,
+            Ok(IpAddr::V6(_)) => *self == RecordType::Aaaa,
+            Err(_) => false,
+        }
+    }
+
+    /// Whether `content` can be published in a record of this type: an
+    /// address of its family, or any text up to Cloudflare's limit for TXT.
+    fn accepts_content(&self, content: &str) -> bool {
+        match self {
+            RecordType::Txt => !content.is_empty() && content.len() <= MAX_TXT_CONTENT_LEN,
+            _ => self.accepts(content),
+        }
+    }
+}
+
+impl fmt::Display for RecordType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Deserialize)]
+struct IpResponse {
+    ip: String,
+}
+
+#[derive(Serialize)]
+struct DnsUpdateRequest {
+    r#type: String,
+    name: String,
+    content: String,
+    ttl: u32,
+    proxied: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tags: Option<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+struct DnsRecordDetailResponse {
+    result: Option<DnsRecordInfo>,
+}
+
+/// Outcome of a single update cycle.
+#[derive(Serialize, Default)]
+struct UpdateReport {
+    ips: BTreeMap<RecordType, String>,
+    ip_error: Option<String>,
+    updated: Vec<String>,
+    /// Unchanged records pushed anyway because of `always_update`.
+    refreshed: Vec<String>,
+    unchanged: Vec<String>,
+    paused: Vec<String>,
+    /// Records skipped because they are outside their active hours.
+    inactive: Vec<String>,
+    /// Records held back by `min_update_interval_secs` or
+    /// `max_updates_per_hour`.
+    debounced: Vec<String>,
+    /// Records skipped because they failed too often recently.
+    circuit_open: Vec<String>,
+    /// Records pushed with another TTL than configured.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    ttl_coerced: Vec<TtlCoercion>,
+    failed: Vec<RecordFailure>,
+    timings: CycleTimings,
+    /// Set when a record reached `ineffective_update_threshold` with
+    /// `abort_on_ineffective_updates`, so the process exits with status 4.
+    #[serde(skip)]
+    abort: bool,
+}
+
+#[derive(Serialize)]
+struct TtlCoercion {
+    record: String,
+    configured: u32,
+    applied: u32,
+    reason: &'static str,
+}
+
+/// How long the parts of a cycle took, to help size `check_interval`.
+#[derive(Serialize, Default, Clone, Copy)]
+struct CycleTimings {
+    ip_fetch_ms: u128,
+    update_ms: u128,
+    total_ms: u128,
+    records_processed: usize,
+    /// Time spent waiting for `max_updates_per_minute`.
+    paced_ms: u128,
+}
+
+#[derive(Serialize)]
+struct RecordFailure {
+    record: String,
+    error: String,
+}
+
+impl RecordFailure {
+    fn new(record: &str, error: impl ToString) -> Self {
+        RecordFailure {
+            record: record.to_string(),
+            error: error.to_string(),
+        }
+    }
+}
+
+impl UpdateReport {
+    /// Exit code for `--once`: 0 when everything succeeded, 2 when some
+    /// records failed, 3 when nothing could be done at all and 4 when
+    /// `abort_on_ineffective_updates` was triggered.
+    fn exit_code(&self) -> i32 {
+        let succeeded = self.updated.len() + self.refreshed.len() + self.unchanged.len();
+
+        if self.abort {
+            4
+        } else if self.ip_error.is_some() || (succeeded == 0 && !self.failed.is_empty()) {
+            3
+        } else if !self.failed.is_empty() {
+            2
+        } else {
+            0
+        }
+    }
+
+    fn summary(&self) -> String {
+        match &self.ip_error {
+            Some(e) => format!("Could not detect public IP: {}", e),
+            None => format!(
+                "{} updated, {} refreshed, {} unchanged, {} paused, {} inactive, {} debounced, {} circuit-broken, {} failed",
+                self.updated.len(),
+                self.refreshed.len(),
+                self.unchanged.len(),
+                self.paused.len(),
+                self.inactive.len(),
+                self.debounced.len(),
+                self.circuit_open.len(),
+                self.failed.len()
+            ),
+        }
+    }
+}
+
+/// Why a record update failed, split by whether trying again could help.
+#[derive(Debug)]
+enum UpdateError {
+    /// Network errors and 5xx/429 responses.
+    Transient(String),
+    /// Anything Cloudflare explicitly rejected.
+    Rejected(String),
+}
+
+/// Why [`verify_update`] found that an update had no effect.
+#[derive(Debug)]
+enum VerifyError {
+    /// Retried on the next cycle.
+    Ineffective(String),
+    /// Reached `ineffective_update_threshold` with
+    /// `abort_on_ineffective_updates` set.
+    Abort(String),
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyError::Ineffective(e) | VerifyError::Abort(e) => f.write_str(e),
+        }
+    }
+}
+
+impl fmt::Display for UpdateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpdateError::Transient(e) => write!(f, "transient error: {}", e),
+            UpdateError::Rejected(e) => write!(f, "rejected: {}", e),
+        }
+    }
+}
+
+impl Error for UpdateError {}
+
+/// A record name exists in the zone, but not with the requested type.
+#[derive(Debug)]
+struct TypeMismatch {
+    name: String,
+    wanted: RecordType,
+    found: Vec<String>,
+}
+
+impl fmt::Display for TypeMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} has no {} record, only {}; create the {} record in Cloudflare first",
+            self.name,
+            self.wanted,
+            self.found.join(", "),
+            self.wanted
+        )
+    }
+}
+
+impl Error for TypeMismatch {}
+
+#[derive(Deserialize)]
+struct CloudflareResponse {
+    success: bool,
+    errors: Vec<serde_json::Value>,
+}
+
+/// Asks the configured providers for the public address of `record_type`'s
+/// family, in order, returning the first usable answer and the URL of the
+/// provider that gave it.
+async fn get_public_ip(
+    config: &Config,
+    clock: &dyn Clock,
+    record_type: RecordType,
+) -> Result<(String, String), Box<dyn Error>> {
+    if let (IpSource::File, Some(path)) = (config.ip_source, &config.ip_file) {
+        let ip = read_ip_file(path, record_type, config.ip_file_max_age_secs, clock.now())?;
+        return Ok((ip, path.clone()));
+    }
+
+    if record_type == RecordType::Aaaa && !config.ipv6_interface.is_empty() {
+        let scope = config
+            .ipv6_scope
+            .as_deref()
+            .map(ip::parse_ipv6_prefix)
+            .transpose()?;
+        let (ip, name) =
+            interface::ipv6_address(&config.ipv6_interface, scope, config.ipv6_address_selection)?;
+        return Ok((ip.to_string(), format!("interface {}", name)));
+    }
+
+    let client = ip_client(config);
+    let providers = provider::ordered(config.ip_providers(record_type), clock.instant());
+    let mut errors = Vec::new();
+
+    for provider in &providers {
+        let result = query_ip_provider(config, &client, record_type, provider).await;
+        metrics::record_ip_provider_request(&provider.url, result.is_ok());
+        if providers.len() > 1 {
+            provider::record_health(&provider.url, result.is_ok(), clock.instant());
+        }
+
+        match result {
+            Ok(ip) => return Ok((ip, provider.url.clone())),
+            Err(e) => {
+                if providers.len() > 1 {
+                    warn!("IP provider {} failed: {}", provider.url, e);
+                }
+                errors.push((provider.url.as_str(), e.to_string()));
+            }
+        }
+    }
+
+    match errors.as_slice() {
+        [(_, e)] => Err(e.clone().into()),
+        _ => Err(errors
+            .iter()
+            .map(|(url, e)| format!("{}: {}", url, e))
+            .collect::<Vec<_>>()
+            .join("; ")
+            .into()),
+    }
+}
+
+/// Reads the first address of `record_type`'s family from `path`, which
+/// holds one address per line and must have been written within
+/// `max_age_secs` of `now`.
+fn read_ip_file(
+    path: &str,
+    record_type: RecordType,
+    max_age_secs: u64,
+    now: SystemTime,
+) -> Result<String, Box<dyn Error>> {
+    let read_error = |e: std::io::Error| format!("Failed to read {}: {}", path, e);
+
+    if max_age_secs > 0 {
+        let modified = fs::metadata(path)
+            .and_then(|meta| meta.modified())
+            .map_err(read_error)?;
+        let age = now.duration_since(modified).unwrap_or_default();
+        if age.as_secs() > max_age_secs {
+            return Err(format!("{} is stale: last written {}s ago", path, age.as_secs()).into());
+        }
+    }
+
+    let contents = fs::read_to_string(path).map_err(read_error)?;
+    if contents.trim().is_empty() {
+        return Err(format!("{} is empty", path).into());
+    }
+
+    let ip = contents
+        .lines()
+        .map(|line| ip::normalize(line.trim()))
+        .find(|ip| record_type.accepts(ip))
+        .ok_or_else(|| {
+            format!(
+                "{} holds no address usable for {} records",
+                path, record_type
+            )
+        })?;
+
+    if let Some(reason) = ip.parse().ok().and_then(ip::non_public_reason) {
+        return Err(format!(
+            "{} holds {}, which is not a public address ({})",
+            path, ip, reason
+        )
+        .into());
+    }
+
+    Ok(ip)
+}
+
+async fn query_ip_provider(
+    config: &Config,
+    client: &Client,
+    record_type: RecordType,
+    provider: &IpProvider,
+) -> Result<String, Box<dyn Error>> {
+    let ip = match provider.source {
+        ProviderSource::Http => {
+            let response = provider.request(client).send().await?.error_for_status()?;
+            let body = read_limited(response, config.max_response_bytes).await?;
+            ip::normalize(&parse_ip_body(provider, &body)?)
+        }
+        ProviderSource::Stun => stun::public_ip(&provider.url, record_type)
+            .await?
+            .to_string(),
+    };
+
+    if !record_type.accepts(&ip) {
+        return Err(format!(
+            "IP provider returned {}, which is not usable for {} records",
+            ip, record_type
+        )
+        .into());
+    }
+
+    if let Some(reason) = ip.parse().ok().and_then(ip::non_public_reason) {
+        return Err(format!(
+            "IP provider returned {}, which is not a public address ({})",
+            ip, reason
+        )
+        .into());
+    }
+
+    Ok(ip)
+}
+
+/// Reads a response body as text, refusing to buffer more than `limit`
+/// bytes so a misbehaving server can't exhaust memory.
+async fn read_limited(
+    mut response: reqwest::Response,
+    limit: usize,
+) -> Result<String, Box<dyn Error>> {
+    let url = response.url().clone();
+    let too_large = || {
+        format!(
+            "Response from {} exceeds max_response_bytes ({} bytes)",
+            url, limit
+        )
+    };
+
+    if response
+        .content_length()
+        .is_some_and(|len| len > limit as u64)
+    {
+        return Err(too_large().into());
+    }
+
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if body.len() + chunk.len() > limit {
+            return Err(too_large().into());
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    Ok(String::from_utf8_lossy(&body).into_owned())
+}
+
+/// Extracts the IP from a provider response in the provider's declared
+/// format. By default it is normally JSON but may be plain text. Anything
+/// else (e.g. a captive portal page) is reported with the start of the body.
+fn parse_ip_body(provider: &IpProvider, body: &str) -> Result<String, Box<dyn Error>> {
+    match provider.format {
+        ResponseFormat::Json => return provider.json_ip(body),
+        ResponseFormat::Auto => {
+            if let Ok(response) = serde_json::from_str::<IpResponse>(body) {
+                return Ok(response.ip);
+            }
+        }
+        ResponseFormat::Text | ResponseFormat::Trace => {}
+    }
+
+    let text = match provider.format {
+        ResponseFormat::Trace => body
+            .lines()
+            .find_map(|line| line.strip_prefix("ip="))
+            .unwrap_or(body),
+        _ => body,
+    }
+    .trim();
+    if text.parse::<IpAddr>().is_ok() {
+        return Ok(text.to_string());
+    }
+
+    let preview: String = text.chars().take(100).collect();
+    Err(format!("IP provider returned an unexpected response: {:?}", preview).into())
+}
+
+/// Public IP for every address family in use, or why it couldn't be detected.
+type DetectedIps = BTreeMap<RecordType, Result<String, String>>;
+
+/// Detects the public IP once per address family needed by `records`.
+async fn detect_ips<'a>(
+    config: &Config,
+    clock: &dyn Clock,
+    records: impl IntoIterator<Item = &'a DnsRecord>,
+) -> DetectedIps {
+    let mut detected = DetectedIps::new();
+
+    for record in records {
+        // Records fed by a command don't need their family detected.
+        if record.content_command.is_some() || detected.contains_key(&record.record_type) {
+            continue;
+        }
+
+        let result = get_public_ip(config, clock, record.record_type)
+            .await
+            .map(|(ip, _)| ip)
+            .map_err(|e| e.to_string());
+        detected.insert(record.record_type, result);
+    }
+
+    detected
+}
+
+/// Returns the IP detected for `record`'s family, or an error naming the
+/// family that couldn't be detected.
+fn detected_ip_for<'a>(detected: &'a DetectedIps, record: &DnsRecord) -> Result<&'a str, String> {
+    match detected.get(&record.record_type) {
+        Some(Ok(ip)) => Ok(ip),
+        Some(Err(e)) => Err(format!(
+            "No public IP detected for {} records: {}",
+            record.record_type, e
+        )),
+        None => Err(format!(
+            "No public IP detected for {} records",
+            record.record_type
+        )),
+    }
+}
+
+/// Runs the `content_command` of `record` with the configured timeout.
+async fn record_command_content(
+    config: &Config,
+    record: &DnsRecord,
+    command: &str,
+) -> Result<String, Box<dyn Error>> {
+    command::content(
+        command,
+        record.record_type,
+        Duration::from_secs(config.command_timeout_secs),
+    )
+    .await
+}
+
+/// Runs the `pre_update_command` or `post_update_command` hook, if set,
+/// describing the update in `DDNS_*` environment variables. Post hooks also
+/// get the `result` of the update. Failures are only logged.
+async fn run_update_hook(
+    config: &Config,
+    name: &str,
+    command: &Option<String>,
+    record: &DnsRecord,
+    old_ip: Option<&str>,
+    new_ip: &str,
+    result: Option<&Result<(), String>>,
+) {
+    let Some(command) = command else {
+        return;
+    };
+
+    let mut env = vec![
+        ("DDNS_RECORD", record.dns_name.as_str()),
+        ("DDNS_RECORD_TYPE", record.record_type.as_str()),
+        ("DDNS_NEW_IP", new_ip),
+        ("DDNS_OLD_IP", old_ip.unwrap_or("")),
+    ];
+    if let Some(result) = result {
+        env.push((
+            "DDNS_RESULT",
+            if result.is_ok() { "success" } else { "failure" },
+        ));
+        env.push((
+            "DDNS_ERROR",
+            result.as_ref().err().map_or("", String::as_str),
+        ));
+    }
+
+    let timeout = Duration::from_secs(config.command_timeout_secs);
+    if let Err(e) = command::hook(name, command, &env, timeout).await {
+        warn!("Update hook for {} failed: {}", record.dns_name, e);
+    }
+}
+
+/// Error to report when no family at all could be detected.
+fn total_detection_failure(detected: &DetectedIps) -> Option<String> {
+    if detected.is_empty() || detected.values().any(Result::is_ok) {
+        return None;
+    }
+
+    let errors: Vec<String> = detected
+        .iter()
+        .filter_map(|(record_type, result)| {
+            result
+                .as_ref()
+                .err()
+                .map(|e| format!("{}: {}", record_type, e))
+        })
+        .collect();
+
+    Some(errors.join("; "))
+}
+
+async fn fetch_zones(client: &Client, account: &Account) -> Result<Vec<ZoneInfo>, Box<dyn Error>> {
+    let mut request = client.get(account.api("/zones"));
+    if let Some(account_id) = &account.account_id {
+        request = request.query(&[("account.id", account_id)]);
+    }
+
+    let response: ZoneResponse = request
+        .header("Authorization", format!("Bearer {}", account.api_token))
+        .header("Content-Type", "application/json")
+        .send()
+        .await?
+        .json()
+        .await?;
+
+    check_success(response.success, &response.errors)?;
+    Ok(response.result)
+}
+
+async fn get_zone_id(
+    client: &Client,
+    account: &Account,
+    domain: &str,
+) -> Result<String, Box<dyn Error>> {
+    for zone in fetch_zones(client, account).await? {
+        if zone.name == domain {
+            return Ok(zone.id);
+        }
+    }
+
+    match &account.account_id {
+        Some(id) => {
+            Err(format!("Zone ID not found for domain: {} in account {}", domain, id).into())
+        }
+        None => Err(format!("Zone ID not found for domain: {}", domain).into()),
+    }
+}
+
+/// Looks up the ID of `domain`, the zone of `record`. Without `zone_name`
+/// the longest visible zone holding the record wins over `domain`, so a
+/// delegated subzone is found even though its parent is visible too. When
+/// there is no such zone, the error explains how the record name relates to
+/// the zones the token can see, to point out typos and zones that need
+/// `zone_name`.
+async fn record_zone_id(
+    client: &Client,
+    account: &Account,
+    record: &DnsRecord,
+    domain: &str,
+) -> Result<String, Box<dyn Error>> {
+    let zones = fetch_zones(client, account).await?;
+    let name = record.cloudflare_name().to_ascii_lowercase();
+    let containing = zones
+        .iter()
+        .filter(|zone| in_zone(&name, &zone.name))
+        .max_by_key(|zone| zone.name.len());
+
+    let found = match &record.zone_name {
+        Some(_) => zones.iter().find(|zone| zone.name == domain),
+        None => containing,
+    };
+    if let Some(zone) = found {
+        return Ok(zone.id.clone());
+    }
+
+    if let Some(zone) = containing {
+        return Err(format!(
+            "{} is in zone {}, not {}: set zone_name = {:?} on the record",
+            name, zone.name, domain, zone.name
+        )
+        .into());
+    }
+
+    let visible: Vec<_> = zones.iter().map(|zone| zone.name.as_str()).collect();
+    if visible.is_empty() {
+        return Err(format!("zone {} not found: the token can't see any zones", domain).into());
+    }
+    Err(format!(
+        "{} is not in any zone the token can see ({}), check the record name for typos",
+        name,
+        visible.join(", ")
+    )
+    .into())
+}
+
+/// Whether `name` is `zone` itself or a name below it.
+fn in_zone(name: &str, zone: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    let zone = zone.to_ascii_lowercase();
+    name == zone || name.ends_with(&format!(".{}", zone))
+}
+
+async fn find_records(
+    client: &Client,
+    account: &Account,
+    zone_id: &str,
+    dns_name: &str,
+    record_type: RecordType,
+    marker: Option<&str>,
+) -> Result<Vec<DnsRecordInfo>, Box<dyn Error>> {
+    let url = account.api(&format!("/zones/{}/dns_records", zone_id));
+    let response: DnsRecordResponse = client
+        .get(&url)
+        .header("Authorization", format!("Bearer {}", account.api_token))
+        .header("Content-Type", "application/json")
+        .send()
+        .await?
+        .json()
+        .await?;
+    check_success(response.success, &response.errors)?;
+
+    let (matches, other_types): (Vec<_>, Vec<_>) = response
+        .result
+        .into_iter()
+        .filter(|record| record.name == dns_name)
+        .partition(|record| record.record_type == record_type.as_str());
+
+    if matches.is_empty() && !other_types.is_empty() {
+        let mut found: Vec<_> = other_types.into_iter().map(|r| r.record_type).collect();
+        found.sort();
+        found.dedup();
+        return Err(TypeMismatch {
+            name: dns_name.to_string(),
+            wanted: record_type,
+            found,
+        }
+        .into());
+    }
+
+    Ok(matches
+        .into_iter()
+        .filter(|record| is_owned(record, marker))
+        .collect())
+}
+
+/// With an ownership marker configured, only records whose comment carries
+/// it are considered managed by this tool.
+fn is_owned(record: &DnsRecordInfo, marker: Option<&str>) -> bool {
+    match marker {
+        Some(marker) => record
+            .comment
+            .as_deref()
+            .is_some_and(|comment| comment.contains(marker)),
+        None => true,
+    }
+}
+
+/// Checks the Cloudflare records matching `record`, refusing to pick one
+/// arbitrarily when several share the same name and type.
+fn select_records(
+    record: &DnsRecord,
+    matches: Vec<DnsRecordInfo>,
+) -> Result<Vec<DnsRecordInfo>, Box<dyn Error>> {
+    if matches.is_empty() {
+        return Err(format!(
+            "DNS record ID not found for domain: {} ({})",
+            record.dns_name, record.record_type
+        )
+        .into());
+    }
+
+    if matches.len() > 1 && !record.update_all_matches {
+        return Err(format!(
+            "Found {} {} records named {}; set update_all_matches = true to update all of them",
+            matches.len(),
+            record.record_type,
+            record.dns_name
+        )
+        .into());
+    }
+
+    Ok(matches)
+}
+
+async fn get_record_ids(
+    client: &Client,
+    account: &Account,
+    zone_id: &str,
+    record: &DnsRecord,
+    marker: Option<&str>,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let matches = find_records(
+        client,
+        account,
+        zone_id,
+        record.cloudflare_name(),
+        record.record_type,
+        marker,
+    )
+    .await?;
+
+    Ok(select_records(record, matches)?
+        .into_iter()
+        .map(|info| info.id)
+        .collect())
+}
+
+/// Logs a successful update of `record` using `log_update_template`.
+fn log_update(config: &Config, record: &DnsRecord, old_ip: Option<&str>, new_ip: &str) {
+    let zone = record.zone().unwrap_or_default();
+    info!(
+        "{}",
+        config
+            .log_update_template
+            .replace("{record}", &record.dns_name)
+            .replace("{old_ip}", old_ip.unwrap_or("unknown"))
+            .replace("{new_ip}", new_ip)
+            .replace("{type}", record.record_type.as_str())
+            .replace("{zone}", &zone)
+    );
+}
+
+async fn update_dns_record(
+    client: &Client,
+    account: &Account,
+    ip: &str,
+    record: &DnsRecord,
+    zone_id: &str,
+    record_id: &str,
+    config: &Config,
+) -> Result<(), UpdateError> {
+    let api_token = &account.api_token;
+    let url = account.api(&format!("/zones/{}/dns_records/{}", zone_id, record_id));
+
+    // Without anything to check or merge, a PATCH of the fields this tool
+    // sets leaves the comment and tags alone and spares reading the record.
+    let replace = config.safe_update
+        || config.preserve_unknown_fields
+        || config.owned_comment_marker.is_some()
+        || (!record.manage_tags && !record.tags.is_empty());
+
+    for _ in 0..=SAFE_UPDATE_RETRIES {
+        let snapshot = if replace {
+            read_record(client, api_token, &url).await?
+        } else {
+            RecordSnapshot::default()
+        };
+
+        let request_data = DnsUpdateRequest {
+            r#type: record.record_type.to_string(),
+            name: record.cloudflare_name().to_string(),
+            content: ip.to_string(),
+            ttl: record.effective_ttl(),
+            proxied: record.proxied,
+            comment: record_comment(
+                config.owned_comment_marker.as_deref(),
+                snapshot
+                    .info
+                    .as_ref()
+                    .and_then(|info| info.comment.as_deref()),
+            ),
+            tags: record_tags(record, snapshot.tags()),
+        };
+
+        let mut request = if replace {
+            client.put(&url)
+        } else {
+            client.patch(&url)
+        }
+        .header("Authorization", format!("Bearer {}", api_token))
+        .header("Content-Type", "application/json");
+        request = match snapshot
+            .info
+            .as_ref()
+            .filter(|_| config.preserve_unknown_fields)
+        {
+            Some(current) => request.json(&merged_update(current, &request_data)),
+            None => request.json(&request_data),
+        };
+        if config.safe_update {
+            request = snapshot.condition(request);
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| UpdateError::Transient(e.to_string()))?;
+
+        let status = response.status();
+        if status == reqwest::StatusCode::PRECONDITION_FAILED {
+            warn!(
+                "{} was changed on Cloudflare while it was being updated, reading it again",
+                record.dns_name
+            );
+            continue;
+        }
+        if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            return Err(UpdateError::Transient(format!(
+                "Cloudflare returned {}",
+                status
+            )));
+        }
+
+        let response: CloudflareResponse = response
+            .json()
+            .await
+            .map_err(|e| UpdateError::Rejected(e.to_string()))?;
+
+        return if response.success {
+            Ok(())
+        } else {
+            Err(UpdateError::Rejected(format!(
+                "Cloudflare API error: {:?}",
+                response.errors
+            )))
+        };
+    }
+
+    Err(UpdateError::Rejected(format!(
+        "{} kept changing on Cloudflare, gave up after {} attempts",
+        record.dns_name,
+        SAFE_UPDATE_RETRIES + 1
+    )))
+}
+
+/// Fields Cloudflare reports but sets itself, left out of merged updates.
+const READ_ONLY_FIELDS: [&str; 10] = [
+    "id",
+    "zone_id",
+    "zone_name",
+    "created_on",
+    "modified_on",
+    "comment_modified_on",
+    "tags_modified_on",
+    "proxiable",
+    "locked",
+    "meta",
+];
+
+/// The `current` record with the fields of `update` laid over it, so fields
+/// such as `settings` or `data` survive the update unchanged.
+fn merged_update(current: &DnsRecordInfo, update: &DnsUpdateRequest) -> serde_json::Value {
+    let mut merged = match serde_json::to_value(current) {
+        Ok(serde_json::Value::Object(fields)) => fields,
+        _ => serde_json::Map::new(),
+    };
+    for field in READ_ONLY_FIELDS {
+        merged.remove(field);
+    }
+    if let Ok(serde_json::Value::Object(fields)) = serde_json::to_value(update) {
+        merged.extend(fields);
+    }
+    serde_json::Value::Object(merged)
+}
+
+/// The record as read right before an update.
+#[derive(Default)]
+struct RecordSnapshot {
+    info: Option<DnsRecordInfo>,
+    etag: Option<String>,
+}
+
+impl RecordSnapshot {
+    /// The record's current tags, if Cloudflare reported any.
+    fn tags(&self) -> Option<Vec<String>> {
+        self.info
+            .as_ref()
+            .and_then(|info| info.extra.get("tags").cloned())
+            .and_then(|tags| serde_json::from_value(tags).ok())
+    }
+
+    /// Makes `request` conditional on the record not having changed since
+    /// it was read: `If-Match` with its ETag, or `If-Unmodified-Since` with
+    /// its `modified_on` when there is no ETag.
+    fn condition(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        if let Some(etag) = &self.etag {
+            return request.header(reqwest::header::IF_MATCH, etag);
+        }
+
+        let modified = self
+            .info
+            .as_ref()
+            .and_then(|info| info.extra.get("modified_on"))
+            .and_then(serde_json::Value::as_str)
+            .and_then(|modified| chrono::DateTime::parse_from_rfc3339(modified).ok());
+        match modified {
+            Some(modified) => request.header(
+                reqwest::header::IF_UNMODIFIED_SINCE,
+                modified
+                    .with_timezone(&chrono::Utc)
+                    .format("%a, %d %b %Y %H:%M:%S GMT")
+                    .to_string(),
+            ),
+            None => request,
+        }
+    }
+}
+
+async fn read_record(
+    client: &Client,
+    api_token: &str,
+    url: &str,
+) -> Result<RecordSnapshot, UpdateError> {
+    let response = client
+        .get(url)
+        .header("Authorization", format!("Bearer {}", api_token))
+        .send()
+        .await
+        .map_err(|e| UpdateError::Transient(e.to_string()))?;
+
+    let etag = response
+        .headers()
+        .get(reqwest::header::ETAG)
+        .and_then(|etag| etag.to_str().ok())
+        .map(str::to_string);
+    let response: DnsRecordDetailResponse = response
+        .json()
+        .await
+        .map_err(|e| UpdateError::Transient(e.to_string()))?;
+
+    Ok(RecordSnapshot {
+        info: response.result,
+        etag,
+    })
+}
+
+/// Tags to send with an update: exactly `tags` with `manage_tags`,
+/// otherwise the record's `current` tags plus `tags`. `None` leaves the
+/// field out, for plans without tag support.
+fn record_tags(record: &DnsRecord, current: Option<Vec<String>>) -> Option<Vec<String>> {
+    if record.manage_tags {
+        return Some(record.tags.clone());
+    }
+
+    if current.is_none() && record.tags.is_empty() {
+        return None;
+    }
+
+    let mut tags = current.unwrap_or_default();
+    for tag in &record.tags {
+        if !tags.contains(tag) {
+            tags.push(tag.clone());
+        }
+    }
+    Some(tags)
+}
+
+/// The comment to send with an update: the record's current comment, with
+/// the ownership marker appended if it doesn't carry it yet.
+fn record_comment(marker: Option<&str>, current: Option<&str>) -> Option<String> {
+    let current = current.filter(|comment| !comment.trim().is_empty());
+    match (marker, current) {
+        (Some(marker), Some(comment)) if !comment.contains(marker) => {
+            Some(format!("{} {}", comment, marker))
+        }
+        (Some(marker), None) => Some(marker.to_string()),
+        (_, current) => current.map(str::to_string),
+    }
+}
+
+/// Runs [`update_dns_record`], retrying transient failures up to
+/// `update_retries` times.
+async fn update_with_retries(
+    ctx: &Context,
+    client: &Client,
+    account: &Account,
+    ip: &str,
+    record: &DnsRecord,
+    zone_id: &str,
+    record_id: &str,
+) -> Result<(), UpdateError> {
+    let config = &ctx.config;
+    let mut attempt = 0;
+
+    loop {
+        match update_dns_record(client, account, ip, record, zone_id, record_id, config).await {
+            Err(UpdateError::Transient(e)) if attempt < config.update_retries => {
+                attempt += 1;
+                warn!(
+                    "Updating {} failed ({}), retrying in {}s ({}/{})",
+                    record.dns_name,
+                    e,
+                    config.update_retry_delay_secs,
+                    attempt,
+                    config.update_retries
+                );
+                ctx.clock
+                    .sleep(Duration::from_secs(config.update_retry_delay_secs))
+                    .await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// HTTP client for the Cloudflare API, honouring `resolve_overrides` and
+/// `http_version`.
+fn api_client(config: &Config) -> Client {
+    let mut builder = match config.http_version {
+        HttpVersion::Auto => Client::builder(),
+        HttpVersion::Http1 => Client::builder().http1_only(),
+        HttpVersion::Http2 => Client::builder().http2_prior_knowledge(),
+    };
+    for (host, address) in &config.resolve_overrides {
+        if let Ok(address) = address.parse::<IpAddr>() {
+            // The port is taken from the request URL.
+            builder = builder.resolve(host, SocketAddr::new(address, 0));
+        }
+    }
+
+    builder.build().expect("Failed to build HTTP client")
+}
+
+/// HTTP client for IP providers. Only this client ever skips certificate
+/// checks, with `ip_provider_insecure_tls`.
+fn ip_client(config: &Config) -> Client {
+    Client::builder()
+        .danger_accept_invalid_certs(config.ip_provider_insecure_tls)
+        .build()
+        .expect("Failed to build HTTP client")
+}
+
+fn state_store(config: &Config) -> Result<Box<dyn StateStore>, Box<dyn Error>> {
+    state::open(
+        config.state_backend,
+        LAST_IP_FILE,
+        config.state_format,
+        config.state_db_path.as_deref().unwrap_or(STATE_DB_FILE),
+        config.redis_url.as_deref(),
+    )
+}
+
+fn save_state(store: &dyn StateStore, ips: &LastIps) {
+    if let Err(e) = store.save(ips) {
+        error!("Failed to save state: {:?}", e);
+    }
+}
+
+/// Remembers the failure of the record stored under `state_key`, or clears
+/// it with `None` after a success.
+fn note_record_error(ctx: &Context, state_key: &str, error: Option<&str>) {
+    let mut errors = ctx.record_errors.lock().unwrap();
+    let entry = error.map(|error| RecordError {
+        error: error.chars().take(MAX_RECORD_ERROR_LEN).collect(),
+        at: ctx
+            .clock
+            .now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+    });
+    let changed = match &entry {
+        Some(entry) => {
+            errors.insert(state_key.to_string(), entry.clone());
+            true
+        }
+        None => errors.remove(state_key).is_some(),
+    };
+
+    if changed {
+        if let Err(e) = ctx.state.save_error(state_key, entry.as_ref()) {
+            warn!("Failed to save the last error of {}: {}", state_key, e);
+        }
+    }
+}
+
+/// A tripped circuit breaker as kept in `BREAKERS_FILE`.
+#[derive(Deserialize, Serialize, Clone, PartialEq)]
+struct BreakerView {
+    state: BreakerState,
+    /// Unix timestamp of the end of the cooldown.
+    until: u64,
+}
+
+fn read_breakers() -> BTreeMap<String, BreakerView> {
+    fs::read_to_string(BREAKERS_FILE)
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default()
+}
+
+/// Publishes the circuit breakers that are open or half-open to the metrics
+/// and to `BREAKERS_FILE`, which is only rewritten when they change.
+fn note_breakers(ctx: &Context) {
+    let now = ctx.clock.now();
+    let breakers: BTreeMap<String, BreakerView> = ctx
+        .breaker
+        .tripped(ctx.clock.instant())
+        .into_iter()
+        .map(|(key, remaining)| {
+            let until = (now + remaining)
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            let state = BreakerState::with_remaining(remaining);
+            (key, BreakerView { state, until })
+        })
+        .collect();
+
+    metrics::record_breakers(
+        breakers
+            .iter()
+            .map(|(key, view)| (key.clone(), view.state))
+            .collect(),
+    );
+
+    if breakers == read_breakers() {
+        return;
+    }
+    let result = serde_json::to_string_pretty(&breakers)
+        .map_err(Into::into)
+        .and_then(|data| state::write_atomic(BREAKERS_FILE, &data));
+    if let Err(e) = result {
+        warn!("Failed to save {}: {}", BREAKERS_FILE, e);
+    }
+}
+
+#[derive(Serialize)]
+struct RecordStateView {
+    record: String,
+    record_type: RecordType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_ip: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_error: Option<RecordError>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    circuit_breaker: Option<BreakerView>,
+}
+
+/// Prints the cached IP, last error and circuit breaker of every configured
+/// record.
+fn show_state(config: &Config, output: OutputFormat) -> Result<(), Box<dyn Error>> {
+    let store = state_store(config)?;
+    let last_ips = store.load();
+    let errors = store.errors();
+    let breakers = read_breakers();
+
+    let records: Vec<RecordStateView> = config
+        .accounts()
+        .iter()
+        .flat_map(|account| &account.dns_records)
+        .map(|record| RecordStateView {
+            record: record.dns_name.clone(),
+            record_type: record.record_type,
+            last_ip: last_ips.get(&record.state_key()).cloned(),
+            last_error: errors.get(&record.state_key()).cloned(),
+            circuit_breaker: breakers.get(&record.state_key()).cloned(),
+        })
+        .collect();
+
+    match output {
+        OutputFormat::Text => {
+            for view in &records {
+                println!(
+                    "{} ({}): {}",
+                    view.record,
+                    view.record_type,
+                    view.last_ip.as_deref().unwrap_or("no cached IP")
+                );
+                if let Some(error) = &view.last_error {
+                    let at = timezone::format(UNIX_EPOCH + Duration::from_secs(error.at));
+                    println!("    last error at {}: {}", at, error.error);
+                }
+                if let Some(breaker) = &view.circuit_breaker {
+                    let until = timezone::format(UNIX_EPOCH + Duration::from_secs(breaker.until));
+                    match breaker.state {
+                        BreakerState::Open => {
+                            println!("    circuit breaker open until {}", until)
+                        }
+                        BreakerState::HalfOpen => {
+                            println!("    circuit breaker half-open, retried next cycle")
+                        }
+                    }
+                }
+            }
+        }
+        OutputFormat::Json => print_json(&records),
+    }
+
+    Ok(())
+}
+
+/// Records paused at runtime through the `pause` subcommand.
+fn read_paused() -> HashSet<String> {
+    fs::read_to_string(PAUSED_FILE)
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default()
+}
+
+fn save_paused(paused: &HashSet<String>) -> Result<(), Box<dyn Error>> {
+    let mut names: Vec<&String> = paused.iter().collect();
+    names.sort();
+    state::write_atomic(PAUSED_FILE, &serde_json::to_string_pretty(&names)?)
+}
+
+fn set_paused(config: &Config, record: &str, paused: bool) -> Result<(), Box<dyn Error>> {
+    let configured = config
+        .accounts()
+        .iter()
+        .any(|account| account.dns_records.iter().any(|r| r.dns_name == record));
+    if !configured {
+        return Err(format!("{} is not a configured record", record).into());
+    }
+
+    let mut names = read_paused();
+    if paused {
+        names.insert(record.to_string());
+    } else {
+        names.remove(record);
+    }
+
+    save_paused(&names)
+}
+
+#[derive(Serialize)]
+struct IpCheck {
+    record_type: RecordType,
+    /// The provider that answered.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    provider: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ip: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Runs only IP detection for `families` and prints what each provider
+/// answered. Returns 0 if every family was detected and 3 otherwise.
+async fn check_ip(config: &Config, families: &[RecordType], output: OutputFormat) -> i32 {
+    let mut checks = Vec::new();
+    for &record_type in families {
+        let check = match get_public_ip(config, &SystemClock, record_type).await {
+            Ok((ip, provider)) => IpCheck {
+                record_type,
+                provider: Some(provider),
+                ip: Some(ip),
+                error: None,
+            },
+            Err(e) => IpCheck {
+                record_type,
+                provider: None,
+                ip: None,
+                error: Some(e.to_string()),
+            },
+        };
+        checks.push(check);
+    }
+
+    match output {
+        OutputFormat::Text => {
+            for check in &checks {
+                match (&check.ip, &check.provider, &check.error) {
+                    (Some(ip), Some(provider), _) => {
+                        println!("{}: {} (from {})", check.record_type, ip, provider)
+                    }
+                    (_, _, Some(e)) => println!("{}: not detected: {}", check.record_type, e),
+                    _ => {}
+                }
+            }
+        }
+        OutputFormat::Json => print_json(&checks),
+    }
+
+    if checks.iter().all(|check| check.ip.is_some()) {
+        0
+    } else {
+        3
+    }
+}
+
+fn run_state_action(
+    config: &Config,
+    action: StateAction,
+    output: OutputFormat,
+) -> Result<(), Box<dyn Error>> {
+    if config.state_backend != StateBackend::Json && !matches!(action, StateAction::Show) {
+        return Err("state reset and repair only support state_backend = \"json\"".into());
+    }
+
+    let store = JsonFileStore::new(LAST_IP_FILE, config.state_format);
+    let backup = match action {
+        StateAction::Show => return show_state(config, output),
+        StateAction::Reset { yes: false } => {
+            return Err("Refusing to reset state without --yes".into());
+        }
+        StateAction::Reset { yes: true } => {
+            let backup = store.reset()?;
+            info!("Reset {}", LAST_IP_FILE);
+            backup
+        }
+        StateAction::Repair => {
+            let summary = store.repair()?;
+            for name in &summary.dropped {
+                warn!("Dropped corrupt state entry: {}", name);
+            }
+            info!(
+                "Repaired {}: kept {} entries, dropped {}",
+                LAST_IP_FILE,
+                summary.kept,
+                summary.dropped.len()
+            );
+            summary.backup
+        }
+    };
+
+    if let Some(backup) = backup {
+        info!("Previous state backed up to {}", backup);
+    }
+
+    Ok(())
+}
+
+fn print_config(config: &Config, format: ConfigFormat) -> Result<(), Box<dyn Error>> {
+    let redacted = config.redacted();
+    let output = match format {
+        ConfigFormat::Toml => toml::to_string_pretty(&redacted)?,
+        ConfigFormat::Json => serde_json::to_string_pretty(&redacted)?,
+    };
+
+    println!("{}", output);
+    Ok(())
+}
+
+/// Builds a config purely from `CF_*` environment variables, for container
+/// deployments that don't mount a config file.
+fn config_from_env() -> Result<Config, Box<dyn Error>> {
+    let required = |name: &str| {
+        std::env::var(name).map_err(|_| format!("Missing required environment variable {}", name))
+    };
+
+    let api_token = required("CF_API_TOKEN")?;
+    let check_interval = duration::parse(&required("CF_CHECK_INTERVAL")?)
+        .map_err(|e| format!("CF_CHECK_INTERVAL: {}", e))?;
+
+    let mut dns_records = Vec::new();
+    for entry in required("CF_RECORDS")?.split(',').map(str::trim) {
+        if entry.is_empty() {
+            continue;
+        }
+
+        let (dns_name, proxied) = match entry.split_once(':') {
+            Some((name, proxied)) => (
+                name,
+                proxied
+                    .parse::<bool>()
+                    .map_err(|_| format!("Invalid proxied flag in CF_RECORDS entry: {}", entry))?,
+            ),
+            None => (entry, false),
+        };
+
+        dns_records.push(serde_json::json!({ "dns_name": dns_name, "proxied": proxied }));
+    }
+
+    if dns_records.is_empty() {
+        return Err("CF_RECORDS does not contain any records".into());
+    }
+
+    let config = serde_json::json!({
+        "api_token": api_token,
+        "check_interval": check_interval,
+        "dns_records": dns_records,
+    });
+
+    Ok(serde_json::from_value(config)?)
+}
+
+fn print_json(value: &impl Serialize) {
+    match serde_json::to_string_pretty(value) {
+        Ok(json) => println!("{}", json),
+        Err(e) => error!("Failed to serialize output: {:?}", e),
+    }
+}
+
+/// Why the config could not be loaded, split by whether reading it again
+/// could help.
+#[derive(Debug)]
+enum ConfigError {
+    /// config.toml could not be read, e.g. a network filesystem is not
+    /// mounted yet.
+    Unavailable(std::io::Error),
+    /// The config was read but can't be parsed or fails validation.
+    Invalid(Box<dyn Error>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Unavailable(e) => write!(f, "cannot read config.toml: {}", e),
+            ConfigError::Invalid(e) => write!(f, "invalid config: {}", e),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+fn load_config() -> Result<Config, ConfigError> {
+    let config: Config = match fs::read_to_string("config.toml") {
+        Ok(config) => toml::from_str(&config).map_err(|e| ConfigError::Invalid(e.into()))?,
+        // Without CF_API_TOKEN the environment was never meant to hold the
+        // config, so a missing file is probably a mount that isn't ready.
+        Err(e)
+            if e.kind() == std::io::ErrorKind::NotFound
+                && std::env::var_os("CF_API_TOKEN").is_some() =>
+        {
+            info!("No config.toml found, reading configuration from environment");
+            config_from_env().map_err(ConfigError::Invalid)?
+        }
+        Err(e) => return Err(ConfigError::Unavailable(e)),
+    };
+    config.validate().map_err(ConfigError::Invalid)?;
+
+    Ok(config)
+}
+
+/// Loads the config, retrying with backoff while it is unavailable when
+/// `wait` is set. An invalid config is never retried.
+async fn load_config_waiting(clock: &dyn Clock, wait: bool) -> Result<Config, ConfigError> {
+    let mut delay = Duration::from_secs(1);
+
+    loop {
+        match load_config() {
+            Err(ConfigError::Unavailable(e)) if wait => {
+                warn!(
+                    "Cannot read config.toml ({}), retrying in {}s",
+                    e,
+                    delay.as_secs()
+                );
+                clock.sleep(delay).await;
+                delay = (delay * 2).min(MAX_CONFIG_RETRY_DELAY);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Whether `name` is made of valid DNS labels.
+fn is_hostname(name: &str) -> bool {
+    !name.is_empty()
+        && name.split('.').all(|label| {
+            !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// Guesses the zone a record lives in from its last two labels.
+fn zone_name_for(dns_name: &str) -> Option<String> {
+    let domain_parts: Vec<&str> = dns_name.split('.').collect();
+    if domain_parts.len() < 2 {
+        return None;
+    }
+
+    Some(format!(
+        "{}.{}",
+        domain_parts[domain_parts.len() - 2],
+        domain_parts[domain_parts.len() - 1]
+    ))
+}
+
+type IdMaps = (HashMap<String, String>, HashMap<RecordKey, Vec<String>>);
+
+async fn resolve_ids(
+    accounts: &[Account],
+    clients: &[Client],
+    marker: Option<&str>,
+    maps: &mut IdMaps,
+) {
+    for (account, client) in accounts.iter().zip(clients) {
+        info!("Resolving records for account {}", account.label());
+
+        for record in &account.dns_records {
+            resolve_record_ids(client, account, record, marker, maps).await;
+        }
+    }
+}
+
+/// Stores the IDs in `maps` in the state backend, and fills in records that
+/// couldn't be resolved with the IDs stored for them last time, so a
+/// Cloudflare outage at startup doesn't leave them without updates.
+fn sync_stored_ids(state: &dyn StateStore, accounts: &[Account], maps: &mut IdMaps) {
+    let (zone_id_map, record_id_map) = maps;
+
+    for record in accounts.iter().flat_map(|account| &account.dns_records) {
+        let key = record.record_key();
+        match (zone_id_map.get(&record.dns_name), record_id_map.get(&key)) {
+            (Some(zone_id), Some(record_ids)) => {
+                let ids = RecordIds {
+                    zone_id: zone_id.clone(),
+                    record_ids: record_ids.clone(),
+                };
+                if let Err(e) = state.save_ids(&record.state_key(), &ids) {
+                    error!("Failed to store IDs of {}: {:?}", record.dns_name, e);
+                }
+            }
+            _ => {
+                if let Some(ids) = state.ids(&record.state_key()) {
+                    info!("Using the stored IDs of {}", record.dns_name);
+                    zone_id_map.insert(record.dns_name.clone(), ids.zone_id);
+                    record_id_map.insert(key, ids.record_ids);
+                }
+            }
+        }
+    }
+}
+
+/// Looks up the zone and record IDs of `record`, adding them to `maps`.
+/// Failures are logged and leave the record unresolved.
+async fn resolve_record_ids(
+    client: &Client,
+    account: &Account,
+    record: &DnsRecord,
+    marker: Option<&str>,
+    (zone_id_map, record_id_map): &mut IdMaps,
+) {
+    let Some(domain) = record.zone() else {
+        error!("Invalid domain name: {}", record.cloudflare_name());
+        return;
+    };
+
+    let zone_id = match record_zone_id(client, account, record, &domain).await {
+        Ok(id) => id,
+        Err(e) => {
+            error!("Failed to get zone ID for {}: {}", record.dns_name, e);
+            return;
+        }
+    };
+
+    zone_id_map.insert(record.dns_name.clone(), zone_id.clone());
+
+    let record_ids = match get_record_ids(client, account, &zone_id, record, marker).await {
+        Ok(ids) => ids,
+        Err(e) => {
+            error!("Failed to get record ID for {}: {:?}", record.dns_name, e);
+            return;
+        }
+    };
+
+    record_id_map.insert(record.record_key(), record_ids);
+}
+
+/// Record name and type, identifying one family's Cloudflare record(s).
+type RecordKey = (String, RecordType);
+
+/// Everything resolved at startup that stays fixed between update cycles.
+struct Context {
+    config: Config,
+    accounts: Vec<Account>,
+    clients: Vec<Client>,
+    /// Zone ID per record name.
+    zone_id_map: HashMap<String, String>,
+    record_id_map: HashMap<RecordKey, Vec<String>>,
+    notifier: Notifier,
+    state: Box<dyn StateStore>,
+    errors: ErrorCoalescer,
+    /// When each record was last pushed by this process, keyed like `LastIps`.
+    last_updates: Mutex<HashMap<String, SystemTime>>,
+    /// When each record was pushed within the last hour, for
+    /// `max_updates_per_hour`.
+    recent_updates: Mutex<HashMap<String, VecDeque<Instant>>>,
+    /// Consecutive updates per record that Cloudflare accepted but that a
+    /// re-read showed had no effect.
+    ineffective_updates: Mutex<HashMap<String, u32>>,
+    /// Earliest time the next update may be sent with
+    /// `max_updates_per_minute`.
+    next_update_slot: Mutex<Option<Instant>>,
+    breaker: CircuitBreaker,
+    record_errors: Mutex<RecordErrors>,
+    /// Whether the Cloudflare API is down, shared by every record.
+    outage: Arc<Mutex<OutageState>>,
+    /// Shared with the health endpoint.
+    clock: Arc<dyn Clock>,
+    rng: Box<dyn Rng>,
+}
+
+/// With `max_updates_per_minute`, waits for the next free update slot, so
+/// the updates of a big cycle are spread out instead of sent in one burst.
+async fn pace_update(ctx: &Context, record: &DnsRecord, report: &mut UpdateReport) {
+    if ctx.config.max_updates_per_minute == 0 {
+        return;
+    }
+
+    let spacing = Duration::from_secs(60) / ctx.config.max_updates_per_minute;
+    let wait = {
+        let mut next = ctx.next_update_slot.lock().unwrap();
+        let now = ctx.clock.instant();
+        let slot = next.map_or(now, |next| next.max(now));
+        *next = Some(slot + spacing);
+        slot - now
+    };
+    if wait.is_zero() {
+        return;
+    }
+
+    debug!(
+        "Pacing updates to {} per minute, waiting {} ms before updating {}",
+        ctx.config.max_updates_per_minute,
+        wait.as_millis(),
+        record.dns_name
+    );
+    report.timings.paced_ms += wait.as_millis();
+    ctx.clock.sleep(wait).await;
+}
+
+/// Waits for the next SIGHUP, or forever if there is no handler.
+async fn next_hangup(signal: &mut Option<Signal>) {
+    match signal {
+        Some(signal) => {
+            signal.recv().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Resolves once `deadline` has passed, or never without one.
+async fn runtime_deadline(clock: &dyn Clock, deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => {
+            let remaining = deadline.saturating_duration_since(clock.instant());
+            clock.sleep(remaining).await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+impl Context {
+    /// Switches to a new config, re-resolving zone and record IDs for it.
+    async fn reload(&mut self, config: Config) {
+        self.accounts = config.accounts();
+        self.clients = self.accounts.iter().map(|_| api_client(&config)).collect();
+        let mut maps = IdMaps::default();
+        resolve_ids(
+            &self.accounts,
+            &self.clients,
+            config.owned_comment_marker.as_deref(),
+            &mut maps,
+        )
+        .await;
+        sync_stored_ids(self.state.as_ref(), &self.accounts, &mut maps);
+        (self.zone_id_map, self.record_id_map) = maps;
+        self.config = config;
+    }
+
+    /// Names of the records whose IDs couldn't be resolved.
+    fn unresolved(&self) -> Vec<&str> {
+        self.accounts
+            .iter()
+            .flat_map(|account| &account.dns_records)
+            .filter(|record| !self.record_id_map.contains_key(&record.record_key()))
+            .map(|record| record.dns_name.as_str())
+            .collect()
+    }
+
+    /// Tries again to resolve records that failed earlier.
+    async fn resolve_missing(&mut self) {
+        let mut maps = (
+            std::mem::take(&mut self.zone_id_map),
+            std::mem::take(&mut self.record_id_map),
+        );
+        let marker = self.config.owned_comment_marker.as_deref();
+
+        for (account, client) in self.accounts.iter().zip(&self.clients) {
+            for record in &account.dns_records {
+                if !maps.1.contains_key(&record.record_key()) {
+                    info!("Retrying to resolve {}", record.dns_name);
+                    resolve_record_ids(client, account, record, marker, &mut maps).await;
+                }
+            }
+        }
+
+        sync_stored_ids(self.state.as_ref(), &self.accounts, &mut maps);
+        (self.zone_id_map, self.record_id_map) = maps;
+    }
+}
+
+/// Whether every Cloudflare record matching `record` already holds
+/// `content`.
+async fn remote_matches(
+    ctx: &Context,
+    client: &Client,
+    account: &Account,
+    record: &DnsRecord,
+    content: &str,
+) -> Result<bool, Box<dyn Error>> {
+    let zone_id = ctx
+        .zone_id_map
+        .get(&record.dns_name)
+        .ok_or("zone ID was not resolved")?;
+    let matches = find_records(
+        client,
+        account,
+        zone_id,
+        record.cloudflare_name(),
+        record.record_type,
+        ctx.config.owned_comment_marker.as_deref(),
+    )
+    .await?;
+
+    Ok(select_records(record, matches)?
+        .iter()
+        .all(|remote| ip::same_address(&remote.content, content)))
+}
+
+/// Re-reads `record` after a successful update and fails when Cloudflare
+/// still shows a different address, so the change is retried next cycle.
+/// Reports the record once this has happened `ineffective_update_threshold`
+/// times in a row.
+async fn verify_update(
+    ctx: &Context,
+    client: &Client,
+    account: &Account,
+    record: &DnsRecord,
+    state_key: &str,
+    content: &str,
+) -> Result<(), VerifyError> {
+    match remote_matches(ctx, client, account, record, content).await {
+        Ok(true) => {
+            ctx.ineffective_updates.lock().unwrap().remove(state_key);
+            Ok(())
+        }
+        Ok(false) => {
+            let count = {
+                let mut counts = ctx.ineffective_updates.lock().unwrap();
+                let count = counts.entry(state_key.to_string()).or_default();
+                *count += 1;
+                *count
+            };
+
+            let error = format!(
+                "update was accepted but Cloudflare still shows a different address ({} in a row)",
+                count
+            );
+            if count < ctx.config.ineffective_update_threshold {
+                warn!(
+                    "{} still does not point to {} after a successful update",
+                    record.dns_name, content
+                );
+                return Err(VerifyError::Ineffective(error));
+            }
+
+            error!(
+                "Updates of {} to {} have been accepted {} times in a row without taking effect; check for a conflicting record or an override on the Cloudflare side",
+                record.dns_name, content, count
+            );
+            if ctx.config.abort_on_ineffective_updates {
+                Err(VerifyError::Abort(error))
+            } else {
+                Err(VerifyError::Ineffective(error))
+            }
+        }
+        Err(e) => {
+            warn!("Could not verify the update of {}: {}", record.dns_name, e);
+            Ok(())
+        }
+    }
+}
+
+/// How much longer `record` must wait before it may be pushed again, if
+/// its last update was less than `min_update_interval_secs` ago.
+fn debounce_remaining(ctx: &Context, record: &DnsRecord, state_key: &str) -> Option<Duration> {
+    if record.min_update_interval_secs == 0 {
+        return None;
+    }
+
+    let last_update = ctx
+        .last_updates
+        .lock()
+        .unwrap()
+        .get(state_key)
+        .copied()
+        .or_else(|| ctx.state.updated_at(state_key))?;
+    let elapsed = ctx
+        .clock
+        .now()
+        .duration_since(last_update)
+        .unwrap_or_default();
+    Duration::from_secs(record.min_update_interval_secs)
+        .checked_sub(elapsed)
+        .filter(|wait| !wait.is_zero())
+}
+
+/// Whether `record` has been pushed `max_updates_per_hour` times within the
+/// last hour.
+fn hourly_cap_reached(ctx: &Context, record: &DnsRecord, state_key: &str) -> bool {
+    if record.max_updates_per_hour == 0 {
+        return false;
+    }
+
+    let now = ctx.clock.instant();
+    let mut recent = ctx.recent_updates.lock().unwrap();
+    let Some(pushes) = recent.get_mut(state_key) else {
+        return false;
+    };
+    while pushes
+        .front()
+        .is_some_and(|at| now.duration_since(*at) >= Duration::from_secs(60 * 60))
+    {
+        pushes.pop_front();
+    }
+    pushes.len() >= record.max_updates_per_hour as usize
+}
+
+async fn run_cycle(ctx: &Context, last_ips: &mut LastIps) -> UpdateReport {
+    let started = ctx.clock.instant();
+    let mut report = UpdateReport::default();
+    check_records(ctx, last_ips, &mut report).await;
+    note_breakers(ctx);
+
+    let timings = &mut report.timings;
+    timings.total_ms = ctx.clock.instant().duration_since(started).as_millis();
+    timings.update_ms = timings.total_ms.saturating_sub(timings.ip_fetch_ms);
+    debug!(
+        "Cycle took {} ms ({} ms fetching IPs, {} ms updating {} records)",
+        timings.total_ms, timings.ip_fetch_ms, timings.update_ms, timings.records_processed
+    );
+    if timings.paced_ms > 0 {
+        info!(
+            "Paced updates to max_updates_per_minute ({}), waiting {} ms in total",
+            ctx.config.max_updates_per_minute, timings.paced_ms
+        );
+    }
+
+    metrics::record_timings(&report.timings);
+    metrics::record_cycle(
+        report.updated.len() + report.refreshed.len(),
+        report.failed.len(),
+        report
+            .ips
+            .iter()
+            .map(|(record_type, ip)| (record_type.as_str(), ip.as_str())),
+    );
+    server::record_cycle(report.exit_code() != 3, ctx.clock.instant());
+
+    report
+}
+
+/// Sends the startup notification, with the IPs of the first cycle. It is
+/// marked failed when no record could be resolved or no IP was detected.
+async fn announce_startup(ctx: &Context, report: &UpdateReport) {
+    let records = ctx.accounts.iter().map(|a| a.dns_records.len()).sum();
+    let unresolved = ctx.unresolved().len();
+    let error = if unresolved == records {
+        Some(format!(
+            "none of the {} records could be resolved in Cloudflare",
+            records
+        ))
+    } else if report.ips.is_empty() {
+        Some(match report.failed.first() {
+            Some(failure) => format!("no IP address was detected: {}", failure.error),
+            None => "no IP address was detected".to_string(),
+        })
+    } else {
+        None
+    };
+    let ip = if report.ips.is_empty() {
+        "none detected".to_string()
+    } else {
+        report
+            .ips
+            .iter()
+            .map(|(family, ip)| format!("{} {}", family, ip))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    ctx.notifier
+        .notify_startup(&StartupEvent::new(records, unresolved, ip, error))
+        .await;
+}
+
+/// Logs the lifetime totals when the process exits cleanly.
+fn log_shutdown_summary(ctx: &Context, started: Instant) {
+    let lifetime = metrics::lifetime();
+    let uptime = Duration::from_secs(ctx.clock.instant().duration_since(started).as_secs());
+    let last_ips = if lifetime.last_ips.is_empty() {
+        "none detected".to_string()
+    } else {
+        lifetime
+            .last_ips
+            .iter()
+            .map(|(family, ip)| format!("{} {}", family, ip))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    info!(
+        "Ran for {}: {} cycles, {} updates, {} failures, last IP: {}",
+        humantime::format_duration(uptime),
+        lifetime.cycles,
+        lifetime.updates,
+        lifetime.failures,
+        last_ips
+    );
+}
+
+/// Counts a failure of `record`, logging when it opens the circuit breaker.
+fn trip_breaker(ctx: &Context, record: &DnsRecord, state_key: &str) {
+    if let Some(cooldown) = ctx.breaker.failure(state_key, ctx.clock.instant()) {
+        warn!(
+            "{} keeps failing, circuit breaker open: skipping it for {}s",
+            record.dns_name,
+            cooldown.as_secs()
+        );
+    }
+}
+
+async fn check_records(ctx: &Context, last_ips: &mut LastIps, report: &mut UpdateReport) {
+    let started = ctx.clock.instant();
+    let detected = detect_ips(
+        &ctx.config,
+        ctx.clock.as_ref(),
+        ctx.accounts.iter().flat_map(|a| &a.dns_records),
+    )
+    .await;
+    report.timings.ip_fetch_ms = ctx.clock.instant().duration_since(started).as_millis();
+
+    for (record_type, result) in &detected {
+        let error_key = format!("public_ip:{}", record_type);
+        match result {
+            Ok(ip) => {
+                ctx.errors.clear(&error_key);
+                report.ips.insert(*record_type, ip.clone());
+            }
+            Err(e) if ctx.config.treat_ip_fetch_error_as_nochange => debug!(
+                "Failed to get public {} IP, keeping the last known one: {}",
+                record_type, e
+            ),
+            Err(e) => ctx.errors.error(
+                &error_key,
+                format!("Failed to get public {} IP: {}", record_type, e),
+                ctx.clock.instant(),
+            ),
+        }
+    }
+
+    if !ctx.config.treat_ip_fetch_error_as_nochange {
+        if let Some(e) = total_detection_failure(&detected) {
+            report.ip_error = Some(e);
+            return;
+        }
+    }
+
+    let paused = read_paused();
+
+    for (account, client) in ctx.accounts.iter().zip(&ctx.clients) {
+        for record in &account.dns_records {
+            if record.paused || paused.contains(&record.dns_name) {
+                debug!("{} is paused, skipping", record.dns_name);
+                report.paused.push(record.dns_name.clone());
+                continue;
+            }
+
+            let active_hours = record
+                .active_hours
+                .as_ref()
+                .or(ctx.config.active_hours.as_ref());
+            if let Some(hours) = active_hours.filter(|hours| !hours.contains(ctx.clock.utc())) {
+                info!(
+                    "{} is outside its active hours ({}-{}), skipping update",
+                    record.dns_name, hours.start, hours.end
+                );
+                report.inactive.push(record.dns_name.clone());
+                continue;
+            }
+
+            let state_key = record.state_key();
+            if let Some(remaining) = ctx.breaker.open_for(&state_key, ctx.clock.instant()) {
+                debug!(
+                    "{} failed too often, skipping for another {}s",
+                    record.dns_name,
+                    remaining.as_secs()
+                );
+                report.circuit_open.push(record.dns_name.clone());
+                continue;
+            }
+
+            report.timings.records_processed += 1;
+            let last_ip = last_ips.get(&state_key).cloned();
+
+            let current_ip = match &record.content_command {
+                Some(command) => match record_command_content(&ctx.config, record, command).await {
+                    Ok(content) => content,
+                    Err(e) => {
+                        error!(
+                            "Failed to run content_command for {}: {}",
+                            record.dns_name, e
+                        );
+                        note_record_error(ctx, &state_key, Some(&e.to_string()));
+                        trip_breaker(ctx, record, &state_key);
+                        report.failed.push(RecordFailure::new(&record.dns_name, e));
+                        continue;
+                    }
+                },
+                None => match detected_ip_for(&detected, record) {
+                    Ok(ip) => ip.to_string(),
+                    Err(_) if ctx.config.treat_ip_fetch_error_as_nochange => {
+                        report.unchanged.push(record.dns_name.clone());
+                        continue;
+                    }
+                    Err(e) => {
+                        note_record_error(ctx, &state_key, Some(&e));
+                        report.failed.push(RecordFailure::new(&record.dns_name, e));
+                        continue;
+                    }
+                },
+            };
+
+            let content = match record.content_for(&current_ip) {
+                Ok(content) => content,
+                Err(e) => {
+                    error!("Failed to build content for {}: {:?}", record.dns_name, e);
+                    note_record_error(ctx, &state_key, Some(&e.to_string()));
+                    trip_breaker(ctx, record, &state_key);
+                    report.failed.push(RecordFailure::new(&record.dns_name, e));
+                    continue;
+                }
+            };
+
+            if let Err(e) = record.check_content(&content) {
+                error!("{}, skipping update", e);
+                note_record_error(ctx, &state_key, Some(&e));
+                ctx.notifier
+                    .notify(&UpdateEvent {
+                        record: &record.dns_name,
+                        record_type: record.record_type.as_str(),
+                        ip: &content,
+                        success: false,
+                        error: Some(&e),
+                        time: timezone::now(),
+                    })
+                    .await;
+                report.failed.push(RecordFailure::new(&record.dns_name, e));
+                continue;
+            }
+
+            if last_ip.is_none() && !ctx.config.force_on_first_run {
+                match remote_matches(ctx, client, account, record, &content).await {
+                    Ok(true) => {
+                        info!(
+                            "{} has no cached IP but already points to {}, skipping update",
+                            record.dns_name, content
+                        );
+                        last_ips.insert(state_key, content);
+                        report.unchanged.push(record.dns_name.clone());
+                        continue;
+                    }
+                    Ok(false) => {}
+                    Err(e) => warn!(
+                        "Could not check the live content of {}, updating anyway: {}",
+                        record.dns_name, e
+                    ),
+                }
+            }
+
+            let changed = !last_ip
+                .as_deref()
+                .is_some_and(|last| ip::same_address(last, &content));
+            if changed || record.always_update {
+                // Records whose zone or IDs couldn't be resolved have already
+                // been logged; skip them rather than failing the whole cycle.
+                let (Some(zone_id), Some(record_ids)) = (
+                    ctx.zone_id_map.get(&record.dns_name),
+                    ctx.record_id_map.get(&record.record_key()),
+                ) else {
+                    warn!(
+                        "{} was not resolved in Cloudflare, skipping update",
+                        record.dns_name
+                    );
+                    note_record_error(ctx, &state_key, Some("zone or record ID was not resolved"));
+                    report.failed.push(RecordFailure::new(
+                        &record.dns_name,
+                        "zone or record ID was not resolved",
+                    ));
+                    continue;
+                };
+
+                if !ctx.outage.lock().unwrap().allows(ctx.clock.instant()) {
+                    debug!(
+                        "Cloudflare API is down, not updating {} yet",
+                        record.dns_name
+                    );
+                    report.failed.push(RecordFailure::new(
+                        &record.dns_name,
+                        "Cloudflare API is down, waiting for it to recover",
+                    ));
+                    continue;
+                }
+
+                if let Some(wait) = debounce_remaining(ctx, record, &state_key) {
+                    info!(
+                        "{} {} but was updated recently, waiting {}s before pushing {}",
+                        record.dns_name,
+                        if changed {
+                            "changed"
+                        } else {
+                            "is due for a refresh"
+                        },
+                        wait.as_secs(),
+                        content
+                    );
+                    report.debounced.push(record.dns_name.clone());
+                    continue;
+                }
+
+                if hourly_cap_reached(ctx, record, &state_key) {
+                    info!(
+                        "{} was already pushed {} times in the last hour (max_updates_per_hour), holding back {}",
+                        record.dns_name, record.max_updates_per_hour, content
+                    );
+                    report.debounced.push(record.dns_name.clone());
+                    continue;
+                }
+
+                if !ctx.state.lock(&state_key) {
+                    info!("Another instance is updating {}, skipping", record.dns_name);
+                    report.unchanged.push(record.dns_name.clone());
+                    continue;
+                }
+
+                // With a shared backend another instance may already have
+                // pushed this change.
+                if changed
+                    && ctx
+                        .state
+                        .get(&state_key)
+                        .is_some_and(|stored| ip::same_address(&stored, &content))
+                {
+                    ctx.state.unlock(&state_key);
+                    info!("{} was already updated to {}", record.dns_name, content);
+                    last_ips.insert(state_key, content);
+                    report.unchanged.push(record.dns_name.clone());
+                    continue;
+                }
+
+                // Records without a cached IP were already compared above
+                // unless force_on_first_run is set.
+                let confirmed_first_run = last_ip.is_none() && !ctx.config.force_on_first_run;
+                if changed
+                    && !confirmed_first_run
+                    && ctx.config.check_strategy == CheckStrategy::CacheThenConfirm
+                {
+                    match remote_matches(ctx, client, account, record, &content).await {
+                        Ok(true) => {
+                            ctx.state.unlock(&state_key);
+                            info!(
+                                "{} already points to {} on Cloudflare, updating the cache only",
+                                record.dns_name, content
+                            );
+                            last_ips.insert(state_key, content);
+                            report.unchanged.push(record.dns_name.clone());
+                            continue;
+                        }
+                        Ok(false) => {}
+                        Err(e) => warn!(
+                            "Could not confirm the live content of {}, updating anyway: {}",
+                            record.dns_name, e
+                        ),
+                    }
+                }
+
+                if !changed {
+                    info!(
+                        "Refreshing {} with its unchanged IP {} (always_update)...",
+                        record.dns_name, content
+                    );
+                } else if last_ip.is_some() {
+                    info!(
+                        "IP has changed to {}, updating dns for {}...",
+                        content, record.dns_name
+                    );
+                } else {
+                    info!(
+                        "No cached IP for {}, pushing {} to make sure Cloudflare matches...",
+                        record.dns_name, content
+                    );
+                }
+
+                if record.effective_ttl() != record.ttl {
+                    info!(
+                        "{} is proxied, so Cloudflare manages its TTL: sending automatic instead of the configured {}s",
+                        record.dns_name, record.ttl
+                    );
+                    report.ttl_coerced.push(TtlCoercion {
+                        record: record.dns_name.clone(),
+                        configured: record.ttl,
+                        applied: record.effective_ttl(),
+                        reason: "proxied records always use automatic TTL",
+                    });
+                }
+
+                if changed {
+                    run_update_hook(
+                        &ctx.config,
+                        "pre_update_command",
+                        &ctx.config.pre_update_command,
+                        record,
+                        last_ip.as_deref(),
+                        &content,
+                        None,
+                    )
+                    .await;
+                }
+
+                let mut result = Ok(());
+                let mut transient = false;
+                for record_id in record_ids {
+                    pace_update(ctx, record, report).await;
+                    if let Err(e) = update_with_retries(
+                        ctx, client, account, &content, record, zone_id, record_id,
+                    )
+                    .await
+                    {
+                        transient |= matches!(e, UpdateError::Transient(_));
+                        result = Err(e.to_string());
+                    }
+                }
+
+                {
+                    let mut outage = ctx.outage.lock().unwrap();
+                    if transient {
+                        outage.failure(ctx.clock.instant());
+                    } else {
+                        outage.success(ctx.clock.instant());
+                    }
+                }
+
+                match &result {
+                    Ok(()) if changed => {
+                        log_update(&ctx.config, record, last_ip.as_deref(), &content)
+                    }
+                    Ok(()) => info!("🔄 Refreshed DNS record for {}", record.dns_name),
+                    Err(_) => {}
+                }
+
+                if result.is_ok() && ctx.config.verify_updates {
+                    result = verify_update(ctx, client, account, record, &state_key, &content)
+                        .await
+                        .map_err(|e| {
+                            report.abort |= matches!(e, VerifyError::Abort(_));
+                            e.to_string()
+                        });
+                }
+
+                if changed {
+                    run_update_hook(
+                        &ctx.config,
+                        "post_update_command",
+                        &ctx.config.post_update_command,
+                        record,
+                        last_ip.as_deref(),
+                        &content,
+                        Some(&result),
+                    )
+                    .await;
+                }
+
+                if result.is_ok() {
+                    last_ips.insert(state_key.clone(), content.clone());
+                    save_state(ctx.state.as_ref(), last_ips);
+                    ctx.last_updates
+                        .lock()
+                        .unwrap()
+                        .insert(state_key.clone(), ctx.clock.now());
+                    if record.max_updates_per_hour > 0 {
+                        ctx.recent_updates
+                            .lock()
+                            .unwrap()
+                            .entry(state_key.clone())
+                            .or_default()
+                            .push_back(ctx.clock.instant());
+                    }
+                }
+                ctx.state.unlock(&state_key);
+
+                // Routine refreshes only notify when they fail.
+                if changed || result.is_err() {
+                    ctx.notifier
+                        .notify(&UpdateEvent {
+                            record: &record.dns_name,
+                            record_type: record.record_type.as_str(),
+                            ip: &content,
+                            success: result.is_ok(),
+                            error: result.as_ref().err().map(String::as_str),
+                            time: timezone::now(),
+                        })
+                        .await;
+                }
+
+                let error_key = format!("update:{}", record.dns_name);
+                match result {
+                    Ok(()) => {
+                        ctx.errors.clear(&error_key);
+                        ctx.breaker.success(&state_key);
+                        note_record_error(ctx, &state_key, None);
+                        if changed {
+                            report.updated.push(record.dns_name.clone());
+                        } else {
+                            report.refreshed.push(record.dns_name.clone());
+                        }
+                    }
+                    Err(e) => {
+                        ctx.errors.error(
+                            &error_key,
+                            format!("Failed to update DNS record for {}: {}", record.dns_name, e),
+                            ctx.clock.instant(),
+                        );
+                        trip_breaker(ctx, record, &state_key);
+                        note_record_error(ctx, &state_key, Some(&e));
+                        report.failed.push(RecordFailure::new(&record.dns_name, e));
+                    }
+                }
+            } else {
+                info!(
+                    "IP has not changed for {}, skipping update",
+                    record.dns_name
+                );
+                report.unchanged.push(record.dns_name.clone());
+            }
+        }
+    }
+
+    save_state(ctx.state.as_ref(), last_ips);
+}
+
+/// Waits until the next cycle is due. After a time jump, a schedule is
+/// recomputed from the new time, while a plain interval runs a single
+/// catch-up cycle right away.
+async fn wait_for_next_cycle(ctx: &Context) {
+    loop {
+        let delay = ctx.config.next_delay(ctx.clock.as_ref(), ctx.rng.as_ref());
+        let jumped = clock::sleep_until_due(ctx.clock.as_ref(), delay).await;
+        if !jumped || ctx.config.schedule.is_none() {
+            return;
+        }
+        info!("Recomputing the schedule after the time jump");
+    }
+}
+
+#[derive(Serialize)]
+struct VersionInfo {
+    version: &'static str,
+    commit: &'static str,
+    built: &'static str,
+}
+
+fn print_version(output: OutputFormat) {
+    let info = VersionInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        commit: env!("GIT_COMMIT"),
+        built: env!("BUILD_TIMESTAMP"),
+    };
+
+    match output {
+        OutputFormat::Text => {
+            println!("{} {}", env!("CARGO_PKG_NAME"), info.version);
+            println!("commit: {}", info.commit);
+            println!("built: {}", info.built);
+        }
+        OutputFormat::Json => print_json(&info),
+    }
+}
+
+type FileLayer = Option<Box<dyn Layer<Registry> + Send + Sync>>;
+
+/// Starts copying the log to `log_file`, if one is configured.
+fn open_log_file(config: &Config, handle: &reload::Handle<FileLayer, Registry>) {
+    let Some(path) = &config.log_file else {
+        return;
+    };
+
+    let writer = match RotatingWriter::open(
+        path,
+        config.log_max_bytes,
+        config.max_rotated_files,
+        config.compress_rotated_files,
+    ) {
+        Ok(writer) => writer,
+        Err(e) => {
+            error!("Failed to open log file {}: {}", path, e);
+            return;
+        }
+    };
+
+    let layer = tracing_subscriber::fmt::layer()
+        .with_ansi(false)
+        .with_timer(LogTimer)
+        .with_writer(MaskingMakeWriter(Mutex::new(writer)))
+        .boxed();
+    if let Err(e) = handle.reload(Some(layer)) {
+        error!("Failed to enable log file {}: {}", path, e);
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+
+    // Completions need neither logging nor a config.
+    if let Some(Command::Completions { shell }) = cli.command {
+        let mut command = Cli::command();
+        let name = command.get_name().to_string();
+        clap_complete::generate(shell, &mut command, name, &mut std::io::stdout());
+        return;
+    }
+    if let Some(Command::Version) = cli.command {
+        print_version(cli.output);
+        return;
+    }
+
+    // The log file is only known once the config is loaded, so its layer
+    // starts out empty.
+    let (file_layer, log_file) = reload::Layer::<FileLayer, Registry>::new(None);
+    tracing_subscriber::registry()
+        .with(file_layer)
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_timer(LogTimer)
+                .with_writer(MaskingMakeWriter(std::io::stderr)),
+        )
+        .with(
+            EnvFilter::builder()
+                .with_default_directive(LevelFilter::INFO.into())
+                .from_env_lossy(),
+        )
+        .init();
+
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+    let mut base_config = match load_config_waiting(clock.as_ref(), cli.wait_for_config).await {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            error!("Failed to load config: {}", e);
+            std::process::exit(1);
+        }
+    };
+    mask::enable(base_config.mask_ip_in_logs);
+    if let Some(zone) = base_config
+        .timezone
+        .as_deref()
+        .and_then(|zone| timezone::parse(zone).ok())
+    {
+        timezone::set(zone);
+    }
+    open_log_file(&base_config, &log_file);
+
+    if let Some(mut interval) = cli.interval {
+        if interval < MIN_INTERVAL_OVERRIDE_SECS {
+            warn!(
+                "--interval {}s is below the minimum of {}s, using {}s",
+                interval, MIN_INTERVAL_OVERRIDE_SECS, MIN_INTERVAL_OVERRIDE_SECS
+            );
+            interval = MIN_INTERVAL_OVERRIDE_SECS;
+        }
+        info!(
+            "Checking every {}s (--interval overrides check_interval)",
+            interval
+        );
+        base_config.check_interval = interval;
+    }
+
+    if base_config.ip_provider_insecure_tls {
+        warn!("⚠️  ip_provider_insecure_tls is enabled: IP provider certificates are NOT verified, so a man-in-the-middle can choose the address pushed to your records. Use this for testing only.");
+    }
+
+    // Commands that work offline only see the cached remote records.
+    let offline = matches!(
+        cli.command,
+        Some(
+            Command::PrintConfig { .. }
+                | Command::Pause { .. }
+                | Command::Resume { .. }
+                | Command::CheckIp { .. }
+                | Command::State { .. }
+        )
+    );
+    let config = records::merged(&base_config, !offline).await;
+
+    match cli.command {
+        Some(Command::PrintConfig { format }) => {
+            if let Err(e) = print_config(&config, format) {
+                error!("Failed to print config: {:?}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::Reconcile { apply }) => {
+            std::process::exit(reconcile::run(&config, apply, cli.output).await);
+        }
+        Some(Command::Pause { record }) => {
+            match set_paused(&config, &record, true) {
+                Ok(()) => info!("Paused updates for {}", record),
+                Err(e) => {
+                    error!("Failed to pause {}: {:?}", record, e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        Some(Command::Resume { record }) => {
+            match set_paused(&config, &record, false) {
+                Ok(()) => info!("Resumed updates for {}", record),
+                Err(e) => {
+                    error!("Failed to resume {}: {:?}", record, e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        Some(Command::Cleanup { yes, force }) => {
+            std::process::exit(cleanup::run(&config, yes, force, cli.output).await);
+        }
+        Some(Command::Diagnose) => {
+            std::process::exit(diagnose::run(&config, cli.output).await);
+        }
+        Some(Command::Bench { count }) => {
+            std::process::exit(bench::run(&config, count, cli.output).await);
+        }
+        Some(Command::PreviewNotify) => {
+            std::process::exit(notify::preview(&config.notifications, cli.output));
+        }
+        Some(Command::Export { domain, out }) => {
+            std::process::exit(export::run(&config, &domain, &out, cli.output).await);
+        }
+        Some(Command::CheckIp { ipv4, ipv6 }) => {
+            let families = match (ipv4, ipv6) {
+                (true, _) => vec![RecordType::A],
+                (_, true) => vec![RecordType::Aaaa],
+                _ => vec![RecordType::A, RecordType::Aaaa],
+            };
+            std::process::exit(check_ip(&config, &families, cli.output).await);
+        }
+        Some(Command::State { action }) => {
+            if let Err(e) = run_state_action(&config, action, cli.output) {
+                error!("State command failed: {:?}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        // Handled before loading the config.
+        Some(Command::Completions { .. } | Command::Version) => return,
+        Some(Command::Update { .. }) | None => {}
+    }
+
+    // `update --record` is a single cycle limited to one record.
+    let single = match &cli.command {
+        Some(Command::Update { record }) => Some(record.as_str()),
+        _ => None,
+    };
+    let once = cli.once || single.is_some();
+
+    if config.startup_delay_secs > 0 && single.is_none() {
+        info!(
+            "Waiting {} seconds before the first check...",
+            config.startup_delay_secs
+        );
+        clock
+            .sleep(Duration::from_secs(config.startup_delay_secs))
+            .await;
+    }
+
+    if single.is_none() && (cli.preview || config.startup_preview) {
+        info!("Checking what the first cycle would change...");
+        let code = reconcile::run(&config, false, cli.output).await;
+        if code != 0 && !(cli.yes || config.assume_yes) {
+            if code == 1 {
+                info!("Review the changes above and re-run with --yes to apply them");
+            } else {
+                error!("The preview could not be completed; re-run with --yes to start anyway");
+            }
+            std::process::exit(code);
+        }
+    }
+
+    let mut accounts = config.accounts();
+    if let Some(name) = single {
+        for account in &mut accounts {
+            account.dns_records.retain(|r| r.dns_name == name);
+        }
+        accounts.retain(|a| !a.dns_records.is_empty());
+        if accounts.is_empty() {
+            error!("{} is not a configured record", name);
+            std::process::exit(3);
+        }
+    }
+    let clients: Vec<Client> = accounts.iter().map(|_| api_client(&config)).collect();
+    let state = match state_store(&config) {
+        Ok(state) => state,
+        Err(e) => {
+            error!("Failed to open state: {:?}", e);
+            std::process::exit(1);
+        }
+    };
+    let mut last_ips = state.load();
+    let record_errors = state.errors();
+
+    let mut maps = IdMaps::default();
+    let resolution = resolve_ids(
+        &accounts,
+        &clients,
+        config.owned_comment_marker.as_deref(),
+        &mut maps,
+    );
+    if config.startup_timeout_secs > 0 {
+        let timeout = Duration::from_secs(config.startup_timeout_secs);
+        if tokio::time::timeout(timeout, resolution).await.is_err() {
+            warn!(
+                "Resolving records took longer than startup_timeout_secs ({}s), stopping early",
+                config.startup_timeout_secs
+            );
+        }
+    } else {
+        resolution.await;
+    }
+    sync_stored_ids(state.as_ref(), &accounts, &mut maps);
+    let (zone_id_map, record_id_map) = maps;
+
+    let mut ctx = Context {
+        notifier: Notifier::new(&config.notifications),
+        breaker: CircuitBreaker::new(
+            config.circuit_breaker_threshold,
+            Duration::from_secs(config.circuit_breaker_cooldown_secs),
+        ),
+        config,
+        accounts,
+        clients,
+        zone_id_map,
+        record_id_map,
+        state,
+        errors: ErrorCoalescer::default(),
+        last_updates: Mutex::default(),
+        recent_updates: Mutex::default(),
+        next_update_slot: Mutex::default(),
+        ineffective_updates: Mutex::default(),
+        outage: Arc::default(),
+        record_errors: Mutex::new(record_errors),
+        clock: clock.clone(),
+        rng: Box::new(SystemRng),
+    };
+
+    let unresolved = ctx.unresolved();
+    if !unresolved.is_empty() {
+        let total = ctx
+            .accounts
+            .iter()
+            .map(|a| a.dns_records.len())
+            .sum::<usize>();
+        match ctx.config.startup_policy {
+            StartupPolicy::FailFast => {
+                error!(
+                    "{} of {} records could not be resolved: {}",
+                    unresolved.len(),
+                    total,
+                    unresolved.join(", ")
+                );
+                std::process::exit(3);
+            }
+            StartupPolicy::Resilient => warn!(
+                "{} of {} records could not be resolved, retrying them every cycle: {}",
+                unresolved.len(),
+                total,
+                unresolved.join(", ")
+            ),
+        }
+    }
+
+    let trigger = Arc::new(Notify::new());
+    if let (Some(listen_addr), Some(token), false) = (
+        ctx.config.webhook_listen_addr.clone(),
+        ctx.config.webhook_token.clone(),
+        once,
+    ) {
+        tokio::spawn(webhook::serve(
+            listen_addr,
+            token,
+            ctx.config.webhook_tls.clone(),
+            trigger.clone(),
+        ));
+    }
+
+    if !once {
+        if let Some(listen_addr) = ctx.config.metrics.listen_addr.clone() {
+            tokio::spawn(server::metrics(listen_addr, ctx.config.metrics.clone()));
+        }
+        if let Some(listen_addr) = ctx.config.health.listen_addr.clone() {
+            let stale_after = server::stale_after(
+                &ctx.config.health,
+                ctx.config.longest_cycle_gap(ctx.clock.as_ref()),
+            );
+            tokio::spawn(server::health(
+                listen_addr,
+                ctx.config.health.clone(),
+                stale_after,
+                ctx.clock.clone(),
+            ));
+        }
+    }
+
+    let events = match (&ctx.config.event_socket, once) {
+        (Some(path), false) => match EventSocket::listen(path) {
+            Ok(events) => Some(events),
+            Err(e) => {
+                error!("Failed to open event socket {}: {:?}", path, e);
+                None
+            }
+        },
+        _ => None,
+    };
+
+    // Only take over SIGHUP when there is something to reload.
+    let mut hangup = match &ctx.config.records_url {
+        Some(_) => signal(SignalKind::hangup())
+            .map_err(|e| error!("Failed to listen for SIGHUP: {:?}", e))
+            .ok(),
+        None => None,
+    };
+
+    let drain = shutdown::listen(Duration::from_secs(ctx.config.shutdown_timeout_secs));
+    let started = ctx.clock.instant();
+    let deadline = (ctx.config.max_runtime_secs > 0)
+        .then(|| started + Duration::from_secs(ctx.config.max_runtime_secs));
+
+    let mut announced = false;
+    loop {
+        let report = run_cycle(&ctx, &mut last_ips).await;
+        if let Some(events) = &events {
+            events.publish(&report);
+        }
+
+        if once {
+            match cli.output {
+                OutputFormat::Text => info!("Update finished: {}", report.summary()),
+                OutputFormat::Json => print_json(&report),
+            }
+            std::process::exit(report.exit_code());
+        }
+
+        if report.abort {
+            error!("Exiting because updates are not taking effect (abort_on_ineffective_updates)");
+            log_shutdown_summary(&ctx, started);
+            std::process::exit(4);
+        }
+
+        if !announced {
+            announced = true;
+            announce_startup(&ctx, &report).await;
+        }
+
+        if drain.requested() {
+            info!("Drain complete, exiting");
+            log_shutdown_summary(&ctx, started);
+            return;
+        }
+
+        if deadline.is_some_and(|deadline| ctx.clock.instant() >= deadline) {
+            info!("Reached max_runtime_secs, exiting");
+            log_shutdown_summary(&ctx, started);
+            return;
+        }
+
+        tokio::select! {
+            _ = drain.wait() => {
+                info!("Drain complete, exiting");
+                log_shutdown_summary(&ctx, started);
+                return;
+            }
+            _ = runtime_deadline(ctx.clock.as_ref(), deadline) => {
+                info!("Reached max_runtime_secs, exiting");
+                log_shutdown_summary(&ctx, started);
+                return;
+            }
+            _ = wait_for_next_cycle(&ctx) => {}
+            _ = trigger.notified() => {}
+            _ = next_hangup(&mut hangup) => {
+                info!("SIGHUP received, reloading records from records_url");
+                match records::reload(&base_config).await {
+                    Some(config) => ctx.reload(config).await,
+                    None => warn!("Keeping the current record list"),
+                }
+            }
+        }
+
+        if ctx.config.startup_policy == StartupPolicy::Resilient {
+            ctx.resolve_missing().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use axum::http::{Method, StatusCode};
+    use clock::fake::{FakeClock, FakeRng};
+    use mock_api::MockApi;
+
+    fn config(toml: &str) -> Config {
+        toml::from_str(toml).unwrap()
+    }
+
+    const UPDATED: &str = r#"{"success": true, "errors": []}"#;
+
+    /// A context for `config` with each record resolved to zone `zone` and
+    /// the record ID `<name>/<type>`, keeping its state in `state_path`.
+    fn context(config: Config, clock: Arc<FakeClock>, state_path: &str) -> Context {
+        let accounts = config.accounts();
+        let mut zone_id_map = HashMap::new();
+        let mut record_id_map = HashMap::new();
+        for record in accounts.iter().flat_map(|a| &a.dns_records) {
+            zone_id_map.insert(record.dns_name.clone(), "zone".to_string());
+            record_id_map.insert(
+                record.record_key(),
+                vec![format!("{}/{}", record.dns_name, record.record_type)],
+            );
+        }
+
+        Context {
+            notifier: Notifier::new(&config.notifications),
+            breaker: CircuitBreaker::new(
+                config.circuit_breaker_threshold,
+                Duration::from_secs(config.circuit_breaker_cooldown_secs),
+            ),
+            clients: accounts.iter().map(|_| Client::new()).collect(),
+            accounts,
+            config,
+            zone_id_map,
+            record_id_map,
+            state: Box::new(JsonFileStore::new(state_path, StateFormat::Compact)),
+            errors: ErrorCoalescer::default(),
+            last_updates: Mutex::default(),
+            recent_updates: Mutex::default(),
+            next_update_slot: Mutex::default(),
+            ineffective_updates: Mutex::default(),
+            outage: Arc::default(),
+            record_errors: Mutex::default(),
+            clock,
+            rng: Box::new(FakeRng(0)),
+        }
+    }
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("ddns-{}-{}", name, std::process::id()));
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn last_errors_are_kept_in_the_state_file_until_cleared() {
+        let config = config(
+            r#"
+            api_token = "token"
+
+            [[dns_records]]
+            dns_name = "home.example.com"
+            proxied = false
+            "#,
+        );
+        let state_path = temp_path("record-errors");
+        let _ = fs::remove_file(&state_path);
+        let ctx = context(config, Arc::new(FakeClock::default()), &state_path);
+        let ips = LastIps::from([("home.example.com".to_string(), "9.9.9.9".to_string())]);
+        ctx.state.save(&ips).unwrap();
+
+        note_record_error(&ctx, "home.example.com", Some("zone not found"));
+        // Saving the IPs again must not drop the error.
+        ctx.state.save(&ips).unwrap();
+
+        let stored = JsonFileStore::new(&state_path, StateFormat::Compact);
+        assert_eq!(stored.load(), ips);
+        assert_eq!(stored.errors()["home.example.com"].error, "zone not found");
+
+        note_record_error(&ctx, "home.example.com", None);
+        assert!(stored.errors().is_empty());
+        assert_eq!(
+            fs::read_to_string(&state_path).unwrap(),
+            r#"{"home.example.com":"9.9.9.9"}"#
+        );
+    }
+
+    #[test]
+    fn records_listed_twice_are_rejected() {
+        let config = config(
+            r#"
+            api_token = "token"
+
+            [[dns_records]]
+            dns_name = "home.example.com"
+            proxied = false
+
+            [[dns_records]]
+            dns_name = "home.example.com"
+            proxied = true
+            "#,
+        );
+
+        let error = config.validate().unwrap_err().to_string();
+        assert!(
+            error.contains("listed more than once") && error.contains("home.example.com (A)"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn entries_overriding_to_the_same_cloudflare_record_are_rejected() {
+        let config = config(
+            r#"
+            api_token = "token"
+
+            [[dns_records]]
+            dns_name = "home-primary"
+            name_override = "home.example.com"
+            proxied = false
+
+            [[dns_records]]
+            dns_name = "home-secondary"
+            name_override = "Home.example.com"
+            proxied = false
+            "#,
+        );
+
+        let error = config.validate().unwrap_err().to_string();
+        assert!(
+            error.contains("listed more than once") && error.contains("Home.example.com (A)"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn a_and_aaaa_of_one_name_are_separate_records() {
+        let config = config(
+            r#"
+            api_token = "token"
+
+            [[dns_records]]
+            dns_name = "home.example.com"
+            proxied = false
+
+            [[dns_records]]
+            dns_name = "home.example.com"
+            proxied = false
+            record_type = "AAAA"
+            "#,
+        );
+        assert!(config.validate().is_ok());
+
+        let keys: Vec<_> = config.accounts()[0]
+            .dns_records
+            .iter()
+            .map(DnsRecord::state_key)
+            .collect();
+        assert_eq!(keys, ["home.example.com", "home.example.com/AAAA"]);
+    }
+
+    fn remote(id: &str, content: &str) -> DnsRecordInfo {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "name": "home.example.com",
+            "type": "A",
+            "content": content,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn several_matches_need_update_all_matches() {
+        let mut config = config(
+            r#"
+            api_token = "token"
+
+            [[dns_records]]
+            dns_name = "home.example.com"
+            proxied = false
+            "#,
+        );
+        let matches = || vec![remote("one", "9.9.9.9"), remote("two", "1.1.1.1")];
+
+        let record = &config.dns_records[0];
+        let error = select_records(record, matches()).err().unwrap().to_string();
+        assert!(error.contains("Found 2 A records"), "{}", error);
+
+        config.dns_records[0].update_all_matches = true;
+        let selected = select_records(&config.dns_records[0], matches()).unwrap();
+        let ids: Vec<_> = selected.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(ids, ["one", "two"]);
+    }
+
+    #[test]
+    fn a_zero_check_interval_is_rejected() {
+        let config = config(
+            r#"
+            api_token = "token"
+            check_interval = "0s"
+            "#,
+        );
+
+        let error = config.validate().unwrap_err().to_string();
+        assert!(error.contains("check_interval"), "{}", error);
+    }
+
+    #[test]
+    fn next_delay_adds_jitter_from_the_rng() {
+        let config = config(
+            r#"
+            api_token = "token"
+            schedule = "0 * * * * *"
+            schedule_jitter_secs = 10
+            "#,
+        );
+        // 20 seconds past the minute.
+        let clock = FakeClock::at(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+
+        let delay = config.next_delay(&clock, &FakeRng(2_500));
+        assert_eq!(delay, Duration::from_millis(42_500));
+    }
+
+    #[test]
+    fn ip_file_age_is_measured_on_the_clock() {
+        let path = &temp_path("ip-file");
+        fs::write(path, "9.9.9.9\n").unwrap();
+
+        let fresh = read_ip_file(path, RecordType::A, 60, SystemTime::now());
+        assert_eq!(fresh.unwrap(), "9.9.9.9");
+
+        let later = SystemTime::now() + Duration::from_secs(120);
+        let stale = read_ip_file(path, RecordType::A, 60, later).unwrap_err();
+        assert!(stale.to_string().contains("is stale"), "{}", stale);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn a_malformed_ip_file_is_an_error() {
+        let path = &temp_path("bad-ip-file");
+        let read = |contents: &str| {
+            fs::write(path, contents).unwrap();
+            read_ip_file(path, RecordType::A, 0, SystemTime::now())
+                .unwrap_err()
+                .to_string()
+        };
+
+        assert!(read("").ends_with("is empty"));
+        assert!(read("  \n").ends_with("is empty"));
+        assert!(
+            read("<html>WAN: unknown</html>\n").ends_with("holds no address usable for A records")
+        );
+        assert!(read("9.9.9\n").ends_with("holds no address usable for A records"));
+        assert!(read("2620:fe::9\n").ends_with("holds no address usable for A records"));
+        assert!(read("192.168.1.1\n").contains("not a public address (private)"));
+
+        fs::remove_file(path).unwrap();
+        let missing = read_ip_file(path, RecordType::A, 0, SystemTime::now()).unwrap_err();
+        assert!(
+            missing.to_string().starts_with("Failed to read"),
+            "{}",
+            missing
+        );
+    }
+
+    #[tokio::test]
+    async fn transient_update_failures_are_retried_after_the_delay() {
+        let puts = Arc::new(AtomicUsize::new(0));
+        let counter = puts.clone();
+        let api = MockApi::start(move |method, _| match *method {
+            Method::GET => (
+                StatusCode::OK,
+                r#"{"success": true, "result": null}"#.to_string(),
+            ),
+            _ if counter.fetch_add(1, Ordering::SeqCst) == 0 => {
+                (StatusCode::INTERNAL_SERVER_ERROR, String::new())
+            }
+            _ => (StatusCode::OK, UPDATED.to_string()),
+        })
+        .await;
+
+        let config = config(&format!(
+            r#"
+            api_token = "token"
+            api_url = "{}"
+            update_retries = 2
+            update_retry_delay_secs = 30
+
+            [[dns_records]]
+            dns_name = "home.example.com"
+            proxied = false
+            "#,
+            api.url
+        ));
+        let clock = Arc::new(FakeClock::default());
+        let ctx = context(config, clock.clone(), &temp_path("retries"));
+        let account = &ctx.accounts[0];
+        let record = &account.dns_records[0];
+
+        let result = update_with_retries(
+            &ctx,
+            &ctx.clients[0],
+            account,
+            "9.9.9.9",
+            record,
+            "zone",
+            "id",
+        )
+        .await;
+
+        assert!(result.is_ok());
+        assert_eq!(puts.load(Ordering::SeqCst), 2);
+        assert_eq!(clock.slept(), [Duration::from_secs(30)]);
+        let paths: Vec<_> = api.requests().into_iter().map(|(line, _)| line).collect();
+        assert_eq!(
+            paths,
+            [
+                "PATCH /zones/zone/dns_records/id",
+                "PATCH /zones/zone/dns_records/id",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn each_family_is_compared_with_its_own_last_ip() {
+        let api = MockApi::start(|method, path| {
+            let body = match (method.as_str(), path) {
+                ("GET", "/v4") => "9.9.9.9",
+                ("GET", "/v6") => "2620:fe::9",
+                ("GET", _) => r#"{"success": true, "result": null}"#,
+                _ => UPDATED,
+            };
+            (StatusCode::OK, body.to_string())
+        })
+        .await;
+
+        let config = config(&format!(
+            r#"
+            api_token = "token"
+            api_url = "{url}"
+            ipv4_providers = ["{url}/v4"]
+            ipv6_providers = ["{url}/v6"]
+
+            [[dns_records]]
+            dns_name = "home.example.com"
+            proxied = false
+
+            [[dns_records.types]]
+            record_type = "A"
+
+            [[dns_records.types]]
+            record_type = "AAAA"
+            "#,
+            url = api.url
+        ));
+        let state_path = temp_path("families");
+        let ctx = context(config, Arc::new(FakeClock::default()), &state_path);
+        let mut last_ips = LastIps::from([
+            ("home.example.com".to_string(), "9.9.9.9".to_string()),
+            (
+                "home.example.com/AAAA".to_string(),
+                "2620:fe::fe".to_string(),
+            ),
+        ]);
+        let mut report = UpdateReport::default();
+
+        check_records(&ctx, &mut last_ips, &mut report).await;
+
+        assert_eq!(report.updated, ["home.example.com"]);
+        assert_eq!(report.unchanged, ["home.example.com"]);
+        assert!(report.failed.is_empty());
+        assert_eq!(last_ips["home.example.com"], "9.9.9.9");
+        assert_eq!(last_ips["home.example.com/AAAA"], "2620:fe::9");
+
+        let puts: Vec<_> = api
+            .requests()
+            .into_iter()
+            .filter(|(line, _)| line.starts_with("PATCH"))
+            .collect();
+        assert_eq!(puts.len(), 1);
+        assert_eq!(
+            puts[0].0,
+            "PATCH /zones/zone/dns_records/home.example.com/AAAA"
+        );
+        assert!(
+            puts[0].1.contains(r#""content":"2620:fe::9""#),
+            "{}",
+            puts[0].1
+        );
+
+        let _ = fs::remove_file(state_path);
+    }
+
+    #[test]
+    fn an_empty_or_null_result_is_no_records() {
+        for body in [
+            r#"{"success": true, "result": []}"#,
+            r#"{"success": true, "result": null}"#,
+            r#"{"success": true}"#,
+        ] {
+            let response: DnsRecordResponse = serde_json::from_str(body).unwrap();
+            assert!(response.result.is_empty(), "{}", body);
+        }
+    }
+
+    #[tokio::test]
+    async fn find_records_treats_a_null_result_as_not_found() {
+        let api = MockApi::start(|_, _| {
+            (
+                StatusCode::OK,
+                r#"{"success": true, "result": null}"#.to_string(),
+            )
+        })
+        .await;
+        let config = config(&format!(
+            r#"
+            api_token = "token"
+            api_url = "{}"
+
+            [[dns_records]]
+            dns_name = "home.example.com"
+            proxied = false
+            "#,
+            api.url
+        ));
+        let account = &config.accounts()[0];
+        let record = &account.dns_records[0];
+
+        let matches = find_records(
+            &Client::new(),
+            account,
+            "zone",
+            "home.example.com",
+            RecordType::A,
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert!(matches.is_empty());
+        let error = select_records(record, matches).err().unwrap().to_string();
+        assert!(error.contains("not found"), "{}", error);
+    }
+
+    /// Runs `find_records` against a zone holding `records`.
+    async fn find_in_zone(
+        records: serde_json::Value,
+        dns_name: &str,
+        record_type: RecordType,
+        marker: Option<&str>,
+    ) -> Result<Vec<DnsRecordInfo>, Box<dyn Error>> {
+        let body = serde_json::json!({"success": true, "result": records}).to_string();
+        let api = MockApi::start(move |_, _| (StatusCode::OK, body.clone())).await;
+        let config = config(&format!(
+            r#"
+            api_token = "token"
+            api_url = "{}"
+
+            [[dns_records]]
+            dns_name = "home.example.com"
+            proxied = false
+            "#,
+            api.url
+        ));
+
+        find_records(
+            &Client::new(),
+            &config.accounts()[0],
+            "zone",
+            dns_name,
+            record_type,
+            marker,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn find_records_only_returns_records_carrying_the_marker() {
+        let records = serde_json::json!([
+            {"id": "owned", "name": "home.example.com", "type": "A", "content": "9.9.9.9",
+             "comment": "router, managed-by-ddns"},
+            {"id": "manual", "name": "home.example.com", "type": "A", "content": "9.9.9.10",
+             "comment": "set by hand"},
+        ]);
+
+        let found = find_in_zone(
+            records.clone(),
+            "home.example.com",
+            RecordType::A,
+            Some("managed-by-ddns"),
+        )
+        .await
+        .unwrap();
+        let ids: Vec<_> = found.iter().map(|record| record.id.as_str()).collect();
+        assert_eq!(ids, ["owned"]);
+
+        let found = find_in_zone(records, "home.example.com", RecordType::A, None)
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn a_name_with_only_other_types_is_a_type_mismatch() {
+        let records = serde_json::json!([
+            {"id": "alias", "name": "home.example.com", "type": "CNAME",
+             "content": "router.example.net"},
+            {"id": "v6", "name": "home.example.com", "type": "AAAA", "content": "2620:fe::9"},
+            {"id": "other", "name": "www.example.com", "type": "A", "content": "9.9.9.9"},
+        ]);
+
+        let error = find_in_zone(records, "home.example.com", RecordType::A, None)
+            .await
+            .err()
+            .unwrap();
+        let mismatch = error.downcast_ref::<TypeMismatch>().unwrap();
+        assert_eq!(mismatch.found, ["AAAA", "CNAME"]);
+        assert_eq!(
+            error.to_string(),
+            "home.example.com has no A record, only AAAA, CNAME; create the A record in Cloudflare first"
+        );
+    }
+
+    #[tokio::test]
+    async fn find_records_keeps_only_the_requested_type() {
+        let records = serde_json::json!([
+            {"id": "v4", "name": "home.example.com", "type": "A", "content": "9.9.9.9"},
+            {"id": "v6", "name": "home.example.com", "type": "AAAA", "content": "2620:fe::9"},
+        ]);
+
+        for (record_type, id) in [(RecordType::A, "v4"), (RecordType::Aaaa, "v6")] {
+            let found = find_in_zone(records.clone(), "home.example.com", record_type, None)
+                .await
+                .unwrap();
+            let ids: Vec<_> = found.iter().map(|record| record.id.as_str()).collect();
+            assert_eq!(ids, [id], "{}", record_type);
+        }
+    }
+
+    /// Runs `record_zone_id` for the record described by `record`, with
+    /// `zones` visible to the token.
+    async fn zone_lookup(zones: serde_json::Value, record: &str) -> Result<String, Box<dyn Error>> {
+        let body = serde_json::json!({"success": true, "result": zones}).to_string();
+        let api = MockApi::start(move |_, _| (StatusCode::OK, body.clone())).await;
+        let config = config(&format!(
+            r#"
+            api_token = "token"
+            api_url = "{}"
+
+            [[dns_records]]
+            proxied = false
+            {}
+            "#,
+            api.url, record
+        ));
+        let account = &config.accounts()[0];
+        let record = &account.dns_records[0];
+
+        record_zone_id(&Client::new(), account, record, &record.zone().unwrap()).await
+    }
+
+    #[tokio::test]
+    async fn a_record_outside_every_zone_is_reported_as_a_typo() {
+        let zones = serde_json::json!([{"id": "example", "name": "example.com"}]);
+
+        let error = zone_lookup(zones.clone(), r#"dns_name = "home.exmaple.com""#)
+            .await
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            error,
+            "home.exmaple.com is not in any zone the token can see (example.com), check the record name for typos"
+        );
+
+        let zones = serde_json::json!([{"id": "assets", "name": "assets.example.com"}]);
+        let error = zone_lookup(
+            zones,
+            r#"dns_name = "cdn.assets.example.com"
+            zone_name = "example.com""#,
+        )
+        .await
+        .unwrap_err()
+        .to_string();
+        assert!(
+            error.contains("is in zone assets.example.com, not example.com"),
+            "{}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn a_delegated_subzone_wins_over_its_parent() {
+        let zones = serde_json::json!([
+            {"id": "parent", "name": "example.com"},
+            {"id": "subzone", "name": "assets.example.com"},
+        ]);
+
+        let inferred = zone_lookup(zones.clone(), r#"dns_name = "cdn.assets.example.com""#)
+            .await
+            .unwrap();
+        assert_eq!(inferred, "subzone");
+
+        let named = zone_lookup(
+            zones.clone(),
+            r#"dns_name = "cdn.assets.example.com"
+            zone_name = "assets.example.com""#,
+        )
+        .await
+        .unwrap();
+        assert_eq!(named, "subzone");
+
+        let parent = zone_lookup(zones, r#"dns_name = "home.example.com""#)
+            .await
+            .unwrap();
+        assert_eq!(parent, "parent");
+    }
+
+    #[tokio::test]
+    async fn account_id_scopes_the_zone_lookup() {
+        let account_id = "0123456789abcdef0123456789abcdef";
+        let api = MockApi::serve(move |request| {
+            let scoped = request.query.as_deref() == Some(&format!("account.id={}", account_id));
+            let id = if scoped { "scoped" } else { "unscoped" };
+            let body = serde_json::json!({
+                "success": true,
+                "result": [{"id": id, "name": "example.com"}],
+            });
+            (StatusCode::OK, body.to_string())
+        })
+        .await;
+        let config = config(&format!(
+            r#"
+            api_token = "token"
+            api_url = "{}"
+            account_id = "{}"
+
+            [[dns_records]]
+            dns_name = "home.example.com"
+            proxied = false
+            "#,
+            api.url, account_id
+        ));
+
+        let zone_id = get_zone_id(&Client::new(), &config.accounts()[0], "example.com")
+            .await
+            .unwrap();
+
+        assert_eq!(zone_id, "scoped");
+        assert_eq!(api.received()[0].path, "/zones");
+    }
+
+    /// Updates the record described by `record` through `api` with the
+    /// top-level `settings`.
+    async fn update_through(
+        api: &MockApi,
+        settings: &str,
+        record: &str,
+    ) -> Result<(), UpdateError> {
+        let config = config(&format!(
+            r#"
+            api_token = "token"
+            api_url = "{}"
+            {}
+
+            [[dns_records]]
+            dns_name = "home.example.com"
+            proxied = false
+            {}
+            "#,
+            api.url, settings, record
+        ));
+        let account = &config.accounts()[0];
+        let record = &account.dns_records[0];
+
+        update_dns_record(
+            &Client::new(),
+            account,
+            "9.9.9.9",
+            record,
+            "zone",
+            "id",
+            &config,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn a_conflicting_update_is_read_and_sent_again() {
+        let puts = Arc::new(AtomicUsize::new(0));
+        let counter = puts.clone();
+        let api = MockApi::serve(move |request| match request.method {
+            Method::GET => (
+                StatusCode::OK,
+                serde_json::json!({"result": {
+                    "id": "id", "name": "home.example.com", "type": "A",
+                    "content": "9.9.9.8", "modified_on": "2026-01-01T00:00:00Z",
+                }})
+                .to_string(),
+            ),
+            _ if counter.fetch_add(1, Ordering::SeqCst) == 0 => {
+                (StatusCode::PRECONDITION_FAILED, String::new())
+            }
+            _ => (StatusCode::OK, UPDATED.to_string()),
+        })
+        .await;
+
+        update_through(&api, "safe_update = true", "")
+            .await
+            .unwrap();
+
+        let lines: Vec<_> = api.requests().into_iter().map(|(line, _)| line).collect();
+        assert_eq!(
+            lines,
+            [
+                "GET /zones/zone/dns_records/id",
+                "PUT /zones/zone/dns_records/id",
+                "GET /zones/zone/dns_records/id",
+                "PUT /zones/zone/dns_records/id",
+            ]
+        );
+        let put = &api.received()[1];
+        assert_eq!(
+            put.headers["if-unmodified-since"],
+            "Thu, 01 Jan 2026 00:00:00 GMT"
+        );
+    }
+
+    #[tokio::test]
+    async fn a_record_that_keeps_changing_is_given_up_on() {
+        let api = MockApi::start(|method, _| match *method {
+            Method::GET => (StatusCode::OK, r#"{"result": null}"#.to_string()),
+            _ => (StatusCode::PRECONDITION_FAILED, String::new()),
+        })
+        .await;
+
+        let error = update_through(&api, "safe_update = true", "")
+            .await
+            .unwrap_err();
+
+        assert!(
+            matches!(&error, UpdateError::Rejected(e) if e.contains("gave up after 4 attempts")),
+            "{}",
+            error
+        );
+        assert_eq!(api.requests().len(), 8);
+    }
+
+    #[tokio::test]
+    async fn unknown_fields_survive_the_update() {
+        let api = MockApi::start(|method, _| match *method {
+            Method::GET => (
+                StatusCode::OK,
+                serde_json::json!({"result": {
+                    "id": "id", "name": "home.example.com", "type": "A",
+                    "content": "9.9.9.8", "ttl": 300,
+                    "settings": {"ipv4_only": true},
+                    "modified_on": "2026-01-01T00:00:00Z",
+                }})
+                .to_string(),
+            ),
+            _ => (StatusCode::OK, UPDATED.to_string()),
+        })
+        .await;
+
+        update_through(&api, "preserve_unknown_fields = true", "")
+            .await
+            .unwrap();
+
+        let (line, body) = api.requests().pop().unwrap();
+        assert_eq!(line, "PUT /zones/zone/dns_records/id");
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["settings"], serde_json::json!({"ipv4_only": true}));
+        assert_eq!(body["content"], "9.9.9.9");
+        assert!(body.get("modified_on").is_none(), "{}", body);
+        assert!(body.get("id").is_none(), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn tags_are_sent_with_the_update() {
+        let api = MockApi::start(|method, _| match *method {
+            Method::GET => (
+                StatusCode::OK,
+                serde_json::json!({"result": {
+                    "id": "id", "name": "home.example.com", "type": "A",
+                    "content": "9.9.9.8", "tags": ["owner:me"],
+                }})
+                .to_string(),
+            ),
+            _ => (StatusCode::OK, UPDATED.to_string()),
+        })
+        .await;
+        let sent = |api: &MockApi| -> Vec<(String, serde_json::Value)> {
+            api.requests()
+                .into_iter()
+                .map(|(line, body)| (line, serde_json::from_str(&body).unwrap_or_default()))
+                .collect()
+        };
+
+        update_through(&api, "", r#"tags = ["env:home"]"#)
+            .await
+            .unwrap();
+        let requests = sent(&api);
+        assert_eq!(requests[0].0, "GET /zones/zone/dns_records/id");
+        assert_eq!(requests[1].0, "PUT /zones/zone/dns_records/id");
+        assert_eq!(
+            requests[1].1["tags"],
+            serde_json::json!(["owner:me", "env:home"])
+        );
+
+        update_through(&api, "", "tags = [\"env:home\"]\nmanage_tags = true")
+            .await
+            .unwrap();
+        let requests = sent(&api);
+        assert_eq!(requests[2].0, "PATCH /zones/zone/dns_records/id");
+        assert_eq!(requests[2].1["tags"], serde_json::json!(["env:home"]));
+
+        update_through(&api, "", "").await.unwrap();
+        let requests = sent(&api);
+        assert_eq!(requests.len(), 4);
+        assert_eq!(requests[3].0, "PATCH /zones/zone/dns_records/id");
+        assert!(requests[3].1.get("tags").is_none(), "{}", requests[3].1);
+        assert!(requests[3].1.get("comment").is_none(), "{}", requests[3].1);
+    }
+
+    #[tokio::test]
+    async fn a_proxied_record_reports_its_ttl_as_coerced() {
+        let api = MockApi::start(|method, path| {
+            let body = match (method.as_str(), path) {
+                ("GET", "/v4") => "9.9.9.9",
+                ("GET", _) => r#"{"success": true, "result": null}"#,
+                _ => UPDATED,
+            };
+            (StatusCode::OK, body.to_string())
+        })
+        .await;
+        let config = config(&format!(
+            r#"
+            api_token = "token"
+            api_url = "{url}"
+            ipv4_providers = ["{url}/v4"]
+
+            [[dns_records]]
+            dns_name = "proxied.example.com"
+            proxied = true
+            ttl = 300
+
+            [[dns_records]]
+            dns_name = "direct.example.com"
+            proxied = false
+            ttl = 300
+            "#,
+            url = api.url
+        ));
+        let state_path = temp_path("ttl-coerced");
+        let ctx = context(config, Arc::new(FakeClock::default()), &state_path);
+        let mut report = UpdateReport::default();
+
+        check_records(&ctx, &mut LastIps::new(), &mut report).await;
+
+        assert_eq!(report.updated.len(), 2);
+        let coerced: Vec<_> = report
+            .ttl_coerced
+            .iter()
+            .map(|c| (c.record.as_str(), c.configured, c.applied))
+            .collect();
+        assert_eq!(coerced, [("proxied.example.com", 300, 1)]);
+        let report = serde_json::to_value(&report).unwrap();
+        assert_eq!(
+            report["ttl_coerced"][0]["reason"],
+            "proxied records always use automatic TTL"
+        );
+
+        let _ = fs::remove_file(state_path);
+    }
+
+    #[tokio::test]
+    async fn update_hooks_get_the_update_in_their_environment() {
+        let dump = temp_path("hook-env");
+        let config = config(&format!(
+            r#"
+            api_token = "token"
+            allow_command_hooks = true
+            post_update_command = "env | grep ^DDNS_ | sort > {}"
+
+            [[dns_records]]
+            dns_name = "home.example.com"
+            proxied = false
+            "#,
+            dump
+        ));
+        let record = &config.dns_records[0];
+
+        run_update_hook(
+            &config,
+            "post_update_command",
+            &config.post_update_command,
+            record,
+            Some("9.9.9.8"),
+            "9.9.9.9",
+            Some(&Err("Cloudflare API error: denied".to_string())),
+        )
+        .await;
+
+        let env = fs::read_to_string(&dump).unwrap();
+        assert_eq!(
+            env.lines().collect::<Vec<_>>(),
+            [
+                "DDNS_ERROR=Cloudflare API error: denied",
+                "DDNS_NEW_IP=9.9.9.9",
+                "DDNS_OLD_IP=9.9.9.8",
+                "DDNS_RECORD=home.example.com",
+                "DDNS_RECORD_TYPE=A",
+                "DDNS_RESULT=failure",
+            ]
+        );
+
+        let _ = fs::remove_file(dump);
+    }
+
+    const CONFIRM: &str = r#"check_strategy = "cache_then_confirm""#;
+
+    /// Runs one cycle with the top-level `settings`, `cached` as the last IP
+    /// and `live` as the content on Cloudflare, detecting 9.9.9.9. Returns
+    /// the report, the last IPs and the requests sent to Cloudflare.
+    async fn live_cycle(
+        settings: &str,
+        cached: Option<&str>,
+        live: &'static str,
+    ) -> (UpdateReport, LastIps, Vec<String>) {
+        let api = MockApi::start(move |method, path| {
+            let body = match (method.as_str(), path) {
+                ("GET", "/v4") => "9.9.9.9".to_string(),
+                ("GET", "/zones/zone/dns_records") => serde_json::json!({
+                    "success": true,
+                    "result": [{"id": "id", "name": "home.example.com", "type": "A",
+                                "content": live}],
+                })
+                .to_string(),
+                _ => UPDATED.to_string(),
+            };
+            (StatusCode::OK, body)
+        })
+        .await;
+        let config = config(&format!(
+            r#"
+            api_token = "token"
+            api_url = "{url}"
+            ipv4_providers = ["{url}/v4"]
+            {settings}
+
+            [[dns_records]]
+            dns_name = "home.example.com"
+            proxied = false
+            "#,
+            url = api.url,
+            settings = settings,
+        ));
+        let cached_name = cached.unwrap_or("none");
+        let state_path = temp_path(&format!("live-{}-{}-{}", settings.len(), cached_name, live));
+        let ctx = context(config, Arc::new(FakeClock::default()), &state_path);
+        let mut last_ips: LastIps = cached
+            .map(|ip| ("home.example.com".to_string(), ip.to_string()))
+            .into_iter()
+            .collect();
+        let mut report = UpdateReport::default();
+
+        check_records(&ctx, &mut last_ips, &mut report).await;
+
+        let _ = fs::remove_file(state_path);
+        let requests = api
+            .requests()
+            .into_iter()
+            .map(|(line, _)| line)
+            .filter(|line| line != "GET /v4")
+            .collect();
+        (report, last_ips, requests)
+    }
+
+    #[tokio::test]
+    async fn the_first_run_pushes_even_when_cloudflare_looks_current() {
+        let (report, last_ips, requests) = live_cycle("", None, "9.9.9.9").await;
+
+        assert_eq!(report.updated, ["home.example.com"]);
+        assert_eq!(
+            requests,
+            ["PATCH /zones/zone/dns_records/home.example.com/A"]
+        );
+        assert_eq!(last_ips["home.example.com"], "9.9.9.9");
+    }
+
+    #[tokio::test]
+    async fn without_force_on_first_run_the_first_run_checks_cloudflare() {
+        let settings = "force_on_first_run = false";
+
+        let (report, last_ips, requests) = live_cycle(settings, None, "9.9.9.9").await;
+        assert_eq!(report.unchanged, ["home.example.com"]);
+        assert_eq!(requests, ["GET /zones/zone/dns_records"]);
+        assert_eq!(last_ips["home.example.com"], "9.9.9.9");
+
+        let (report, _, requests) = live_cycle(settings, None, "9.9.9.8").await;
+        assert_eq!(report.updated, ["home.example.com"]);
+        assert_eq!(requests.len(), 2, "{:?}", requests);
+    }
+
+    #[tokio::test]
+    async fn cache_then_confirm_skips_everything_on_a_cache_hit() {
+        let (report, last_ips, requests) = live_cycle(CONFIRM, Some("9.9.9.9"), "9.9.9.8").await;
+
+        assert_eq!(report.unchanged, ["home.example.com"]);
+        assert!(requests.is_empty(), "{:?}", requests);
+        assert_eq!(last_ips["home.example.com"], "9.9.9.9");
+    }
+
+    #[tokio::test]
+    async fn cache_then_confirm_only_fixes_the_cache_when_cloudflare_is_current() {
+        let (report, last_ips, requests) = live_cycle(CONFIRM, Some("9.9.9.8"), "9.9.9.9").await;
+
+        assert_eq!(report.unchanged, ["home.example.com"]);
+        assert!(report.updated.is_empty());
+        assert_eq!(requests, ["GET /zones/zone/dns_records"]);
+        assert_eq!(last_ips["home.example.com"], "9.9.9.9");
+    }
+
+    #[tokio::test]
+    async fn cache_then_confirm_updates_when_cloudflare_differs() {
+        let (report, last_ips, requests) = live_cycle(CONFIRM, Some("9.9.9.8"), "9.9.9.8").await;
+
+        assert_eq!(report.updated, ["home.example.com"]);
+        assert_eq!(
+            requests,
+            [
+                "GET /zones/zone/dns_records",
+                "PATCH /zones/zone/dns_records/home.example.com/A",
+            ]
+        );
+        assert_eq!(last_ips["home.example.com"], "9.9.9.9");
+    }
+
+    #[tokio::test]
+    async fn an_unresolved_record_is_skipped_until_it_resolves() {
+        let api = MockApi::start(|method, path| {
+            let body = match (method.as_str(), path) {
+                ("GET", "/v4") => "9.9.9.9".to_string(),
+                ("GET", "/zones") => serde_json::json!({
+                    "success": true,
+                    "result": [{"id": "zone", "name": "example.com"}],
+                })
+                .to_string(),
+                ("GET", "/zones/zone/dns_records") => serde_json::json!({
+                    "success": true,
+                    "result": [{"id": "late", "name": "late.example.com", "type": "A",
+                                "content": "9.9.9.8"}],
+                })
+                .to_string(),
+                _ => UPDATED.to_string(),
+            };
+            (StatusCode::OK, body)
+        })
+        .await;
+        let config = config(&format!(
+            r#"
+            api_token = "token"
+            api_url = "{url}"
+            ipv4_providers = ["{url}/v4"]
+
+            [[dns_records]]
+            dns_name = "late.example.com"
+            proxied = false
+
+            [[dns_records]]
+            dns_name = "home.example.com"
+            proxied = false
+            "#,
+            url = api.url
+        ));
+        let state_path = temp_path("late-resolution");
+        let mut ctx = context(config, Arc::new(FakeClock::default()), &state_path);
+        let late = ctx.accounts[0].dns_records[0].clone();
+        ctx.zone_id_map.remove(&late.dns_name);
+        ctx.record_id_map.remove(&late.record_key());
+        let mut last_ips = LastIps::new();
+
+        let first = run_cycle(&ctx, &mut last_ips).await;
+        assert_eq!(first.updated, ["home.example.com"]);
+        let failed: Vec<_> = first.failed.iter().map(|f| f.record.as_str()).collect();
+        assert_eq!(failed, ["late.example.com"]);
+
+        ctx.resolve_missing().await;
+        let second = run_cycle(&ctx, &mut last_ips).await;
+        assert_eq!(second.updated, ["late.example.com"]);
+        assert!(second.failed.is_empty());
+        assert!(api
+            .requests()
+            .iter()
+            .any(|(line, _)| line == "PATCH /zones/zone/dns_records/late"));
+
+        let _ = fs::remove_file(state_path);
+    }
+
+    #[tokio::test]
+    async fn a_dual_stack_name_keeps_one_id_per_family() {
+        let api = MockApi::start(|method, path| {
+            let body = match (method.as_str(), path) {
+                ("GET", "/v4") => "9.9.9.9".to_string(),
+                ("GET", "/v6") => "2620:fe::9".to_string(),
+                ("GET", "/zones") => serde_json::json!({
+                    "success": true,
+                    "result": [{"id": "zone", "name": "example.com"}],
+                })
+                .to_string(),
+                ("GET", "/zones/zone/dns_records") => serde_json::json!({
+                    "success": true,
+                    "result": [
+                        {"id": "v6-id", "name": "home.example.com", "type": "AAAA",
+                         "content": "2620:fe::fe"},
+                        {"id": "v4-id", "name": "home.example.com", "type": "A",
+                         "content": "9.9.9.8"},
+                    ],
+                })
+                .to_string(),
+                _ => UPDATED.to_string(),
+            };
+            (StatusCode::OK, body)
+        })
+        .await;
+        let config = config(&format!(
+            r#"
+            api_token = "token"
+            api_url = "{url}"
+            ipv4_providers = ["{url}/v4"]
+            ipv6_providers = ["{url}/v6"]
+
+            [[dns_records]]
+            dns_name = "home.example.com"
+            proxied = false
+
+            [[dns_records.types]]
+            record_type = "A"
+
+            [[dns_records.types]]
+            record_type = "AAAA"
+            "#,
+            url = api.url
+        ));
+        let state_path = temp_path("dual-stack-ids");
+        let mut ctx = context(config, Arc::new(FakeClock::default()), &state_path);
+        let mut maps = IdMaps::default();
+
+        resolve_ids(&ctx.accounts, &ctx.clients, None, &mut maps).await;
+        let name = "home.example.com".to_string();
+        assert_eq!(maps.1[&(name.clone(), RecordType::A)], ["v4-id"]);
+        assert_eq!(maps.1[&(name, RecordType::Aaaa)], ["v6-id"]);
+
+        (ctx.zone_id_map, ctx.record_id_map) = maps;
+        let report = run_cycle(&ctx, &mut LastIps::new()).await;
+        assert_eq!(report.updated.len(), 2);
+        let mut patches: Vec<_> = api
+            .requests()
+            .into_iter()
+            .filter(|(line, _)| line.starts_with("PATCH"))
+            .map(|(line, body)| {
+                let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+                (line, body["content"].as_str().unwrap().to_string())
+            })
+            .collect();
+        patches.sort();
+        assert_eq!(
+            patches,
+            [
+                (
+                    "PATCH /zones/zone/dns_records/v4-id".to_string(),
+                    "9.9.9.9".to_string()
+                ),
+                (
+                    "PATCH /zones/zone/dns_records/v6-id".to_string(),
+                    "2620:fe::9".to_string()
+                ),
+            ]
+        );
+
+        let _ = fs::remove_file(state_path);
+    }
+
+    #[tokio::test]
+    async fn the_startup_notification_reports_a_failed_start() {
+        let api = MockApi::start(|_, _| (StatusCode::OK, String::new())).await;
+        let config = config(&format!(
+            r#"
+            api_token = "token"
+
+            [notifications]
+            on_startup = true
+
+            [notifications.webhook]
+            url = "{}/hook"
+
+            [[dns_records]]
+            dns_name = "home.example.com"
+            proxied = false
+            "#,
+            api.url
+        ));
+        let state_path = temp_path("startup-notification");
+        let mut ctx = context(config, Arc::new(FakeClock::default()), &state_path);
+        let sent = |api: &MockApi| -> serde_json::Value {
+            serde_json::from_str(&api.requests().pop().unwrap().1).unwrap()
+        };
+
+        let mut report = UpdateReport::default();
+        report.ips.insert(RecordType::A, "9.9.9.9".to_string());
+        announce_startup(&ctx, &report).await;
+        let event = sent(&api);
+        assert_eq!(event["success"], true);
+        assert!(event.get("error").is_none(), "{}", event);
+
+        let mut report = UpdateReport::default();
+        report
+            .failed
+            .push(RecordFailure::new("home.example.com", "provider timed out"));
+        announce_startup(&ctx, &report).await;
+        let event = sent(&api);
+        assert_eq!(event["success"], false);
+        assert_eq!(
+            event["error"],
+            "no IP address was detected: provider timed out"
+        );
+
+        ctx.record_id_map.clear();
+        announce_startup(&ctx, &UpdateReport::default()).await;
+        let event = sent(&api);
+        assert_eq!(event["success"], false);
+        assert_eq!(event["unresolved"], 1);
+        assert_eq!(
+            event["error"],
+            "none of the 1 records could be resolved in Cloudflare"
+        );
+
+        let _ = fs::remove_file(state_path);
+    }
+
+    #[test]
+    fn provider_bodies_may_be_json_or_plain_text() {
+        let provider = IpProvider::new("https://ip.example");
+        let ip = |body| parse_ip_body(&provider, body).unwrap();
+
+        assert_eq!(ip(r#"{"ip": "9.9.9.9"}"#), "9.9.9.9");
+        assert_eq!(ip("9.9.9.9\n"), "9.9.9.9");
+        assert_eq!(ip("  2620:fe::9 "), "2620:fe::9");
+    }
+
+    #[test]
+    fn an_html_body_is_reported_with_its_start() {
+        let provider = IpProvider::new("https://ip.example");
+        let body = "<html><head><title>Log in to the Wi-Fi</title></head></html>";
+
+        let error = parse_ip_body(&provider, body).unwrap_err().to_string();
+        assert!(error.contains("unexpected response"), "{}", error);
+        assert!(error.contains("<html><head>"), "{}", error);
+    }
+
+    #[test]
+    fn declared_formats_are_parsed_as_declared() {
+        let mut provider = IpProvider::new("https://ip.example");
+
+        provider.format = ResponseFormat::Trace;
+        let trace = "fl=123\nh=ip.example\nip=9.9.9.9\nts=1700000000\n";
+        assert_eq!(parse_ip_body(&provider, trace).unwrap(), "9.9.9.9");
+
+        provider.format = ResponseFormat::Text;
+        assert!(parse_ip_body(&provider, r#"{"ip": "9.9.9.9"}"#).is_err());
+
+        provider.format = ResponseFormat::Json;
+        provider.field = Some("address".to_string());
+        let custom = r#"{"ip": "10.0.0.1", "address": "9.9.9.9"}"#;
+        assert_eq!(parse_ip_body(&provider, custom).unwrap(), "9.9.9.9");
+
+        provider.field = Some("/data/client/ip".to_string());
+        let nested = r#"{"data": {"client": {"ip": "2620:fe::9"}}}"#;
+        assert_eq!(parse_ip_body(&provider, nested).unwrap(), "2620:fe::9");
+
+        let error = parse_ip_body(&provider, r#"{"ip": "9.9.9.9"}"#)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("no field /data/client/ip"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn provider_credentials_are_sent_as_basic_auth() {
+        let api = MockApi::serve(|request| {
+            let authorized = request
+                .headers
+                .get("authorization")
+                .is_some_and(|value| value == "Basic ZGRuczpzZWNyZXQ=");
+            if authorized {
+                (StatusCode::OK, "9.9.9.9".to_string())
+            } else {
+                (StatusCode::UNAUTHORIZED, String::new())
+            }
+        })
+        .await;
+        let provider_config = |credentials: &str| {
+            config(&format!(
+                r#"
+                api_token = "token"
+
+                [[ipv4_providers]]
+                url = "{}/v4"
+                {}
+
+                [[dns_records]]
+                dns_name = "home.example.com"
+                proxied = false
+                "#,
+                api.url, credentials
+            ))
+        };
+        let clock = FakeClock::default();
+
+        let with_auth = provider_config(
+            r#"username = "ddns"
+                password = "secret"
+                allow_insecure_auth = true"#,
+        );
+        let (ip, _) = get_public_ip(&with_auth, &clock, RecordType::A)
+            .await
+            .unwrap();
+        assert_eq!(ip, "9.9.9.9");
+
+        let without_auth = provider_config("");
+        let error = get_public_ip(&without_auth, &clock, RecordType::A)
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("401"), "{}", error);
+        assert_eq!(api.received().len(), 2);
+    }
+
+    #[test]
+    fn content_must_match_content_regex() {
+        let config = config(
+            r#"
+            api_token = "token"
+
+            [[dns_records]]
+            dns_name = "home.example.com"
+            proxied = false
+            content_regex = '^9\.9\.'
+            "#,
+        );
+        let record = &config.dns_records[0];
+
+        assert!(record.check_content("9.9.9.9").is_ok());
+        let error = record.check_content("Error: no route").unwrap_err();
+        assert!(error.contains("does not match content_regex"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn txt_content_not_matching_content_regex_is_not_pushed() {
+        let api = MockApi::start(|method, _| match *method {
+            Method::GET => (
+                StatusCode::OK,
+                r#"{"success": true, "result": null}"#.to_string(),
+            ),
+            _ => (StatusCode::OK, UPDATED.to_string()),
+        })
+        .await;
+        let config = config(&format!(
+            r#"
+            api_token = "token"
+            api_url = "{}"
+            allow_command_hooks = true
+
+            [[dns_records]]
+            dns_name = "home.example.com"
+            proxied = false
+            record_type = "TXT"
+            content_command = "echo 'Error: lookup timed out'"
+            content_regex = '^v=spf1 '
+            "#,
+            api.url
+        ));
+        let ctx = context(
+            config,
+            Arc::new(FakeClock::default()),
+            &temp_path("txt-regex"),
+        );
+        let mut last_ips = LastIps::new();
+        let mut report = UpdateReport::default();
+
+        check_records(&ctx, &mut last_ips, &mut report).await;
+
+        assert!(report.updated.is_empty());
+        assert_eq!(report.failed.len(), 1);
+        assert!(last_ips.is_empty());
+        assert!(api
+            .requests()
+            .iter()
+            .all(|(line, _)| !line.starts_with("PATCH")));
+    }
+
+    #[test]
+    fn an_invalid_content_regex_fails_to_load() {
+        let result = toml::from_str::<Config>(
+            r#"
+            api_token = "token"
+
+            [[dns_records]]
+            dns_name = "home.example.com"
+            proxied = false
+            content_regex = "9.9.9.("
+            "#,
+        );
+
+        let error = result.err().unwrap().to_string();
+        assert!(error.contains("invalid content_regex"), "{}", error);
+    }
+
+    #[test]
+    fn updates_keep_the_comment_and_add_a_missing_marker() {
+        let marker = Some("managed-by-ddns");
+
+        assert_eq!(
+            record_comment(marker, Some("home router")).as_deref(),
+            Some("home router managed-by-ddns")
+        );
+        assert_eq!(
+            record_comment(marker, Some("home router, managed-by-ddns")).as_deref(),
+            Some("home router, managed-by-ddns")
+        );
+        assert_eq!(
+            record_comment(marker, None).as_deref(),
+            Some("managed-by-ddns")
+        );
+        assert_eq!(
+            record_comment(None, Some("home router")).as_deref(),
+            Some("home router")
+        );
+        assert_eq!(record_comment(None, None), None);
+    }
+
+    #[tokio::test]
+    async fn ineffective_updates_abort_through_the_exit_code() {
+        let api = MockApi::start(|_, _| {
+            let body = serde_json::json!({
+                "success": true,
+                "result": [{
+                    "id": "id",
+                    "name": "home.example.com",
+                    "type": "A",
+                    "content": "1.1.1.1",
+                }],
+            });
+            (StatusCode::OK, body.to_string())
+        })
+        .await;
+        let config = config(&format!(
+            r#"
+            api_token = "token"
+            api_url = "{}"
+            verify_updates = true
+            ineffective_update_threshold = 2
+            abort_on_ineffective_updates = true
+
+            [[dns_records]]
+            dns_name = "home.example.com"
+            proxied = false
+            "#,
+            api.url
+        ));
+        let ctx = context(config, Arc::default(), &temp_path("abort"));
+        let account = &ctx.accounts[0];
+        let record = &account.dns_records[0];
+        let verify = || {
+            verify_update(
+                &ctx,
+                &ctx.clients[0],
+                account,
+                record,
+                "home.example.com",
+                "9.9.9.9",
+            )
+        };
+
+        assert!(matches!(verify().await, Err(VerifyError::Ineffective(_))));
+        assert!(matches!(verify().await, Err(VerifyError::Abort(_))));
+
+        let report = UpdateReport {
+            abort: true,
+            ..UpdateReport::default()
+        };
+        assert_eq!(report.exit_code(), 4);
+    }
+
+    #[test]
+    fn redacted_config_hides_urls_with_secrets() {
+        let config = config(
+            r#"
+            api_token = "token"
+            records_url = "https://config.example.com/records.toml?key=records-secret"
+
+            [notifications.webhook]
+            url = "https://hooks.example.com/services/webhook-secret"
+
+            [[ipv4_providers]]
+            url = "https://ip.example.com/?token=provider-secret"
+
+            [[ipv4_providers]]
+            url = "stun.example.com:3478"
+            source = "stun"
+            "#,
+        );
+
+        let redacted = toml::to_string(&config.redacted()).unwrap();
+        for secret in ["records-secret", "webhook-secret", "provider-secret"] {
+            assert!(!redacted.contains(secret), "{}", redacted);
+        }
+        assert!(redacted.contains("https://hooks.example.com/<redacted>"));
+        assert!(redacted.contains("stun.example.com:3478"));
+    }
+
+    #[test]
+    fn cleanup_force_is_only_accepted_with_yes() {
+        let parse = |args: &[&str]| {
+            Cli::try_parse_from(["simple_cloudflare_ddns", "cleanup"].iter().chain(args))
+                .map(|cli| cli.command)
+        };
+
+        assert!(parse(&["--force"]).is_err());
+        assert!(matches!(
+            parse(&["--yes"]),
+            Ok(Some(Command::Cleanup {
+                yes: true,
+                force: false
+            }))
+        ));
+        assert!(matches!(
+            parse(&["--yes", "--force"]),
+            Ok(Some(Command::Cleanup {
+                yes: true,
+                force: true
+            }))
+        ));
+    }
+
+    #[tokio::test]
+    async fn max_updates_per_minute_spaces_out_updates() {
+        let api = MockApi::start(|method, path| {
+            let body = match (method.as_str(), path) {
+                ("GET", "/v4") => "9.9.9.9",
+                ("GET", _) => r#"{"success": true, "result": null}"#,
+                _ => UPDATED,
+            };
+            (StatusCode::OK, body.to_string())
+        })
+        .await;
+        let config = config(&format!(
+            r#"
+            api_token = "token"
+            api_url = "{url}"
+            ipv4_providers = ["{url}/v4"]
+            max_updates_per_minute = 30
+
+            [[dns_records]]
+            dns_name = "a.example.com"
+            proxied = false
+
+            [[dns_records]]
+            dns_name = "b.example.com"
+            proxied = false
+
+            [[dns_records]]
+            dns_name = "c.example.com"
+            proxied = false
+            "#,
+            url = api.url
+        ));
+        let state_path = temp_path("pacing");
+        let clock = Arc::new(FakeClock::default());
+        let ctx = context(config, clock.clone(), &state_path);
+
+        let report = run_cycle(&ctx, &mut LastIps::new()).await;
+
+        assert_eq!(report.updated.len(), 3);
+        // The first update goes out at once, the others 2s apart.
+        assert_eq!(clock.slept(), [Duration::from_secs(2); 2]);
+        assert_eq!(report.timings.paced_ms, 4000);
+
+        let _ = fs::remove_file(state_path);
+    }
+
+    #[tokio::test]
+    async fn unsuccessful_responses_are_api_errors_not_empty_results() {
+        let api = MockApi::start(|_, _| {
+            let body = r#"{"success": false, "errors": [{"code": 10000, "message": "Authentication error"}], "result": null}"#;
+            (StatusCode::FORBIDDEN, body.to_string())
+        })
+        .await;
+        let config = config(&format!(
+            r#"
+            api_token = "token"
+            api_url = "{}"
+
+            [[dns_records]]
+            dns_name = "home.example.com"
+            proxied = false
+            "#,
+            api.url
+        ));
+        let account = &config.accounts()[0];
+
+        let error = fetch_zones(&Client::new(), account)
+            .await
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(error.contains("Authentication error"), "{}", error);
+
+        let error = find_records(
+            &Client::new(),
+            account,
+            "zone",
+            "home.example.com",
+            RecordType::A,
+            None,
+        )
+        .await
+        .err()
+        .unwrap()
+        .to_string();
+        assert!(error.contains("Authentication error"), "{}", error);
+    }
+
+    #[test]
+    fn pausing_an_unknown_record_fails() {
+        let config = config(
+            r#"
+            api_token = "token"
+
+            [[dns_records]]
+            dns_name = "home.example.com"
+            proxied = false
+            "#,
+        );
+
+        let error = set_paused(&config, "hmoe.example.com", true)
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(error.contains("not a configured record"), "{}", error);
+    }
+
+    #[test]
+    fn content_map_keys_match_any_spelling_of_the_address() {
+        let config = config(
+            r#"
+            api_token = "token"
+
+            [[dns_records]]
+            dns_name = "home.example.com"
+            proxied = false
+            record_type = "AAAA"
+            content_map = { "2620:00fe::9" = "2620:fe::fe", "*" = "2620:fe::10" }
+            "#,
+        );
+        let record = &config.accounts()[0].dns_records[0];
+
+        assert_eq!(record.content_for("2620:fe::9").unwrap(), "2620:fe::fe");
+        assert_eq!(record.content_for("2620:fe::1").unwrap(), "2620:fe::10");
+    }
+
+    #[test]
+    fn txt_records_need_a_content_command_and_no_proxy() {
+        let txt = |extra: &str| {
+            config(&format!(
+                r#"
+                api_token = "token"
+                allow_command_hooks = true
+
+                [[dns_records]]
+                dns_name = "home.example.com"
+                record_type = "TXT"
+                {}
+                "#,
+                extra
+            ))
+            .validate()
+        };
+
+        let error = txt("proxied = false").unwrap_err().to_string();
+        assert!(error.contains("needs a content_command"), "{}", error);
+
+        let error = txt("proxied = true\ncontent_command = \"echo ok\"")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("can't be proxied"), "{}", error);
+
+        assert!(txt("proxied = false\ncontent_command = \"echo ok\"").is_ok());
+    }
+
+    #[tokio::test]
+    async fn txt_records_are_pushed_with_the_command_output() {
+        let api = MockApi::start(|method, _| match *method {
+            Method::GET => (
+                StatusCode::OK,
+                r#"{"success": true, "result": null}"#.to_string(),
+            ),
+            _ => (StatusCode::OK, UPDATED.to_string()),
+        })
+        .await;
+        let config = config(&format!(
+            r#"
+            api_token = "token"
+            api_url = "{}"
+            allow_command_hooks = true
+
+            [[dns_records]]
+            dns_name = "home.example.com"
+            proxied = false
+            record_type = "TXT"
+            content_command = "echo 'v=spf1 ip4:9.9.9.9 -all'"
+            "#,
+            api.url
+        ));
+        let ctx = context(config, Arc::new(FakeClock::default()), &temp_path("txt"));
+        let mut last_ips = LastIps::new();
+        let mut report = UpdateReport::default();
+
+        check_records(&ctx, &mut last_ips, &mut report).await;
+
+        assert_eq!(report.updated, ["home.example.com"]);
+        // No IP had to be detected for a record fed by its command.
+        assert!(report.ips.is_empty());
+        assert_eq!(last_ips["home.example.com/TXT"], "v=spf1 ip4:9.9.9.9 -all");
+
+        let patch = api
+            .requests()
+            .into_iter()
+            .find(|(line, _)| line.starts_with("PATCH"))
+            .unwrap();
+        assert_eq!(
+            patch.0,
+            "PATCH /zones/zone/dns_records/home.example.com/TXT"
+        );
+        assert!(patch.1.contains(r#""type":"TXT""#), "{}", patch.1);
+        assert!(
+            patch.1.contains(r#""content":"v=spf1 ip4:9.9.9.9 -all""#),
+            "{}",
+            patch.1
+        );
+    }
+
+    #[test]
+    fn content_map_keys_for_the_same_address_are_rejected() {
+        let result: Result<Config, _> = toml::from_str(
+            r#"
+            api_token = "token"
+
+            [[dns_records]]
+            dns_name = "home.example.com"
+            proxied = false
+            content_map = { "9.9.9.9" = "1.1.1.1", "::ffff:9.9.9.9" = "8.8.8.8" }
+            "#,
+        );
+
+        let error = result.err().unwrap().to_string();
+        assert!(error.contains("more than one key for 9.9.9.9"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn always_update_refreshes_stop_at_max_updates_per_hour() {
+        let api = MockApi::start(|method, path| {
+            let body = match (method.as_str(), path) {
+                ("GET", "/v4") => "9.9.9.9",
+                ("GET", _) => r#"{"success": true, "result": null}"#,
+                _ => UPDATED,
+            };
+            (StatusCode::OK, body.to_string())
+        })
+        .await;
+        let config = config(&format!(
+            r#"
+            api_token = "token"
+            api_url = "{url}"
+            ipv4_providers = ["{url}/v4"]
+
+            [[dns_records]]
+            dns_name = "health.example.com"
+            proxied = false
+            always_update = true
+            max_updates_per_hour = 2
+            "#,
+            url = api.url
+        ));
+        let state_path = temp_path("hourly-cap");
+        let clock = Arc::new(FakeClock::default());
+        let ctx = context(config, clock.clone(), &state_path);
+        let mut last_ips =
+            LastIps::from([("health.example.com".to_string(), "9.9.9.9".to_string())]);
+
+        let mut cycles = Vec::new();
+        for _ in 0..3 {
+            let report = run_cycle(&ctx, &mut last_ips).await;
+            cycles.push((report.refreshed.len(), report.debounced.len()));
+            clock.advance(Duration::from_secs(5 * 60));
+        }
+        assert_eq!(cycles, [(1, 0), (1, 0), (0, 1)]);
+
+        // An hour after the first refresh there is room for one more.
+        clock.advance(Duration::from_secs(45 * 60));
+        let report = run_cycle(&ctx, &mut last_ips).await;
+        assert_eq!(report.refreshed, ["health.example.com"]);
 
-        tokio::time::sleep(std::time::Duration::from_secs(config.check_interval)).await;
+        let _ = fs::remove_file(state_path);
     }
 }
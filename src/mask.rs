@@ -0,0 +1,181 @@
+use std::{
+    borrow::Cow,
+    io::{self, Write},
+    net::{IpAddr, SocketAddr},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use tracing::{Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::ip;
+
+/// Set from `mask_ip_in_logs` once the config is loaded.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn enable(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Hides the host part of a public address: `203.0.113.7` becomes
+/// `203.0.x.x` and an IPv6 address is cut down to its `/32`.
+pub fn mask_ip(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            format!("{}.{}.x.x", a, b)
+        }
+        IpAddr::V6(ip) => {
+            let [a, b, ..] = ip.segments();
+            format!("{:x}:{:x}::/32", a, b)
+        }
+    }
+}
+
+/// Masks every public address in `text`. Private and otherwise local
+/// addresses say nothing about the operator and are kept.
+pub fn mask_ips(text: &str) -> Cow<'_, str> {
+    let is_token = |c: char| c.is_ascii_hexdigit() || c == '.' || c == ':';
+    let mut masked = String::new();
+    let mut rest = text;
+    let mut changed = false;
+
+    while let Some(start) = rest.find(is_token) {
+        let end = rest[start..]
+            .find(|c| !is_token(c))
+            .map_or(rest.len(), |len| start + len);
+        masked.push_str(&rest[..start]);
+
+        let token = &rest[start..end];
+        match mask_token(token) {
+            Some(replacement) => {
+                masked.push_str(&replacement);
+                changed = true;
+            }
+            None => masked.push_str(token),
+        }
+        rest = &rest[end..];
+    }
+
+    if !changed {
+        return Cow::Borrowed(text);
+    }
+    masked.push_str(rest);
+    Cow::Owned(masked)
+}
+
+/// The masked form of `token` if it is a public address, optionally with a
+/// port (`203.0.113.7:443`) or trailing punctuation (`203.0.113.7.`).
+fn mask_token(token: &str) -> Option<String> {
+    let trimmed = token.trim_end_matches(['.', ':']);
+
+    [token, trimmed].into_iter().find_map(|candidate| {
+        let suffix = &token[candidate.len()..];
+        if let Ok(ip) = candidate.parse::<IpAddr>() {
+            return maskable(ip).then(|| format!("{}{}", mask_ip(ip), suffix));
+        }
+        match candidate.parse::<SocketAddr>() {
+            Ok(SocketAddr::V4(addr)) if maskable(IpAddr::V4(*addr.ip())) => Some(format!(
+                "{}:{}{}",
+                mask_ip(IpAddr::V4(*addr.ip())),
+                addr.port(),
+                suffix
+            )),
+            _ => None,
+        }
+    })
+}
+
+/// Public IPv4 addresses and global unicast IPv6 ones (`2000::/3`), which
+/// keeps things like `::c` in Rust paths from being taken for addresses.
+fn maskable(ip: IpAddr) -> bool {
+    let global = match ip {
+        IpAddr::V4(_) => true,
+        IpAddr::V6(ip) => ip.segments()[0] & 0xe000 == 0x2000,
+    };
+    global && ip::non_public_reason(ip).is_none()
+}
+
+/// Wraps a log writer so that, with `mask_ip_in_logs`, every line below
+/// trace level has its public addresses masked. Trace output keeps them in
+/// full for debugging.
+pub struct MaskingMakeWriter<M>(pub M);
+
+impl<'a, M: MakeWriter<'a>> MakeWriter<'a> for MaskingMakeWriter<M> {
+    type Writer = MaskingWriter<M::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        MaskingWriter {
+            inner: self.0.make_writer(),
+            mask: ENABLED.load(Ordering::Relaxed),
+        }
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        MaskingWriter {
+            inner: self.0.make_writer_for(meta),
+            mask: ENABLED.load(Ordering::Relaxed) && *meta.level() != Level::TRACE,
+        }
+    }
+}
+
+pub struct MaskingWriter<W> {
+    inner: W,
+    mask: bool,
+}
+
+impl<W: Write> Write for MaskingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.mask {
+            return self.inner.write(buf);
+        }
+
+        // Each event is formatted into one buffer, so an address is never
+        // split across writes.
+        let text = String::from_utf8_lossy(buf);
+        self.inner.write_all(mask_ips(&text).as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn masks_public_ipv4_addresses() {
+        assert_eq!(
+            mask_ips("IP has changed to 9.9.9.9, updating dns"),
+            "IP has changed to 9.9.x.x, updating dns"
+        );
+        assert_eq!(
+            mask_ips("connecting to 9.9.9.9:443."),
+            "connecting to 9.9.x.x:443."
+        );
+    }
+
+    #[test]
+    fn masks_public_ipv6_addresses() {
+        assert_eq!(
+            mask_ips("pushing 2620:fe::9 to home.example.com"),
+            "pushing 2620:fe::/32 to home.example.com"
+        );
+    }
+
+    #[test]
+    fn keeps_private_and_local_addresses() {
+        let text = "gateway 192.168.1.1, resolver 127.0.0.53, ula fd00::1, link fe80::1";
+        assert_eq!(mask_ips(text), text);
+    }
+
+    #[test]
+    fn leaves_text_without_addresses_untouched() {
+        let text = "Checking records for home.example.com at 12:30 (cafe)";
+        assert!(matches!(mask_ips(text), Cow::Borrowed(_)));
+        assert!(matches!(mask_ips(""), Cow::Borrowed("")));
+    }
+}
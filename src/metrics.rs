@@ -0,0 +1,227 @@
+use std::{collections::BTreeMap, fmt::Write, sync::Mutex, time::Instant};
+
+use crate::{breaker::BreakerState, provider, CycleTimings};
+
+/// Totals over the lifetime of the process.
+#[derive(Clone, Default)]
+pub struct Lifetime {
+    pub cycles: u64,
+    pub updates: u64,
+    pub failures: u64,
+    /// Last detected IP per address family.
+    pub last_ips: BTreeMap<String, String>,
+}
+
+static LIFETIME: Mutex<Lifetime> = Mutex::new(Lifetime {
+    cycles: 0,
+    updates: 0,
+    failures: 0,
+    last_ips: BTreeMap::new(),
+});
+
+/// Timings of the last finished cycle.
+static LAST_CYCLE: Mutex<Option<CycleTimings>> = Mutex::new(None);
+
+/// Records whose circuit breaker is open or half-open after the last cycle.
+static BREAKERS: Mutex<BTreeMap<String, BreakerState>> = Mutex::new(BTreeMap::new());
+
+/// Attempts per IP provider URL and result ("success" or "failure").
+static IP_PROVIDER_REQUESTS: Mutex<BTreeMap<(String, &'static str), u64>> =
+    Mutex::new(BTreeMap::new());
+
+/// Counts one IP detection attempt against `provider`.
+pub fn record_ip_provider_request(provider: &str, success: bool) {
+    let result = if success { "success" } else { "failure" };
+    *IP_PROVIDER_REQUESTS
+        .lock()
+        .unwrap()
+        .entry((provider.to_string(), result))
+        .or_default() += 1;
+}
+
+/// Adds a finished cycle to the lifetime totals.
+pub fn record_cycle<'a>(
+    updates: usize,
+    failures: usize,
+    ips: impl IntoIterator<Item = (&'a str, &'a str)>,
+) {
+    let mut lifetime = LIFETIME.lock().unwrap();
+    lifetime.cycles += 1;
+    lifetime.updates += updates as u64;
+    lifetime.failures += failures as u64;
+    for (family, ip) in ips {
+        lifetime.last_ips.insert(family.to_string(), ip.to_string());
+    }
+}
+
+/// Keeps the timings of a finished cycle for the `ddns_last_cycle_*` gauges.
+pub fn record_timings(timings: &CycleTimings) {
+    *LAST_CYCLE.lock().unwrap() = Some(*timings);
+}
+
+/// Replaces the circuit breakers shown by `ddns_circuit_breaker_state`.
+pub fn record_breakers(breakers: BTreeMap<String, BreakerState>) {
+    *BREAKERS.lock().unwrap() = breakers;
+}
+
+pub fn lifetime() -> Lifetime {
+    LIFETIME.lock().unwrap().clone()
+}
+
+/// Renders all counters in the Prometheus text exposition format.
+pub fn render() -> String {
+    let mut out = String::new();
+    out.push_str("# HELP ddns_ip_provider_requests_total IP detection attempts per provider.\n");
+    out.push_str("# TYPE ddns_ip_provider_requests_total counter\n");
+
+    for ((provider, result), count) in IP_PROVIDER_REQUESTS.lock().unwrap().iter() {
+        let _ = writeln!(
+            out,
+            "ddns_ip_provider_requests_total{{provider=\"{}\",result=\"{}\"}} {}",
+            escape_label(provider),
+            result,
+            count
+        );
+    }
+
+    out.push_str(
+        "# HELP ddns_ip_provider_demoted IP providers tried last after a recent failure.\n",
+    );
+    out.push_str("# TYPE ddns_ip_provider_demoted gauge\n");
+    for provider in provider::demoted(Instant::now()) {
+        let _ = writeln!(
+            out,
+            "ddns_ip_provider_demoted{{provider=\"{}\"}} 1",
+            escape_label(&provider)
+        );
+    }
+
+    out.push_str(
+        "# HELP ddns_circuit_breaker_state Records skipped (open) or about to be retried (half_open) after repeated failures.\n",
+    );
+    out.push_str("# TYPE ddns_circuit_breaker_state gauge\n");
+    for (record, state) in BREAKERS.lock().unwrap().iter() {
+        let _ = writeln!(
+            out,
+            "ddns_circuit_breaker_state{{record=\"{}\",state=\"{}\"}} 1",
+            escape_label(record),
+            state.as_str()
+        );
+    }
+
+    let lifetime = lifetime();
+    for (name, help, value) in [
+        ("ddns_cycles_total", "Update cycles run.", lifetime.cycles),
+        (
+            "ddns_updates_total",
+            "Records pushed to Cloudflare.",
+            lifetime.updates,
+        ),
+        (
+            "ddns_update_failures_total",
+            "Records that failed to update.",
+            lifetime.failures,
+        ),
+    ] {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        let _ = writeln!(out, "{} {}", name, value);
+    }
+
+    if let Some(timings) = *LAST_CYCLE.lock().unwrap() {
+        for (name, help, value) in [
+            (
+                "ddns_last_cycle_ip_fetch_ms",
+                "Time the last cycle spent detecting IPs.",
+                timings.ip_fetch_ms,
+            ),
+            (
+                "ddns_last_cycle_update_ms",
+                "Time the last cycle spent updating records.",
+                timings.update_ms,
+            ),
+            (
+                "ddns_last_cycle_total_ms",
+                "Duration of the last cycle.",
+                timings.total_ms,
+            ),
+            (
+                "ddns_last_cycle_paced_ms",
+                "Time the last cycle waited for max_updates_per_minute.",
+                timings.paced_ms,
+            ),
+            (
+                "ddns_last_cycle_records_processed",
+                "Records the last cycle checked.",
+                timings.records_processed as u128,
+            ),
+        ] {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} gauge", name);
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+    }
+
+    out
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_the_last_cycle_timings_as_gauges() {
+        record_timings(&CycleTimings {
+            ip_fetch_ms: 120,
+            update_ms: 480,
+            total_ms: 600,
+            records_processed: 3,
+            paced_ms: 0,
+        });
+
+        let rendered = render();
+        for line in [
+            "# TYPE ddns_last_cycle_total_ms gauge",
+            "ddns_last_cycle_ip_fetch_ms 120",
+            "ddns_last_cycle_update_ms 480",
+            "ddns_last_cycle_total_ms 600",
+            "ddns_last_cycle_paced_ms 0",
+            "ddns_last_cycle_records_processed 3",
+        ] {
+            assert!(
+                rendered.lines().any(|l| l == line),
+                "{} in\n{}",
+                line,
+                rendered
+            );
+        }
+    }
+
+    #[test]
+    fn renders_open_and_half_open_circuit_breakers() {
+        record_breakers(BTreeMap::from([
+            ("a.example.com".to_string(), BreakerState::Open),
+            ("b.example.com/AAAA".to_string(), BreakerState::HalfOpen),
+        ]));
+
+        let rendered = render();
+        for line in [
+            "ddns_circuit_breaker_state{record=\"a.example.com\",state=\"open\"} 1",
+            "ddns_circuit_breaker_state{record=\"b.example.com/AAAA\",state=\"half_open\"} 1",
+        ] {
+            assert!(
+                rendered.lines().any(|l| l == line),
+                "{} in\n{}",
+                line,
+                rendered
+            );
+        }
+    }
+}
@@ -0,0 +1,74 @@
+//! A stand-in HTTP server for tests of code that talks to Cloudflare,
+//! notification webhooks or IP providers.
+
+use std::sync::{Arc, Mutex};
+
+use axum::http::{HeaderMap, Method, StatusCode, Uri};
+
+/// One request received by a [`MockApi`].
+#[derive(Clone)]
+pub struct MockRequest {
+    pub method: Method,
+    pub path: String,
+    pub query: Option<String>,
+    pub headers: HeaderMap,
+    pub body: String,
+}
+
+/// Serves on a local port, answering each request with the status and body
+/// `respond` returns for it, and keeps every request it received.
+pub struct MockApi {
+    pub url: String,
+    received: Arc<Mutex<Vec<MockRequest>>>,
+}
+
+impl MockApi {
+    /// Answers by method and path only.
+    pub async fn start<F>(respond: F) -> MockApi
+    where
+        F: Fn(&Method, &str) -> (StatusCode, String) + Clone + Send + Sync + 'static,
+    {
+        MockApi::serve(move |request| respond(&request.method, &request.path)).await
+    }
+
+    /// Answers with the whole request at hand, for mocks that check headers
+    /// or query parameters.
+    pub async fn serve<F>(respond: F) -> MockApi
+    where
+        F: Fn(&MockRequest) -> (StatusCode, String) + Clone + Send + Sync + 'static,
+    {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let log = received.clone();
+        let app = axum::Router::new().fallback(
+            move |method: Method, uri: Uri, headers: HeaderMap, body: String| {
+                let request = MockRequest {
+                    method,
+                    path: uri.path().to_string(),
+                    query: uri.query().map(str::to_string),
+                    headers,
+                    body,
+                };
+                let reply = respond(&request);
+                log.lock().unwrap().push(request);
+                async move { reply }
+            },
+        );
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        MockApi { url, received }
+    }
+
+    /// The requests so far as `"METHOD /path"` with their body.
+    pub fn requests(&self) -> Vec<(String, String)> {
+        self.received()
+            .into_iter()
+            .map(|r| (format!("{} {}", r.method, r.path), r.body))
+            .collect()
+    }
+
+    pub fn received(&self) -> Vec<MockRequest> {
+        self.received.lock().unwrap().clone()
+    }
+}
@@ -0,0 +1,525 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+};
+
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE},
+    Client, Method, Url,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error};
+
+use crate::{print_json, timezone, tls::ClientTlsConfig, OutputFormat, REDACTED};
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+pub struct NotificationConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub webhook: Option<WebhookConfig>,
+    /// Send one [`StartupEvent`] once the first cycle after startup ran.
+    #[serde(default)]
+    pub on_startup: bool,
+}
+
+const JSON: &str = "application/json";
+const FORM: &str = "application/x-www-form-urlencoded";
+
+/// A generic webhook that receives a description of every update.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct WebhookConfig {
+    pub url: String,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default = "default_method")]
+    pub method: String,
+    #[serde(default = "default_content_type")]
+    pub content_type: String,
+    /// Request body with `{record}`, `{record_type}`, `{ip}`, `{success}`,
+    /// `{error}` and `{time}` placeholders. Without it the event is sent as JSON or as
+    /// form fields, depending on `content_type`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body_template: Option<String>,
+    /// Like `body_template`, for the startup notification, with `{host}`,
+    /// `{records}`, `{unresolved}`, `{ip}`, `{success}`, `{error}` and
+    /// `{time}` placeholders.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub startup_body_template: Option<String>,
+    /// Client certificate for endpoints that require mTLS.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls: Option<ClientTlsConfig>,
+}
+
+fn default_method() -> String {
+    "POST".to_string()
+}
+
+fn default_content_type() -> String {
+    JSON.to_string()
+}
+
+impl WebhookConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        let method = self.method()?;
+        if ![Method::POST, Method::PUT, Method::PATCH].contains(&method) {
+            return Err(format!(
+                "Notification method must be POST, PUT or PATCH, got {}",
+                self.method
+            ));
+        }
+
+        HeaderValue::from_str(&self.content_type)
+            .map_err(|_| format!("Invalid notification content_type: {}", self.content_type))?;
+
+        if let Some(tls) = &self.tls {
+            tls.validate()?;
+        }
+
+        for (name, value) in &self.headers {
+            HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("Invalid notification header name: {}", name))?;
+            HeaderValue::from_str(value)
+                .map_err(|_| format!("Invalid value for notification header {}", name))?;
+        }
+
+        Ok(())
+    }
+
+    fn method(&self) -> Result<Method, String> {
+        Method::from_bytes(self.method.to_ascii_uppercase().as_bytes())
+            .map_err(|_| format!("Invalid notification method: {}", self.method))
+    }
+
+    fn is_json(&self) -> bool {
+        self.content_type
+            .split(';')
+            .next()
+            .is_some_and(|media_type| media_type.trim().eq_ignore_ascii_case(JSON))
+    }
+
+    fn is_form(&self) -> bool {
+        self.content_type
+            .split(';')
+            .next()
+            .is_some_and(|media_type| media_type.trim().eq_ignore_ascii_case(FORM))
+    }
+
+    /// Builds the request body for `event` in the configured content type.
+    fn body(&self, event: &UpdateEvent) -> Result<String, String> {
+        let Some(template) = &self.body_template else {
+            return self.encode(event);
+        };
+
+        Ok(template
+            .replace("{record}", &self.escape(event.record))
+            .replace("{record_type}", &self.escape(event.record_type))
+            .replace("{ip}", &self.escape(event.ip))
+            .replace("{success}", &event.success.to_string())
+            .replace("{error}", &self.escape(event.error.unwrap_or_default()))
+            .replace("{time}", &self.escape(&event.time)))
+    }
+
+    /// Builds the request body for the startup notification.
+    fn startup_body(&self, event: &StartupEvent) -> Result<String, String> {
+        let Some(template) = &self.startup_body_template else {
+            return self.encode(event);
+        };
+
+        Ok(template
+            .replace("{host}", &self.escape(&event.host))
+            .replace("{records}", &event.records.to_string())
+            .replace("{unresolved}", &event.unresolved.to_string())
+            .replace("{ip}", &self.escape(&event.ip))
+            .replace("{success}", &event.success.to_string())
+            .replace(
+                "{error}",
+                &self.escape(event.error.as_deref().unwrap_or_default()),
+            )
+            .replace("{time}", &self.escape(&event.time)))
+    }
+
+    /// Sends `event` as form fields or JSON, depending on `content_type`.
+    fn encode(&self, event: &impl Serialize) -> Result<String, String> {
+        if self.is_form() {
+            serde_urlencoded::to_string(event).map_err(|e| e.to_string())
+        } else {
+            serde_json::to_string(event).map_err(|e| e.to_string())
+        }
+    }
+
+    /// Escapes a substituted value so it can't break the surrounding JSON.
+    fn escape(&self, value: &str) -> String {
+        if self.is_json() {
+            let quoted = serde_json::to_string(value).unwrap_or_default();
+            quoted.trim_matches('"').to_string()
+        } else {
+            value.to_string()
+        }
+    }
+
+    fn header_map(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.headers {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(value),
+            ) {
+                headers.insert(name, value);
+            }
+        }
+
+        if let Ok(content_type) = HeaderValue::from_str(&self.content_type) {
+            headers.insert(CONTENT_TYPE, content_type);
+        }
+
+        headers
+    }
+}
+
+#[derive(Serialize)]
+pub struct UpdateEvent<'a> {
+    pub record: &'a str,
+    pub record_type: &'a str,
+    pub ip: &'a str,
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<&'a str>,
+    /// When the update was attempted, in the configured `timezone`.
+    pub time: String,
+}
+
+/// Sent once per start with `on_startup`, so a booting fleet can be seen
+/// coming up.
+#[derive(Serialize)]
+pub struct StartupEvent {
+    pub host: String,
+    /// Number of records managed.
+    pub records: usize,
+    /// How many of them couldn't be resolved in Cloudflare.
+    pub unresolved: usize,
+    /// Detected addresses, e.g. `A 203.0.113.7, AAAA 2001:db8::1`.
+    pub ip: String,
+    /// Whether the first cycle could resolve a record and detect an address.
+    pub success: bool,
+    /// Why it couldn't.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// When the first cycle finished, in the configured `timezone`.
+    pub time: String,
+}
+
+impl StartupEvent {
+    pub fn new(records: usize, unresolved: usize, ip: String, error: Option<String>) -> Self {
+        StartupEvent {
+            host: hostname(),
+            records,
+            unresolved,
+            ip,
+            success: error.is_none(),
+            error,
+            time: timezone::now(),
+        }
+    }
+}
+
+/// Name of this machine, or "unknown" when it can't be read.
+fn hostname() -> String {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .or_else(|_| fs::read_to_string("/etc/hostname"))
+        .map(|name| name.trim().to_string())
+        .ok()
+        .filter(|name| !name.is_empty())
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+pub struct Notifier {
+    client: Client,
+    webhook: Option<WebhookConfig>,
+    on_startup: bool,
+}
+
+impl Notifier {
+    pub fn new(config: &NotificationConfig) -> Self {
+        let client = match config.webhook.as_ref().and_then(|w| w.tls.as_ref()) {
+            Some(tls) => client_with_identity(tls).unwrap_or_else(|e| {
+                error!("Failed to load notification client certificate: {}", e);
+                Client::new()
+            }),
+            None => Client::new(),
+        };
+
+        Notifier {
+            client,
+            webhook: config.webhook.clone(),
+            on_startup: config.on_startup,
+        }
+    }
+
+    pub async fn notify(&self, event: &UpdateEvent<'_>) {
+        let Some(webhook) = &self.webhook else {
+            return;
+        };
+        self.send(webhook, event.record, webhook.body(event)).await;
+    }
+
+    /// Sends `event` if `on_startup` is enabled.
+    pub async fn notify_startup(&self, event: &StartupEvent) {
+        let Some(webhook) = self.webhook.as_ref().filter(|_| self.on_startup) else {
+            return;
+        };
+        self.send(webhook, "startup", webhook.startup_body(event))
+            .await;
+    }
+
+    /// Sends `body` to `webhook`; `subject` names the notification in logs.
+    async fn send(&self, webhook: &WebhookConfig, subject: &str, body: Result<String, String>) {
+        let (method, body) = match webhook.method().and_then(|m| Ok((m, body?))) {
+            Ok(request) => request,
+            Err(e) => {
+                error!("Failed to build notification for {}: {}", subject, e);
+                return;
+            }
+        };
+
+        let header_names: Vec<&str> = webhook.headers.keys().map(String::as_str).collect();
+        debug!(
+            "Sending {} notification for {} to {} as {} with headers {:?} (values redacted)",
+            method, subject, webhook.url, webhook.content_type, header_names
+        );
+
+        let result = self
+            .client
+            .request(method, &webhook.url)
+            .headers(webhook.header_map())
+            .body(body)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+
+        if let Err(e) = result {
+            error!("Failed to send notification for {}: {:?}", subject, e);
+        }
+    }
+}
+
+/// A notification as it would be sent, for `preview-notify`.
+#[derive(Serialize)]
+struct Preview {
+    notification: &'static str,
+    method: String,
+    /// The URL without its path and query, which often hold a token.
+    url: String,
+    headers: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    body: Option<String>,
+    /// Why the body couldn't be rendered.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Renders every notification with sample data and prints it instead of
+/// sending it. Returns 0 if all of them rendered, 1 otherwise.
+pub fn preview(config: &NotificationConfig, output: OutputFormat) -> i32 {
+    let Some(webhook) = &config.webhook else {
+        error!("No notification webhook is configured");
+        return 1;
+    };
+
+    let update = UpdateEvent {
+        record: "home.example.com",
+        record_type: "A",
+        ip: "203.0.113.7",
+        success: true,
+        error: None,
+        time: timezone::now(),
+    };
+    let failure = UpdateEvent {
+        success: false,
+        error: Some("Cloudflare API error: sample failure"),
+        time: update.time.clone(),
+        ..update
+    };
+    let startup = StartupEvent {
+        host: hostname(),
+        records: 2,
+        unresolved: 0,
+        ip: "A 203.0.113.7, AAAA 2001:db8::1".to_string(),
+        success: true,
+        error: None,
+        time: update.time.clone(),
+    };
+
+    let mut headers: BTreeMap<String, String> = webhook
+        .headers
+        .keys()
+        .map(|name| (name.clone(), REDACTED.to_string()))
+        .collect();
+    headers.insert("Content-Type".to_string(), webhook.content_type.clone());
+
+    let mut previews = vec![
+        ("update", webhook.body(&update)),
+        ("update failure", webhook.body(&failure)),
+    ];
+    if config.on_startup {
+        previews.push(("startup", webhook.startup_body(&startup)));
+    }
+
+    let previews: Vec<Preview> = previews
+        .into_iter()
+        .map(|(notification, body)| {
+            let (body, error) = match body {
+                Ok(body) => (Some(body), None),
+                Err(e) => (None, Some(e)),
+            };
+            Preview {
+                notification,
+                method: webhook.method.to_ascii_uppercase(),
+                url: redact_url(&webhook.url),
+                headers: headers.clone(),
+                body,
+                error,
+            }
+        })
+        .collect();
+
+    match output {
+        OutputFormat::Text => {
+            for preview in &previews {
+                println!("# {}", preview.notification);
+                println!("{} {}", preview.method, preview.url);
+                for (name, value) in &preview.headers {
+                    println!("{}: {}", name, value);
+                }
+                println!();
+                match (&preview.body, &preview.error) {
+                    (Some(body), _) => println!("{}", body),
+                    (None, error) => {
+                        println!("Failed to render: {}", error.as_deref().unwrap_or_default())
+                    }
+                }
+                println!();
+            }
+        }
+        OutputFormat::Json => print_json(&previews),
+    }
+
+    if previews.iter().all(|preview| preview.error.is_none()) {
+        0
+    } else {
+        1
+    }
+}
+
+/// `url` with credentials, path and query replaced, e.g.
+/// `https://hooks.example.com/<redacted>`.
+pub fn redact_url(url: &str) -> String {
+    let Ok(parsed) = Url::parse(url) else {
+        return REDACTED.to_string();
+    };
+    let origin = parsed.origin().ascii_serialization();
+    if parsed.path() == "/" && parsed.query().is_none() && parsed.username().is_empty() {
+        origin
+    } else {
+        format!("{}/{}", origin, REDACTED)
+    }
+}
+
+#[cfg(feature = "tls")]
+fn client_with_identity(tls: &ClientTlsConfig) -> Result<Client, String> {
+    Client::builder()
+        .use_rustls_tls()
+        .identity(tls.identity()?)
+        .build()
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "tls"))]
+fn client_with_identity(tls: &ClientTlsConfig) -> Result<Client, String> {
+    tls.validate().map(|_| Client::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+
+    use super::*;
+    use crate::mock_api::MockApi;
+
+    fn notifier(webhook: &str) -> Notifier {
+        let webhook: WebhookConfig = toml::from_str(webhook).unwrap();
+        webhook.validate().unwrap();
+        Notifier::new(&NotificationConfig {
+            webhook: Some(webhook),
+            on_startup: false,
+        })
+    }
+
+    fn event() -> UpdateEvent<'static> {
+        UpdateEvent {
+            record: "home.example.com",
+            record_type: "A",
+            ip: "9.9.9.9",
+            success: true,
+            error: None,
+            time: "2026-01-01T00:00:00Z".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn the_configured_method_and_content_type_are_sent() {
+        let api = MockApi::start(|_, _| (StatusCode::OK, String::new())).await;
+
+        notifier(&format!(r#"url = "{}/hook""#, api.url))
+            .notify(&event())
+            .await;
+        notifier(&format!(
+            r#"
+            url = "{}/legacy"
+            method = "put"
+            content_type = "application/x-www-form-urlencoded"
+            body_template = "name={{record}}&ip={{ip}}"
+            "#,
+            api.url
+        ))
+        .notify(&event())
+        .await;
+
+        let received = api.received();
+        let sent: Vec<(String, &str, &str)> = received
+            .iter()
+            .map(|request| {
+                (
+                    format!("{} {}", request.method, request.path),
+                    request.headers[CONTENT_TYPE].to_str().unwrap(),
+                    request.body.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(sent[0].0, "POST /hook");
+        assert_eq!(sent[0].1, JSON);
+        let body: serde_json::Value = serde_json::from_str(sent[0].2).unwrap();
+        assert_eq!(body["record"], "home.example.com");
+        assert_eq!(
+            sent[1],
+            (
+                "PUT /legacy".to_string(),
+                FORM,
+                "name=home.example.com&ip=9.9.9.9"
+            )
+        );
+    }
+
+    #[test]
+    fn unsupported_methods_are_rejected() {
+        let webhook: WebhookConfig = toml::from_str(
+            r#"
+            url = "https://hooks.example.com/"
+            method = "GET"
+            "#,
+        )
+        .unwrap();
+
+        let error = webhook.validate().unwrap_err();
+        assert!(error.contains("POST, PUT or PATCH"), "{}", error);
+    }
+}
@@ -0,0 +1,103 @@
+use std::time::{Duration, Instant};
+
+use tracing::{info, warn};
+
+/// First pause after the Cloudflare API is found to be down.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Longest pause between probes while the API stays down.
+const MAX_BACKOFF: Duration = Duration::from_secs(10 * 60);
+
+/// Whether the Cloudflare API looks down, shared by all records so that an
+/// outage pauses them together instead of each one retrying on its own.
+/// Once the backoff has passed, the next update acts as a probe: success
+/// ends the outage, another transient failure doubles the backoff.
+#[derive(Default)]
+pub struct OutageState {
+    down_since: Option<Instant>,
+    retry_at: Option<Instant>,
+    backoff: Duration,
+}
+
+impl OutageState {
+    /// Whether an update may be sent at `now`.
+    pub fn allows(&self, now: Instant) -> bool {
+        self.retry_at.is_none_or(|retry_at| now >= retry_at)
+    }
+
+    /// Counts a transient failure (network error, 5xx or 429) at `now`.
+    /// Returns whether this failure started an outage.
+    pub fn failure(&mut self, now: Instant) -> bool {
+        let started = self.down_since.is_none();
+        if started {
+            self.down_since = Some(now);
+            self.backoff = INITIAL_BACKOFF;
+            warn!(
+                "Cloudflare API looks down, pausing all updates for {}s",
+                self.backoff.as_secs()
+            );
+        } else {
+            self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+            info!(
+                "Cloudflare API is still down, probing again in {}s",
+                self.backoff.as_secs()
+            );
+        }
+        self.retry_at = Some(now + self.backoff);
+        started
+    }
+
+    /// Counts a successful request at `now`, ending any outage. Returns
+    /// whether there was one to end.
+    pub fn success(&mut self, now: Instant) -> bool {
+        self.retry_at = None;
+        let Some(since) = self.down_since.take() else {
+            return false;
+        };
+        info!(
+            "Cloudflare API is reachable again after {}s, resuming updates",
+            now.duration_since(since).as_secs()
+        );
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_full_outage_pauses_updates_until_a_probe_succeeds() {
+        let start = Instant::now();
+        let mut outage = OutageState::default();
+        assert!(outage.allows(start));
+
+        assert!(outage.failure(start));
+        assert!(!outage.allows(start + Duration::from_secs(29)));
+        assert!(outage.allows(start + INITIAL_BACKOFF));
+
+        let probe = start + INITIAL_BACKOFF;
+        assert!(!outage.failure(probe));
+        assert!(!outage.allows(probe + Duration::from_secs(59)));
+        let probe = probe + INITIAL_BACKOFF * 2;
+        assert!(outage.allows(probe));
+
+        assert!(outage.success(probe));
+        assert!(outage.allows(probe));
+        assert!(!outage.success(probe + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn backoff_stops_growing_at_the_maximum() {
+        let mut now = Instant::now();
+        let mut outage = OutageState::default();
+        for _ in 0..10 {
+            outage.failure(now);
+            now += MAX_BACKOFF;
+        }
+        outage.failure(now);
+
+        assert!(!outage.allows(now + MAX_BACKOFF - Duration::from_secs(1)));
+        assert!(outage.allows(now + MAX_BACKOFF));
+    }
+}
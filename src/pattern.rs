@@ -0,0 +1,132 @@
+/// Most names a single record pattern may expand to.
+const MAX_EXPANSION: usize = 256;
+
+/// Expands numeric ranges in a record name, so `node{01..50}.example.com`
+/// becomes `node01.example.com` through `node50.example.com`. A bound with a
+/// leading zero pads every number to the same width. Several ranges expand
+/// to every combination. Names without a `{` are returned as they are.
+pub fn expand(name: &str) -> Result<Vec<String>, String> {
+    let Some(open) = name.find('{') else {
+        if name.contains('}') {
+            return Err(format!("Unmatched '}}' in record name {:?}", name));
+        }
+        return Ok(vec![name.to_string()]);
+    };
+    let close = name[open..]
+        .find('}')
+        .map(|len| open + len)
+        .ok_or_else(|| format!("Unmatched '{{' in record name {:?}", name))?;
+
+    let numbers = range(&name[open + 1..close])
+        .map_err(|e| format!("Invalid range in record name {:?}: {}", name, e))?;
+    let (prefix, rest) = (&name[..open], &name[close + 1..]);
+    let rests = expand(rest)?;
+
+    if numbers.len().saturating_mul(rests.len()) > MAX_EXPANSION {
+        return Err(format!(
+            "Record name {:?} expands to more than {} names",
+            name, MAX_EXPANSION
+        ));
+    }
+
+    Ok(numbers
+        .iter()
+        .flat_map(|number| {
+            rests
+                .iter()
+                .map(move |rest| format!("{}{}{}", prefix, number, rest))
+        })
+        .collect())
+}
+
+/// The numbers of a `start..end` range, zero-padded when a bound is.
+fn range(range: &str) -> Result<Vec<String>, String> {
+    let (start, end) = range
+        .split_once("..")
+        .ok_or_else(|| format!("expected {{start..end}}, got {{{}}}", range))?;
+    let parse = |bound: &str| {
+        if bound.is_empty() || !bound.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(format!("{:?} is not a number", bound));
+        }
+        bound
+            .parse::<u32>()
+            .map_err(|_| format!("{} is too large", bound))
+    };
+    let (first, last) = (parse(start)?, parse(end)?);
+    if first > last {
+        return Err(format!("{} is greater than {}", first, last));
+    }
+    if (last - first) as usize >= MAX_EXPANSION {
+        return Err(format!("more than {} names", MAX_EXPANSION));
+    }
+
+    let padded = |bound: &str| bound.len() > 1 && bound.starts_with('0');
+    let width = if padded(start) || padded(end) {
+        start.len().max(end.len())
+    } else {
+        0
+    };
+
+    Ok((first..=last)
+        .map(|number| format!("{:0width$}", number, width = width))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges_expand_to_every_name() {
+        assert_eq!(
+            expand("node{1..3}.example.com").unwrap(),
+            [
+                "node1.example.com",
+                "node2.example.com",
+                "node3.example.com"
+            ]
+        );
+        assert_eq!(
+            expand("node{08..10}.example.com").unwrap(),
+            [
+                "node08.example.com",
+                "node09.example.com",
+                "node10.example.com"
+            ]
+        );
+        assert_eq!(
+            expand("r{1..2}-{0..1}").unwrap(),
+            ["r1-0", "r1-1", "r2-0", "r2-1"]
+        );
+        assert_eq!(expand("home.example.com").unwrap(), ["home.example.com"]);
+    }
+
+    #[test]
+    fn invalid_ranges_are_rejected() {
+        for (name, error) in [
+            ("node{3..1}", "3 is greater than 1"),
+            ("node{1..x}", "\"x\" is not a number"),
+            ("node{..3}", "\"\" is not a number"),
+            ("node{1-3}", "expected {start..end}, got {1-3}"),
+        ] {
+            let message = expand(name).err().unwrap();
+            assert!(message.ends_with(error), "{}: {}", name, message);
+        }
+
+        assert!(expand("node{1..3").err().unwrap().contains("Unmatched '{'"));
+        assert!(expand("node1..3}").err().unwrap().contains("Unmatched '}'"));
+    }
+
+    #[test]
+    fn expansion_is_limited_in_size() {
+        assert_eq!(expand("n{1..256}").unwrap().len(), MAX_EXPANSION);
+        assert!(expand("n{1..257}")
+            .err()
+            .unwrap()
+            .ends_with("more than 256 names"));
+        assert!(expand("n{1..20}-{1..20}")
+            .err()
+            .unwrap()
+            .ends_with("expands to more than 256 names"));
+    }
+}
@@ -0,0 +1,323 @@
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use reqwest::{
+    header::{HeaderName, HeaderValue},
+    Client, RequestBuilder, Url,
+};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use tracing::{info, warn};
+
+/// How long a provider that failed is tried only after the healthy ones.
+/// Once it has passed, the provider is probed in its usual place again.
+const DEMOTION: Duration = Duration::from_secs(5 * 60);
+
+/// Providers that failed recently, with the end of their demotion.
+static DEMOTED: Mutex<BTreeMap<String, Instant>> = Mutex::new(BTreeMap::new());
+
+/// How a provider's response carries the address.
+#[derive(Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ResponseFormat {
+    /// `{"ip": "..."}` JSON or the bare address, whichever the body is.
+    #[default]
+    Auto,
+    /// JSON with the address in `field`.
+    Json,
+    /// The bare address.
+    Text,
+    /// Cloudflare's `/cdn-cgi/trace` output, with the address on its `ip=`
+    /// line: the address Cloudflare itself sees.
+    Trace,
+}
+
+impl ResponseFormat {
+    fn is_auto(&self) -> bool {
+        *self == ResponseFormat::Auto
+    }
+}
+
+/// How a provider is asked for the address.
+#[derive(Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProviderSource {
+    /// A GET request to an IP-echo service.
+    #[default]
+    Http,
+    /// A STUN binding request, for hosts behind NAT without an HTTP echo
+    /// service; `url` is then the STUN server as `host` or `host:port`.
+    Stun,
+}
+
+impl ProviderSource {
+    pub fn is_http(&self) -> bool {
+        *self == ProviderSource::Http
+    }
+}
+
+/// An endpoint answering with the caller's public address. In the config it
+/// is either a bare URL or a table adding credentials and headers for a
+/// private IP-echo service.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct IpProvider {
+    pub url: String,
+    #[serde(default, skip_serializing_if = "ProviderSource::is_http")]
+    pub source: ProviderSource,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    /// Allow sending credentials over plain HTTP.
+    #[serde(default)]
+    pub allow_insecure_auth: bool,
+    #[serde(default, skip_serializing_if = "ResponseFormat::is_auto")]
+    pub format: ResponseFormat,
+    /// Providers with a lower priority are tried first; ties keep the order
+    /// of the list.
+    #[serde(default)]
+    pub priority: i32,
+    /// Field holding the address with `format = "json"`: a top-level key,
+    /// or a JSON pointer such as `/data/ip`. Defaults to `ip`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+}
+
+impl IpProvider {
+    pub fn new(url: &str) -> Self {
+        IpProvider {
+            url: url.to_string(),
+            source: ProviderSource::Http,
+            username: None,
+            password: None,
+            headers: BTreeMap::new(),
+            allow_insecure_auth: false,
+            format: ResponseFormat::Auto,
+            priority: 0,
+            field: None,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.source == ProviderSource::Stun {
+            return self.validate_stun();
+        }
+
+        let url = Url::parse(&self.url)
+            .map_err(|e| format!("Invalid IP provider URL {:?}: {}", self.url, e))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(format!(
+                "IP provider URL {:?} must use http or https",
+                self.url
+            ));
+        }
+
+        if self.password.is_some() && self.username.is_none() {
+            return Err(format!(
+                "IP provider {} has a password but no username",
+                self.url
+            ));
+        }
+
+        if self.username.is_some() && url.scheme() != "https" && !self.allow_insecure_auth {
+            return Err(format!(
+                "IP provider {} would receive credentials over plain HTTP; use https or set allow_insecure_auth = true",
+                self.url
+            ));
+        }
+
+        if self.field.is_some() && self.format != ResponseFormat::Json {
+            return Err(format!(
+                "IP provider {} sets field without format = \"json\"",
+                self.url
+            ));
+        }
+
+        for (name, value) in &self.headers {
+            HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("Invalid header name for {}: {:?}", self.url, name))?;
+            HeaderValue::from_str(value)
+                .map_err(|_| format!("Invalid value for header {} of {}", name, self.url))?;
+        }
+
+        Ok(())
+    }
+
+    /// STUN providers name a server rather than a URL and take none of the
+    /// HTTP options.
+    fn validate_stun(&self) -> Result<(), String> {
+        if self.url.is_empty() || self.url.contains('/') {
+            return Err(format!(
+                "STUN provider {:?} must be a server as host or host:port",
+                self.url
+            ));
+        }
+
+        if self.username.is_some()
+            || self.password.is_some()
+            || !self.headers.is_empty()
+            || !self.format.is_auto()
+            || self.field.is_some()
+        {
+            return Err(format!(
+                "STUN provider {} can't set username, password, headers, format or field",
+                self.url
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Extracts the address from a JSON response according to `field`.
+    pub fn json_ip(&self, body: &str) -> Result<String, Box<dyn Error>> {
+        let value: Value = serde_json::from_str(body)
+            .map_err(|e| format!("IP provider returned invalid JSON: {}", e))?;
+
+        let field = self.field.as_deref().unwrap_or("ip");
+        let found = if field.starts_with('/') {
+            value.pointer(field)
+        } else {
+            value.get(field)
+        };
+
+        match found {
+            Some(Value::String(ip)) => Ok(ip.clone()),
+            Some(other) => {
+                Err(format!("IP provider field {} is not a string: {}", field, other).into())
+            }
+            None => Err(format!("IP provider response has no field {}", field).into()),
+        }
+    }
+
+    /// A GET request to this provider with its credentials and headers.
+    pub fn request(&self, client: &Client) -> RequestBuilder {
+        let mut request = client.get(&self.url);
+
+        if let Some(username) = &self.username {
+            request = request.basic_auth(username, self.password.as_ref());
+        }
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+
+        request
+    }
+}
+
+/// Deserializes a provider list whose entries are bare URLs or tables.
+pub fn deserialize_list<'de, D>(deserializer: D) -> Result<Vec<IpProvider>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Entry {
+        Url(String),
+        Full(IpProvider),
+    }
+
+    let entries = Vec::<Entry>::deserialize(deserializer)?;
+    Ok(entries
+        .into_iter()
+        .map(|entry| match entry {
+            Entry::Url(url) => IpProvider::new(&url),
+            Entry::Full(provider) => provider,
+        })
+        .collect())
+}
+
+/// Orders `providers` by priority, moving those demoted after a recent
+/// failure behind the healthy ones.
+pub fn ordered(mut providers: Vec<IpProvider>, now: Instant) -> Vec<IpProvider> {
+    let demoted = DEMOTED.lock().unwrap();
+    providers.sort_by_key(|provider| {
+        let is_demoted = demoted.get(&provider.url).is_some_and(|until| now < *until);
+        (is_demoted, provider.priority)
+    });
+    providers
+}
+
+/// Demotes `url` after a failure, or restores it after a success.
+pub fn record_health(url: &str, success: bool, now: Instant) {
+    let mut demoted = DEMOTED.lock().unwrap();
+    if success {
+        if demoted.remove(url).is_some() {
+            info!("IP provider {} answered again, restoring its priority", url);
+        }
+    } else if demoted.insert(url.to_string(), now + DEMOTION).is_none() {
+        warn!(
+            "Demoting IP provider {} for {}s after a failure",
+            url,
+            DEMOTION.as_secs()
+        );
+    }
+}
+
+/// Providers currently demoted.
+pub fn demoted(now: Instant) -> Vec<String> {
+    DEMOTED
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, until)| now < **until)
+        .map(|(url, _)| url.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clock::{fake::FakeClock, Clock};
+
+    use super::*;
+
+    fn urls(providers: &[IpProvider]) -> Vec<&str> {
+        providers
+            .iter()
+            .map(|provider| provider.url.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn a_failed_provider_is_demoted_then_restored() {
+        let clock = FakeClock::default();
+        let first = "https://first.demotion.test/";
+        let second = "https://second.demotion.test/";
+        let providers = vec![IpProvider::new(first), IpProvider::new(second)];
+
+        record_health(first, false, clock.instant());
+        assert_eq!(
+            urls(&ordered(providers.clone(), clock.instant())),
+            [second, first]
+        );
+        assert!(demoted(clock.instant()).contains(&first.to_string()));
+
+        clock.advance(DEMOTION);
+        assert_eq!(
+            urls(&ordered(providers.clone(), clock.instant())),
+            [first, second]
+        );
+        assert!(!demoted(clock.instant()).contains(&first.to_string()));
+
+        record_health(first, false, clock.instant());
+        record_health(first, true, clock.instant());
+        assert_eq!(urls(&ordered(providers, clock.instant())), [first, second]);
+    }
+
+    #[test]
+    fn priority_decides_the_order_among_healthy_providers() {
+        let clock = FakeClock::default();
+        let low = "https://low.priority.test/";
+        let high = "https://high.priority.test/";
+        let mut preferred = IpProvider::new(high);
+        preferred.priority = -1;
+
+        let providers = vec![IpProvider::new(low), preferred];
+        assert_eq!(urls(&ordered(providers, clock.instant())), [high, low]);
+    }
+}
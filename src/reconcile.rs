@@ -0,0 +1,340 @@
+use std::collections::BTreeMap;
+
+use reqwest::Client;
+use serde::Serialize;
+use tracing::error;
+
+use crate::{
+    api_client, clock::SystemClock, detect_ips, detected_ip_for, find_records, ip, log_update,
+    print_json, read_paused, record_command_content, record_zone_id, save_state, select_records,
+    state_store, total_detection_failure, update_dns_record, Account, Config, DnsRecord,
+    OutputFormat, RecordType,
+};
+
+#[derive(Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum RecordStatus {
+    InSync,
+    Drifted { remote: String, fixed: bool },
+    Missing,
+    Error { error: String },
+}
+
+#[derive(Serialize)]
+struct RecordResult {
+    record: String,
+    record_type: String,
+    content: String,
+    #[serde(flatten)]
+    status: RecordStatus,
+}
+
+#[derive(Serialize, Default)]
+struct ReconcileReport {
+    ips: BTreeMap<RecordType, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ip_error: Option<String>,
+    records: Vec<RecordResult>,
+}
+
+impl ReconcileReport {
+    fn exit_code(&self) -> i32 {
+        let errors = self.ip_error.is_some()
+            || self
+                .records
+                .iter()
+                .any(|r| matches!(r.status, RecordStatus::Error { .. }));
+        let drift = self.records.iter().any(|r| {
+            matches!(
+                r.status,
+                RecordStatus::Drifted { .. } | RecordStatus::Missing
+            )
+        });
+
+        if errors {
+            3
+        } else if drift {
+            1
+        } else {
+            0
+        }
+    }
+
+    fn print_text(&self) {
+        if let Some(e) = &self.ip_error {
+            println!("Could not detect public IP: {}", e);
+            return;
+        }
+
+        for (record_type, ip) in &self.ips {
+            println!("Detected public IP for {} records: {}", record_type, ip);
+        }
+
+        for result in &self.records {
+            let line = match &result.status {
+                RecordStatus::InSync => format!("in sync  {}", result.content),
+                RecordStatus::Drifted { remote, fixed } => {
+                    let action = if *fixed { ", updated" } else { "" };
+                    format!("drifted  {} -> {}{}", remote, result.content, action)
+                }
+                RecordStatus::Missing => "missing".to_string(),
+                RecordStatus::Error { error } => format!("error    {}", error),
+            };
+
+            println!("{} ({}): {}", result.record, result.record_type, line);
+        }
+    }
+}
+
+/// Compares every configured record with the content it should have and
+/// prints the result. Returns the process exit code: 0 when all records are
+/// in sync, 1 when drift was found and 3 when the check itself could not be
+/// completed.
+pub async fn run(config: &Config, apply: bool, output: OutputFormat) -> i32 {
+    let report = reconcile(config, apply).await;
+
+    match output {
+        OutputFormat::Text => report.print_text(),
+        OutputFormat::Json => print_json(&report),
+    }
+
+    report.exit_code()
+}
+
+async fn reconcile(config: &Config, apply: bool) -> ReconcileReport {
+    let mut report = ReconcileReport::default();
+
+    let accounts = config.accounts();
+    let detected = detect_ips(
+        config,
+        &SystemClock,
+        accounts.iter().flat_map(|a| &a.dns_records),
+    )
+    .await;
+    for (record_type, result) in &detected {
+        match result {
+            Ok(ip) => {
+                report.ips.insert(*record_type, ip.clone());
+            }
+            Err(e) => error!("Failed to get public {} IP: {}", record_type, e),
+        }
+    }
+
+    if let Some(e) = total_detection_failure(&detected) {
+        report.ip_error = Some(e);
+        return report;
+    }
+
+    // The state only needs to be kept in step with records fixed by --apply,
+    // so failing to open it shouldn't stop the report.
+    let state = state_store(config)
+        .map_err(|e| error!("Failed to open state: {:?}", e))
+        .ok();
+    let mut last_ips = state.as_ref().map(|s| s.load()).unwrap_or_default();
+    let paused = read_paused();
+
+    for account in &accounts {
+        let client = api_client(config);
+
+        for record in &account.dns_records {
+            // Paused records are still reported but never changed.
+            let apply = apply && !record.paused && !paused.contains(&record.dns_name);
+            let current_ip = match &record.content_command {
+                Some(command) => record_command_content(config, record, command).await,
+                None => detected_ip_for(&detected, record)
+                    .map(str::to_string)
+                    .map_err(Into::into),
+            };
+            let result = match current_ip {
+                Ok(ip) => check_record(&client, config, account, record, &ip, apply).await,
+                Err(e) => Err(e),
+            };
+            let (content, status) = match result {
+                Ok(result) => result,
+                Err(e) => (
+                    String::new(),
+                    RecordStatus::Error {
+                        error: e.to_string(),
+                    },
+                ),
+            };
+
+            if let RecordStatus::Drifted { fixed: true, .. } = status {
+                last_ips.insert(record.state_key(), content.clone());
+            }
+
+            report.records.push(RecordResult {
+                record: record.dns_name.clone(),
+                record_type: record.record_type.to_string(),
+                content,
+                status,
+            });
+        }
+    }
+
+    if let (true, Some(state)) = (apply, &state) {
+        save_state(state.as_ref(), &last_ips);
+    }
+
+    report
+}
+
+async fn check_record(
+    client: &Client,
+    config: &Config,
+    account: &Account,
+    record: &DnsRecord,
+    current_ip: &str,
+    apply: bool,
+) -> Result<(String, RecordStatus), Box<dyn std::error::Error>> {
+    let marker = config.owned_comment_marker.as_deref();
+    if !record.record_type.accepts_content(current_ip) {
+        return Err(format!(
+            "detected IP is not valid for a {} record",
+            record.record_type
+        )
+        .into());
+    }
+
+    let content = record.content_for(current_ip)?;
+    record.check_content(&content)?;
+    let domain = record.zone().ok_or("invalid domain name")?;
+    let zone_id = record_zone_id(client, account, record, &domain).await?;
+
+    let matches = find_records(
+        client,
+        account,
+        &zone_id,
+        record.cloudflare_name(),
+        record.record_type,
+        marker,
+    )
+    .await?;
+    if matches.is_empty() {
+        return Ok((content, RecordStatus::Missing));
+    }
+
+    let drifted: Vec<_> = select_records(record, matches)?
+        .into_iter()
+        .filter(|remote| !ip::same_address(&remote.content, &content))
+        .collect();
+
+    let Some(first) = drifted.first() else {
+        return Ok((content, RecordStatus::InSync));
+    };
+    let remote = first.content.clone();
+
+    if apply {
+        // Keep fixing the other matches, but report the first failure: a
+        // record that --apply could not fix is an error, not just drift.
+        let mut failure = None;
+        for info in &drifted {
+            match update_dns_record(
+                client, account, &content, record, &zone_id, &info.id, config,
+            )
+            .await
+            {
+                Ok(()) => log_update(config, record, Some(&info.content), &content),
+                Err(e) => {
+                    error!("Failed to update DNS record for {}: {}", record.dns_name, e);
+                    failure.get_or_insert(e);
+                }
+            }
+        }
+        if let Some(e) = failure {
+            return Err(format!("failed to update drifted record: {}", e).into());
+        }
+    }
+
+    Ok((
+        content,
+        RecordStatus::Drifted {
+            remote,
+            fixed: apply,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_api::MockApi;
+    use axum::http::{Method, StatusCode};
+
+    #[tokio::test]
+    async fn content_not_matching_content_regex_is_never_pushed() {
+        // Nothing listens on the discard port: any request would fail with
+        // a connection error instead of the content_regex one.
+        let config: Config = toml::from_str(
+            r#"
+            api_token = "token"
+            api_url = "http://127.0.0.1:9"
+
+            [[dns_records]]
+            dns_name = "home.example.com"
+            proxied = false
+            content_regex = '^9\.9\.'
+            "#,
+        )
+        .unwrap();
+        let account = &config.accounts()[0];
+        let record = &account.dns_records[0];
+
+        let error = check_record(&Client::new(), &config, account, record, "1.1.1.1", true)
+            .await
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(error.contains("does not match content_regex"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn a_failed_apply_is_an_error_not_drift() {
+        let api = MockApi::start(|method, path| match (method, path) {
+            (&Method::GET, "/zones") => (
+                StatusCode::OK,
+                r#"{"success": true, "result": [{"id": "zone", "name": "example.com"}]}"#
+                    .to_string(),
+            ),
+            (&Method::GET, _) => (
+                StatusCode::OK,
+                r#"{"success": true, "result": [
+                    {"id": "one", "name": "home.example.com", "type": "A", "content": "1.1.1.1"}
+                ]}"#
+                .to_string(),
+            ),
+            _ => (StatusCode::INTERNAL_SERVER_ERROR, String::new()),
+        })
+        .await;
+        let config: Config = toml::from_str(&format!(
+            r#"
+            api_token = "token"
+            api_url = "{}"
+
+            [[dns_records]]
+            dns_name = "home.example.com"
+            proxied = false
+            "#,
+            api.url
+        ))
+        .unwrap();
+        let account = &config.accounts()[0];
+        let record = &account.dns_records[0];
+
+        let (_, status) = check_record(&Client::new(), &config, account, record, "9.9.9.9", false)
+            .await
+            .unwrap();
+        assert!(matches!(status, RecordStatus::Drifted { fixed: false, .. }));
+
+        let error = check_record(&Client::new(), &config, account, record, "9.9.9.9", true)
+            .await
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(error.contains("failed to update"), "{}", error);
+        assert!(api
+            .requests()
+            .iter()
+            .any(|(line, _)| line == "PATCH /zones/zone/dns_records/one"));
+    }
+}
@@ -0,0 +1,95 @@
+use std::{error::Error, fs};
+
+use tracing::{error, info, warn};
+
+use crate::{read_limited, state, Config, DnsRecord};
+
+/// Last record list successfully fetched from `records_url`.
+const CACHE_FILE: &str = "records_cache.json";
+
+/// Returns `base` with the records published at `records_url` added to its
+/// top-level records. With `fetch` false, or when the URL can't be loaded or
+/// returns an invalid list, the last good list is used instead.
+pub async fn merged(base: &Config, fetch: bool) -> Config {
+    if base.records_url.is_none() {
+        return base.clone();
+    }
+
+    if fetch {
+        if let Some(config) = reload(base).await {
+            return config;
+        }
+    }
+
+    let Some(records) = read_cache() else {
+        return base.clone();
+    };
+
+    match merge(base, records) {
+        Ok(config) => {
+            if fetch {
+                warn!("Using the last good record list from {}", CACHE_FILE);
+            }
+            config
+        }
+        Err(e) => {
+            error!("Ignoring cached record list in {}: {}", CACHE_FILE, e);
+            base.clone()
+        }
+    }
+}
+
+/// Fetches `records_url` again, returning the merged config only if the new
+/// list is valid so a bad fetch never replaces a running config.
+pub async fn reload(base: &Config) -> Option<Config> {
+    let url = base.records_url.as_ref()?;
+
+    let records = match fetch_records(url, base.max_response_bytes).await {
+        Ok(records) => records,
+        Err(e) => {
+            error!("Failed to load records from {}: {}", url, e);
+            return None;
+        }
+    };
+
+    match merge(base, records.clone()) {
+        Ok(config) => {
+            info!("Loaded {} records from {}", records.len(), url);
+            save_cache(&records);
+            Some(config)
+        }
+        Err(e) => {
+            error!("Rejected records from {}: {}", url, e);
+            None
+        }
+    }
+}
+
+async fn fetch_records(url: &str, limit: usize) -> Result<Vec<DnsRecord>, Box<dyn Error>> {
+    let response = reqwest::get(url).await?.error_for_status()?;
+    let body = read_limited(response, limit).await?;
+    Ok(serde_json::from_str(&body)?)
+}
+
+fn merge(base: &Config, records: Vec<DnsRecord>) -> Result<Config, Box<dyn Error>> {
+    let mut config = base.clone();
+    config.dns_records.extend(records);
+    config.validate()?;
+    Ok(config)
+}
+
+fn read_cache() -> Option<Vec<DnsRecord>> {
+    fs::read_to_string(CACHE_FILE)
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+}
+
+fn save_cache(records: &[DnsRecord]) {
+    let result = serde_json::to_string_pretty(records)
+        .map_err(Into::into)
+        .and_then(|data| state::write_atomic(CACHE_FILE, &data));
+
+    if let Err(e) = result {
+        error!("Failed to cache record list: {:?}", e);
+    }
+}
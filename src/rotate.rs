@@ -0,0 +1,185 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use flate2::{write::GzEncoder, Compression};
+
+/// An append-only file that is rotated once it would grow past `max_bytes`:
+/// `file` becomes `file.1`, `file.1` becomes `file.2` and so on, keeping at
+/// most `max_rotated` old files. Old files are optionally gzipped (`file.1.gz`).
+pub struct RotatingWriter {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_bytes: u64,
+    max_rotated: u32,
+    compress: bool,
+}
+
+impl RotatingWriter {
+    /// Opens `path` for appending. A `max_bytes` of 0 disables rotation.
+    pub fn open(
+        path: impl AsRef<Path>,
+        max_bytes: u64,
+        max_rotated: u32,
+        compress: bool,
+    ) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = open_append(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(RotatingWriter {
+            path,
+            file,
+            size,
+            max_bytes,
+            max_rotated,
+            compress,
+        })
+    }
+
+    /// Name of the `index`-th rotated file.
+    fn rotated_path(&self, index: u32) -> PathBuf {
+        let suffix = if self.compress { ".gz" } else { "" };
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}{}", index, suffix));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        if self.max_rotated == 0 {
+            self.file = File::create(&self.path)?;
+            self.size = 0;
+            return Ok(());
+        }
+
+        remove_if_exists(&self.rotated_path(self.max_rotated))?;
+        for index in (1..self.max_rotated).rev() {
+            let from = self.rotated_path(index);
+            if from.exists() {
+                fs::rename(&from, self.rotated_path(index + 1))?;
+            }
+        }
+
+        let first = self.rotated_path(1);
+        if self.compress {
+            let mut encoder = GzEncoder::new(File::create(&first)?, Compression::default());
+            io::copy(&mut File::open(&self.path)?, &mut encoder)?;
+            encoder.finish()?.sync_all()?;
+            fs::remove_file(&self.path)?;
+        } else {
+            fs::rename(&self.path, &first)?;
+        }
+
+        self.file = open_append(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.max_bytes > 0 && self.size > 0 && self.size + buf.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ddns-rotate-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn files(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn rotates_at_the_size_limit_and_keeps_max_rotated_files() {
+        let dir = temp_dir("plain");
+        let path = dir.join("ddns.log");
+        let mut writer = RotatingWriter::open(&path, 10, 2, false).unwrap();
+
+        writer.write_all(b"first\n").unwrap();
+        writer.write_all(b"abc\n").unwrap();
+        writer.flush().unwrap();
+        assert_eq!(files(&dir), ["ddns.log"]);
+
+        writer.write_all(b"second\n").unwrap();
+        writer.write_all(b"third\n").unwrap();
+        writer.write_all(b"fourth\n").unwrap();
+        writer.flush().unwrap();
+
+        assert_eq!(files(&dir), ["ddns.log", "ddns.log.1", "ddns.log.2"]);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth\n");
+        assert_eq!(
+            fs::read_to_string(dir.join("ddns.log.1")).unwrap(),
+            "third\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("ddns.log.2")).unwrap(),
+            "second\n"
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rotated_files_can_be_gzipped() {
+        let dir = temp_dir("gzip");
+        let path = dir.join("ddns.log");
+        let mut writer = RotatingWriter::open(&path, 8, 1, true).unwrap();
+
+        writer.write_all(b"first\n").unwrap();
+        writer.write_all(b"second\n").unwrap();
+        writer.flush().unwrap();
+
+        assert_eq!(files(&dir), ["ddns.log", "ddns.log.1.gz"]);
+        let mut rotated = String::new();
+        GzDecoder::new(File::open(dir.join("ddns.log.1.gz")).unwrap())
+            .read_to_string(&mut rotated)
+            .unwrap();
+        assert_eq!(rotated, "first\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
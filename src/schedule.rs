@@ -0,0 +1,155 @@
+use std::{str::FromStr, time::Duration};
+
+use chrono::{DateTime, Local, NaiveTime, Utc};
+use cron::Schedule;
+use serde::{Deserialize, Serialize};
+
+use crate::clock::Rng;
+
+/// Daily window (`"HH:MM"`) during which records may be updated. A window
+/// whose end is before its start crosses midnight; equal bounds mean the
+/// whole day.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ActiveHours {
+    pub start: String,
+    pub end: String,
+    /// Interpret the bounds in UTC instead of the host's local time.
+    #[serde(default)]
+    pub utc: bool,
+}
+
+impl ActiveHours {
+    pub fn validate(&self) -> Result<(), String> {
+        self.bounds().map(|_| ())
+    }
+
+    fn bounds(&self) -> Result<(NaiveTime, NaiveTime), String> {
+        let parse = |value: &str| {
+            NaiveTime::parse_from_str(value, "%H:%M")
+                .map_err(|_| format!("Invalid active_hours time {:?}, expected HH:MM", value))
+        };
+
+        Ok((parse(&self.start)?, parse(&self.end)?))
+    }
+
+    /// Whether `now` falls inside the window.
+    pub fn contains(&self, now: DateTime<Utc>) -> bool {
+        let Ok((start, end)) = self.bounds() else {
+            return true;
+        };
+
+        let time = if self.utc {
+            now.time()
+        } else {
+            now.with_timezone(&Local).time()
+        };
+
+        if start <= end {
+            start == end || (start <= time && time < end)
+        } else {
+            time >= start || time < end
+        }
+    }
+}
+
+/// Parses a cron expression in the `cron` crate's syntax, which starts with a
+/// seconds field (`"0 0 * * * *"` is every hour on the hour).
+pub fn parse_cron(expression: &str) -> Result<Schedule, String> {
+    Schedule::from_str(expression).map_err(|e| format!("Invalid schedule {:?}: {}", expression, e))
+}
+
+/// Time from `now` until the next run of `schedule` in local time, or `None`
+/// if it never fires again.
+pub fn delay_until_next(schedule: &Schedule, now: DateTime<Local>) -> Option<Duration> {
+    let next = schedule.after(&now).next()?;
+    Some((next - now).to_std().unwrap_or_default())
+}
+
+/// Longest wait between two consecutive runs of `schedule` over roughly the
+/// week after `now`, or `None` if it runs fewer than twice more.
+pub fn longest_gap(schedule: &Schedule, now: DateTime<Local>) -> Option<Duration> {
+    let mut runs = schedule.after(&now);
+    let mut previous = runs.next()?;
+    let horizon = previous + chrono::Duration::days(8);
+    let mut longest: Option<Duration> = None;
+
+    for run in runs.take(10_000) {
+        let gap = (run - previous).to_std().unwrap_or_default();
+        longest = Some(longest.map_or(gap, |longest| longest.max(gap)));
+        if run > horizon {
+            break;
+        }
+        previous = run;
+    }
+
+    longest
+}
+
+/// A random delay of up to `max_secs`, so several instances on the same
+/// schedule don't all hit the APIs in the same second.
+pub fn jitter(max_secs: u64, rng: &dyn Rng) -> Duration {
+    if max_secs == 0 {
+        return Duration::ZERO;
+    }
+
+    Duration::from_millis(rng.below(max_secs * 1000))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hours(start: &str, end: &str) -> ActiveHours {
+        ActiveHours {
+            start: start.to_string(),
+            end: end.to_string(),
+            utc: true,
+        }
+    }
+
+    fn at(time: &str) -> DateTime<Utc> {
+        format!("2024-03-01T{}:00Z", time).parse().unwrap()
+    }
+
+    #[test]
+    fn contains_times_inside_the_window() {
+        let window = hours("08:00", "18:00");
+        assert!(window.contains(at("08:00")));
+        assert!(window.contains(at("12:30")));
+    }
+
+    #[test]
+    fn excludes_times_outside_the_window() {
+        let window = hours("08:00", "18:00");
+        assert!(!window.contains(at("07:59")));
+        assert!(!window.contains(at("18:00")));
+        assert!(!window.contains(at("23:00")));
+    }
+
+    #[test]
+    fn longest_gap_covers_the_weekend_of_a_weekday_schedule() {
+        // A week without DST changes in either hemisphere.
+        let now = at("12:00").with_timezone(&Local) - chrono::Duration::days(50);
+        let hourly = parse_cron("0 0 * * * *").unwrap();
+        assert_eq!(
+            longest_gap(&hourly, now),
+            Some(Duration::from_secs(60 * 60))
+        );
+
+        let weekdays = parse_cron("0 0 9 * * Mon-Fri").unwrap();
+        assert_eq!(
+            longest_gap(&weekdays, now),
+            Some(Duration::from_secs(3 * 24 * 60 * 60))
+        );
+    }
+
+    #[test]
+    fn a_window_can_cross_midnight() {
+        let window = hours("22:00", "06:00");
+        assert!(window.contains(at("23:30")));
+        assert!(window.contains(at("00:00")));
+        assert!(window.contains(at("05:59")));
+        assert!(!window.contains(at("06:00")));
+        assert!(!window.contains(at("12:00")));
+    }
+}
@@ -0,0 +1,465 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    routing::get,
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+use crate::{clock::Clock, duration, metrics, tls::ServerTlsConfig};
+
+/// A dedicated Prometheus endpoint, e.g. on an internal interface.
+#[derive(Deserialize, Serialize, Clone, Default)]
+pub struct MetricsConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub listen_addr: Option<String>,
+    /// Serve HTTPS instead of HTTP.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls: Option<ServerTlsConfig>,
+}
+
+impl MetricsConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        match &self.tls {
+            Some(tls) => tls.validate(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A health endpoint for orchestrators and load balancers.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct HealthConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub listen_addr: Option<String>,
+    /// How long after startup failed cycles still report `starting`, so a
+    /// slow first resolution doesn't get the process killed.
+    #[serde(default, deserialize_with = "duration::deserialize")]
+    pub startup_grace_secs: u64,
+    /// Status code while `starting`: 503, or 200 to pass probes meanwhile.
+    #[serde(default = "default_starting_status")]
+    pub starting_status: u16,
+    /// Age of the last successful cycle after which the endpoint reports
+    /// `stale`. 0 means three times `check_interval`, or with a `schedule`
+    /// three times its longest gap between runs.
+    #[serde(default, deserialize_with = "duration::deserialize")]
+    pub stale_after_secs: u64,
+    /// Serve HTTPS instead of HTTP.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls: Option<ServerTlsConfig>,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        HealthConfig {
+            listen_addr: None,
+            startup_grace_secs: 0,
+            starting_status: default_starting_status(),
+            stale_after_secs: 0,
+            tls: None,
+        }
+    }
+}
+
+fn default_starting_status() -> u16 {
+    503
+}
+
+impl HealthConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !matches!(self.starting_status, 200 | 503) {
+            return Err(format!(
+                "health.starting_status must be 200 or 503, got {}",
+                self.starting_status
+            ));
+        }
+        match &self.tls {
+            Some(tls) => tls.validate(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Cycles {
+    last: Option<Instant>,
+    last_success: Option<Instant>,
+}
+
+static CYCLES: Mutex<Cycles> = Mutex::new(Cycles {
+    last: None,
+    last_success: None,
+});
+
+/// Records a finished cycle for `/healthz`. A cycle succeeds unless it
+/// failed outright: no IP detected, or no record updated successfully.
+pub fn record_cycle(success: bool, now: Instant) {
+    let mut cycles = CYCLES.lock().unwrap();
+    cycles.last = Some(now);
+    if success {
+        cycles.last_success = Some(now);
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct Health {
+    status: &'static str,
+    /// Seconds since the last cycle finished, if one has.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_cycle_secs: Option<u64>,
+    /// Seconds since the last successful cycle, if one has succeeded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_success_secs: Option<u64>,
+}
+
+#[derive(Clone)]
+struct HealthState {
+    started: Instant,
+    grace: Duration,
+    starting_status: StatusCode,
+    stale_after: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+/// Serves `GET /metrics` on its own listener.
+pub async fn metrics(listen_addr: String, config: MetricsConfig) {
+    let app = Router::new().route("/metrics", get(handle_metrics));
+    serve("metrics", listen_addr, config.tls, app).await;
+}
+
+/// The configured `stale_after_secs`, or by default three times `cycle_gap`,
+/// the longest expected wait between cycles.
+pub fn stale_after(config: &HealthConfig, cycle_gap: Duration) -> Duration {
+    match config.stale_after_secs {
+        0 => cycle_gap.saturating_mul(3),
+        secs => Duration::from_secs(secs),
+    }
+}
+
+/// Serves `GET /healthz` on its own listener. `stale_after` is the
+/// resolved `stale_after_secs`.
+pub async fn health(
+    listen_addr: String,
+    config: HealthConfig,
+    stale_after: Duration,
+    clock: Arc<dyn Clock>,
+) {
+    let state = HealthState::new(&config, stale_after, clock);
+    let app = Router::new()
+        .route("/healthz", get(handle_health))
+        .with_state(state);
+    serve("health", listen_addr, config.tls, app).await;
+}
+
+impl HealthState {
+    fn new(config: &HealthConfig, stale_after: Duration, clock: Arc<dyn Clock>) -> Self {
+        HealthState {
+            started: clock.instant(),
+            grace: Duration::from_secs(config.startup_grace_secs),
+            starting_status: StatusCode::from_u16(config.starting_status)
+                .unwrap_or(StatusCode::SERVICE_UNAVAILABLE),
+            stale_after,
+            clock,
+        }
+    }
+}
+
+/// Binds `listen_addr` and serves `app` until the process exits, over HTTPS
+/// when `tls` is set. A failure is logged and only ends this listener; the
+/// update loop and the other listeners keep running.
+pub async fn serve(name: &str, listen_addr: String, tls: Option<ServerTlsConfig>, app: Router) {
+    let listener = match tokio::net::TcpListener::bind(&listen_addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!(
+                "Failed to bind {} listener on {}: {:?}",
+                name, listen_addr, e
+            );
+            return;
+        }
+    };
+
+    let result = match tls {
+        Some(tls) => serve_tls(name, listener, app, &tls, &listen_addr).await,
+        None => {
+            info!("Serving {} on {}", name, listen_addr);
+            axum::serve(listener, app).await
+        }
+    };
+
+    if let Err(e) = result {
+        error!("The {} server stopped: {:?}", name, e);
+    }
+}
+
+#[cfg(feature = "tls")]
+async fn serve_tls(
+    name: &str,
+    listener: tokio::net::TcpListener,
+    app: Router,
+    tls: &ServerTlsConfig,
+    listen_addr: &str,
+) -> std::io::Result<()> {
+    let config = tls.load().map_err(std::io::Error::other)?;
+    let server = axum_server::from_tcp_rustls(
+        listener.into_std()?,
+        axum_server::tls_rustls::RustlsConfig::from_config(config),
+    )?;
+
+    info!(
+        "Serving {} on {} (TLS{})",
+        name,
+        listen_addr,
+        if tls.client_ca_path.is_some() {
+            ", client certificates required"
+        } else {
+            ""
+        }
+    );
+    server.serve(app.into_make_service()).await
+}
+
+#[cfg(not(feature = "tls"))]
+async fn serve_tls(
+    _name: &str,
+    _listener: tokio::net::TcpListener,
+    _app: Router,
+    tls: &ServerTlsConfig,
+    _listen_addr: &str,
+) -> std::io::Result<()> {
+    tls.validate().map_err(std::io::Error::other)
+}
+
+pub async fn handle_metrics() -> ([(header::HeaderName, &'static str); 1], String) {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics::render(),
+    )
+}
+
+async fn handle_health(State(state): State<HealthState>) -> (StatusCode, Json<Health>) {
+    let cycles = *CYCLES.lock().unwrap();
+    let (code, health) = health_status(&state, cycles, state.clock.instant());
+    (code, Json(health))
+}
+
+/// `starting` until a cycle has finished, and through failed cycles until
+/// the grace period ends; `healthy` while the last success is recent;
+/// `stale` once it is too old, or `unhealthy` if there never was one.
+fn health_status(state: &HealthState, cycles: Cycles, now: Instant) -> (StatusCode, Health) {
+    let starting = cycles.last_success.is_none()
+        && (cycles.last.is_none() || now.duration_since(state.started) < state.grace);
+
+    let (status, code) = match cycles.last_success {
+        _ if starting => ("starting", state.starting_status),
+        Some(success) if now.duration_since(success) <= state.stale_after => {
+            ("healthy", StatusCode::OK)
+        }
+        Some(_) => ("stale", StatusCode::SERVICE_UNAVAILABLE),
+        None => ("unhealthy", StatusCode::SERVICE_UNAVAILABLE),
+    };
+
+    let age = |at: Option<Instant>| at.map(|at| now.duration_since(at).as_secs());
+    (
+        code,
+        Health {
+            status,
+            last_cycle_secs: age(cycles.last),
+            last_success_secs: age(cycles.last_success),
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::fake::FakeClock, schedule};
+
+    #[test]
+    fn health_follows_the_fake_clock() {
+        let clock = Arc::new(FakeClock::default());
+        let state = HealthState::new(
+            &HealthConfig::default(),
+            Duration::from_secs(60),
+            clock.clone(),
+        );
+        let mut cycles = Cycles {
+            last: None,
+            last_success: None,
+        };
+
+        let (code, health) = health_status(&state, cycles, clock.instant());
+        assert_eq!(
+            (code, health.status),
+            (StatusCode::SERVICE_UNAVAILABLE, "starting")
+        );
+
+        clock.advance(Duration::from_secs(10));
+        cycles.last = Some(clock.instant());
+        cycles.last_success = cycles.last;
+        clock.advance(Duration::from_secs(5));
+        let (code, health) = health_status(&state, cycles, clock.instant());
+        assert_eq!(code, StatusCode::OK);
+        assert_eq!(
+            health,
+            Health {
+                status: "healthy",
+                last_cycle_secs: Some(5),
+                last_success_secs: Some(5),
+            }
+        );
+    }
+
+    #[test]
+    fn failed_cycles_report_starting_during_the_grace_period() {
+        let clock = Arc::new(FakeClock::default());
+        let config = HealthConfig {
+            startup_grace_secs: 60,
+            starting_status: 200,
+            ..HealthConfig::default()
+        };
+        let state = HealthState::new(&config, Duration::from_secs(300), clock.clone());
+
+        clock.advance(Duration::from_secs(30));
+        let cycles = Cycles {
+            last: Some(clock.instant()),
+            last_success: None,
+        };
+        let (code, health) = health_status(&state, cycles, clock.instant());
+        assert_eq!((code, health.status), (StatusCode::OK, "starting"));
+
+        clock.advance(Duration::from_secs(30));
+        let (code, health) = health_status(&state, cycles, clock.instant());
+        assert_eq!(
+            (code, health.status),
+            (StatusCode::SERVICE_UNAVAILABLE, "unhealthy")
+        );
+    }
+
+    #[test]
+    fn an_old_success_is_stale() {
+        let clock = Arc::new(FakeClock::default());
+        let state = HealthState::new(
+            &HealthConfig::default(),
+            Duration::from_secs(300),
+            clock.clone(),
+        );
+        let success = clock.instant();
+        let cycles = Cycles {
+            last: Some(success),
+            last_success: Some(success),
+        };
+
+        clock.advance(Duration::from_secs(300));
+        let (code, health) = health_status(&state, cycles, clock.instant());
+        assert_eq!((code, health.status), (StatusCode::OK, "healthy"));
+
+        clock.advance(Duration::from_secs(1));
+        let (code, health) = health_status(&state, cycles, clock.instant());
+        assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            health,
+            Health {
+                status: "stale",
+                last_cycle_secs: Some(301),
+                last_success_secs: Some(301),
+            }
+        );
+    }
+
+    #[test]
+    fn an_hourly_schedule_is_only_stale_after_three_hours() {
+        let clock = Arc::new(FakeClock::default());
+        let hourly = schedule::parse_cron("0 0 * * * *").unwrap();
+        let gap = schedule::longest_gap(&hourly, clock.local()).unwrap();
+        let default = stale_after(&HealthConfig::default(), gap);
+        assert_eq!(default, Duration::from_secs(3 * 60 * 60));
+
+        let state = HealthState::new(&HealthConfig::default(), default, clock.clone());
+        let success = clock.instant();
+        let cycles = Cycles {
+            last: Some(success),
+            last_success: Some(success),
+        };
+
+        // Well past three check_intervals, but only two scheduled runs.
+        clock.advance(Duration::from_secs(2 * 60 * 60));
+        let (_, health) = health_status(&state, cycles, clock.instant());
+        assert_eq!(health.status, "healthy");
+
+        clock.advance(Duration::from_secs(60 * 60 + 1));
+        let (_, health) = health_status(&state, cycles, clock.instant());
+        assert_eq!(health.status, "stale");
+
+        let configured = HealthConfig {
+            stale_after_secs: 600,
+            ..HealthConfig::default()
+        };
+        assert_eq!(stale_after(&configured, gap), Duration::from_secs(600));
+    }
+
+    #[test]
+    fn listener_tls_is_validated() {
+        let tls = ServerTlsConfig {
+            cert_path: "/nonexistent/cert.pem".to_string(),
+            key_path: "/nonexistent/key.pem".to_string(),
+            client_ca_path: None,
+        };
+
+        let metrics = MetricsConfig {
+            listen_addr: Some("127.0.0.1:9100".to_string()),
+            tls: Some(tls.clone()),
+        };
+        let health = HealthConfig {
+            tls: Some(tls),
+            ..HealthConfig::default()
+        };
+        assert!(metrics.validate().is_err());
+        assert!(health.validate().is_err());
+        assert!(MetricsConfig::default().validate().is_ok());
+    }
+
+    #[tokio::test]
+    async fn a_busy_port_only_stops_its_own_listener() {
+        let busy = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let busy_addr = busy.local_addr().unwrap().to_string();
+        let free_addr = {
+            let probe = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            probe.local_addr().unwrap().to_string()
+        };
+
+        let failed = metrics(busy_addr, MetricsConfig::default());
+        tokio::time::timeout(Duration::from_secs(5), failed)
+            .await
+            .expect("a listener that can't bind returns");
+
+        let clock: Arc<dyn Clock> = Arc::new(FakeClock::default());
+        let serving = tokio::spawn(health(
+            free_addr.clone(),
+            HealthConfig::default(),
+            Duration::from_secs(900),
+            clock,
+        ));
+
+        let url = format!("http://{}/healthz", free_addr);
+        let mut answered = None;
+        for _ in 0..50 {
+            if let Ok(response) = reqwest::get(&url).await {
+                answered = Some(response.status());
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(answered, Some(reqwest::StatusCode::SERVICE_UNAVAILABLE));
+        assert!(!serving.is_finished());
+
+        serving.abort();
+        drop(busy);
+    }
+}
@@ -0,0 +1,167 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::{
+    signal::unix::{signal, Signal, SignalKind},
+    sync::Notify,
+};
+use tracing::{error, info, warn};
+
+/// Exit code used when shutdown is forced, as if killed by SIGTERM.
+const FORCED_EXIT_CODE: i32 = 128 + 15;
+
+/// Set by the first SIGTERM: the main loop finishes the cycle in progress
+/// (including its notifications and state save) and then exits.
+#[derive(Default)]
+pub struct Drain {
+    requested: AtomicBool,
+    notify: Notify,
+}
+
+impl Drain {
+    pub fn requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    /// Completes once a drain has been requested.
+    pub async fn wait(&self) {
+        if !self.requested() {
+            self.notify.notified().await;
+        }
+    }
+
+    fn request(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+        self.notify.notify_one();
+    }
+}
+
+/// Starts listening for SIGTERM. The first signal requests a drain and gives
+/// it `timeout` to finish; a second signal, or the timeout running out,
+/// exits immediately.
+pub fn listen(timeout: Duration) -> Arc<Drain> {
+    let drain = Arc::new(Drain::default());
+
+    let terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(e) => {
+            error!("Failed to listen for SIGTERM: {:?}", e);
+            return drain;
+        }
+    };
+
+    let handle = drain.clone();
+    tokio::spawn(async move {
+        supervise(terminate, &handle, timeout).await;
+        std::process::exit(FORCED_EXIT_CODE);
+    });
+
+    drain
+}
+
+/// Termination signals, so tests can send their own.
+trait Signals {
+    /// Waits for the next signal; `None` once no more can arrive.
+    async fn recv(&mut self) -> Option<()>;
+}
+
+impl Signals for Signal {
+    async fn recv(&mut self) -> Option<()> {
+        Signal::recv(self).await
+    }
+}
+
+/// Why a drain was cut short.
+#[derive(Debug, PartialEq)]
+enum Forced {
+    SecondSignal,
+    Timeout,
+}
+
+/// Requests a drain on the first signal, then returns once shutdown has to
+/// be forced. Never returns if no signal arrives.
+async fn supervise(mut signals: impl Signals, drain: &Drain, timeout: Duration) -> Forced {
+    if signals.recv().await.is_none() {
+        return std::future::pending().await;
+    }
+    info!(
+        "SIGTERM received, draining: finishing the current cycle before exiting (send SIGTERM again to exit now)"
+    );
+    drain.request();
+
+    let second = async {
+        match signals.recv().await {
+            Some(()) => {}
+            None => std::future::pending().await,
+        }
+    };
+    tokio::select! {
+        _ = second => {
+            warn!("Second SIGTERM received, exiting immediately");
+            Forced::SecondSignal
+        }
+        _ = tokio::time::sleep(timeout) => {
+            error!(
+                "Drain did not finish within {} seconds, exiting immediately",
+                timeout.as_secs()
+            );
+            Forced::Timeout
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+    use super::*;
+
+    impl Signals for UnboundedReceiver<()> {
+        async fn recv(&mut self) -> Option<()> {
+            UnboundedReceiver::recv(self).await
+        }
+    }
+
+    #[tokio::test]
+    async fn one_sigterm_drains_without_forcing_an_exit() {
+        let (send, signals) = unbounded_channel();
+        let drain = Drain::default();
+        send.send(()).unwrap();
+
+        let supervised = supervise(signals, &drain, Duration::from_secs(60));
+        let forced = tokio::time::timeout(Duration::from_millis(100), supervised).await;
+
+        assert!(forced.is_err(), "shutdown was forced: {:?}", forced);
+        assert!(drain.requested());
+        drain.wait().await;
+    }
+
+    #[tokio::test]
+    async fn a_second_sigterm_exits_immediately() {
+        let (send, signals) = unbounded_channel();
+        let drain = Drain::default();
+        send.send(()).unwrap();
+        send.send(()).unwrap();
+
+        let forced = supervise(signals, &drain, Duration::from_secs(60)).await;
+
+        assert_eq!(forced, Forced::SecondSignal);
+        assert!(drain.requested());
+    }
+
+    #[tokio::test]
+    async fn a_drain_that_takes_too_long_is_cut_short() {
+        let (send, signals) = unbounded_channel();
+        let drain = Drain::default();
+        send.send(()).unwrap();
+
+        let forced = supervise(signals, &drain, Duration::from_millis(10)).await;
+
+        assert_eq!(forced, Forced::Timeout);
+    }
+}
@@ -0,0 +1,313 @@
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fs,
+    net::IpAddr,
+    path::Path,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "redis")]
+mod redis;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+/// Last IP pushed for each record, keyed by record name.
+pub type LastIps = BTreeMap<String, String>;
+
+/// The Cloudflare IDs a record was resolved to.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RecordIds {
+    pub zone_id: String,
+    pub record_ids: Vec<String>,
+}
+
+/// The last failure of a record.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct RecordError {
+    pub error: String,
+    /// Unix timestamp of the failure.
+    pub at: u64,
+}
+
+/// Last error per record, keyed like `LastIps`.
+pub type RecordErrors = BTreeMap<String, RecordError>;
+
+/// Key of the last errors in the JSON state file. Record keys always hold a
+/// dot, so it can't clash with one.
+const ERRORS_KEY: &str = "_errors";
+
+/// Where the last pushed IPs are kept between cycles and restarts.
+pub trait StateStore: Send + Sync {
+    /// Returns the stored state, or an empty state if none could be read.
+    fn load(&self) -> LastIps;
+
+    fn save(&self, ips: &LastIps) -> Result<(), Box<dyn Error>>;
+
+    /// Returns the freshest stored IP for a single record.
+    fn get(&self, record: &str) -> Option<String> {
+        self.load().remove(record)
+    }
+
+    /// When `record` last changed, for backends that keep timestamps.
+    fn updated_at(&self, _record: &str) -> Option<SystemTime> {
+        None
+    }
+
+    /// Claims the right to update `record`. Backends shared by several
+    /// instances use this so only one of them pushes a given change.
+    fn lock(&self, _record: &str) -> bool {
+        true
+    }
+
+    fn unlock(&self, _record: &str) {}
+
+    /// The IDs last stored for `record`, for backends that keep them.
+    fn ids(&self, _record: &str) -> Option<RecordIds> {
+        None
+    }
+
+    /// Stores the IDs `record` was resolved to, for backends that keep them.
+    fn save_ids(&self, _record: &str, _ids: &RecordIds) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    /// The last error of every record that failed since its last success.
+    fn errors(&self) -> RecordErrors;
+
+    /// Stores the last error of `record`, or clears it with `None`.
+    fn save_error(&self, record: &str, error: Option<&RecordError>) -> Result<(), Box<dyn Error>>;
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum StateFormat {
+    /// Indented JSON that is easy to read and edit by hand.
+    #[default]
+    Pretty,
+    /// Single-line JSON, smaller and faster to write.
+    Compact,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StateBackend {
+    #[default]
+    Json,
+    /// Requires building with the `sqlite` feature.
+    Sqlite,
+    /// Requires building with the `redis` feature.
+    Redis,
+}
+
+/// Opens the configured state backend. `db_path` and `redis_url` are only
+/// used by the SQLite and Redis backends respectively.
+#[cfg_attr(
+    not(all(feature = "sqlite", feature = "redis")),
+    allow(unused_variables)
+)]
+pub fn open(
+    backend: StateBackend,
+    json_path: &str,
+    format: StateFormat,
+    db_path: &str,
+    redis_url: Option<&str>,
+) -> Result<Box<dyn StateStore>, Box<dyn Error>> {
+    let json = JsonFileStore::new(json_path, format);
+
+    match backend {
+        StateBackend::Json => Ok(Box::new(json)),
+        #[cfg(feature = "sqlite")]
+        StateBackend::Sqlite => Ok(Box::new(sqlite::SqliteStore::open(db_path, &json)?)),
+        #[cfg(not(feature = "sqlite"))]
+        StateBackend::Sqlite => {
+            Err("state_backend = \"sqlite\" requires building with the sqlite feature".into())
+        }
+        #[cfg(feature = "redis")]
+        StateBackend::Redis => {
+            let url = redis_url.ok_or("state_backend = \"redis\" requires redis_url")?;
+            Ok(Box::new(redis::RedisStore::open(url)?))
+        }
+        #[cfg(not(feature = "redis"))]
+        StateBackend::Redis => {
+            Err("state_backend = \"redis\" requires building with the redis feature".into())
+        }
+    }
+}
+
+/// Converts a stored Unix timestamp back into a `SystemTime`.
+#[cfg_attr(not(any(feature = "sqlite", feature = "redis")), allow(dead_code))]
+fn from_unix_secs(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+/// Writes `data` to a temporary file next to `path` and renames it into
+/// place, so a crash mid-write never leaves a truncated file behind.
+pub fn write_atomic(path: &str, data: &str) -> Result<(), Box<dyn Error>> {
+    let tmp = format!("{}.tmp", path);
+    fs::write(&tmp, data)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Outcome of [`JsonFileStore::repair`].
+pub struct RepairSummary {
+    pub kept: usize,
+    pub dropped: Vec<String>,
+    pub backup: Option<String>,
+}
+
+/// The default backend: a single JSON file.
+pub struct JsonFileStore {
+    path: String,
+    format: StateFormat,
+    /// Held while the file is read and rewritten, so saving IPs and errors
+    /// at once doesn't drop either.
+    writing: Mutex<()>,
+}
+
+impl JsonFileStore {
+    pub fn new(path: &str, format: StateFormat) -> Self {
+        JsonFileStore {
+            path: path.to_string(),
+            format,
+            writing: Mutex::default(),
+        }
+    }
+
+    /// The IPs and last errors in the state file. Errors sit under
+    /// `ERRORS_KEY`, which is left out while there are none, so the file
+    /// keeps the plain name-to-IP layout of older versions.
+    fn read(&self) -> (LastIps, RecordErrors) {
+        let Some(serde_json::Value::Object(mut entries)) = fs::read_to_string(&self.path)
+            .ok()
+            .and_then(|data| serde_json::from_str(&data).ok())
+        else {
+            return Default::default();
+        };
+
+        let errors = entries
+            .remove(ERRORS_KEY)
+            .and_then(|errors| serde_json::from_value(errors).ok())
+            .unwrap_or_default();
+        let ips = serde_json::from_value(serde_json::Value::Object(entries)).unwrap_or_default();
+        (ips, errors)
+    }
+
+    fn write(&self, ips: &LastIps, errors: &RecordErrors) -> Result<(), Box<dyn Error>> {
+        write_atomic(&self.path, &self.encode(ips, errors)?)
+    }
+
+    fn encode(&self, ips: &LastIps, errors: &RecordErrors) -> Result<String, Box<dyn Error>> {
+        let mut entries = serde_json::Map::new();
+        for (name, ip) in ips {
+            entries.insert(name.clone(), ip.clone().into());
+        }
+        if !errors.is_empty() {
+            entries.insert(ERRORS_KEY.to_string(), serde_json::to_value(errors)?);
+        }
+
+        Ok(match self.format {
+            StateFormat::Pretty => serde_json::to_string_pretty(&entries)?,
+            StateFormat::Compact => serde_json::to_string(&entries)?,
+        })
+    }
+
+    /// Copies the current file to a timestamped backup, returning its path,
+    /// or `None` if there is no file to back up.
+    fn backup(&self) -> Result<Option<String>, Box<dyn Error>> {
+        if !Path::new(&self.path).exists() {
+            return Ok(None);
+        }
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        let backup = format!("{}.{}.bak", self.path, now);
+        fs::copy(&self.path, &backup)?;
+        Ok(Some(backup))
+    }
+
+    /// Backs up the state file and replaces it with an empty state.
+    pub fn reset(&self) -> Result<Option<String>, Box<dyn Error>> {
+        let backup = self.backup()?;
+        self.write(&LastIps::new(), &RecordErrors::new())?;
+        Ok(backup)
+    }
+
+    /// Rewrites the state file keeping only entries that map a record name to
+    /// a valid IP. A file that isn't a JSON object at all is replaced with an
+    /// empty state. The original is backed up first.
+    pub fn repair(&self) -> Result<RepairSummary, Box<dyn Error>> {
+        let data = match fs::read_to_string(&self.path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(RepairSummary {
+                    kept: 0,
+                    dropped: Vec::new(),
+                    backup: None,
+                })
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut ips = LastIps::new();
+        let mut errors = RecordErrors::new();
+        let mut dropped = Vec::new();
+        match serde_json::from_str::<serde_json::Value>(&data) {
+            Ok(serde_json::Value::Object(entries)) => {
+                for (name, value) in entries {
+                    if name == ERRORS_KEY {
+                        match serde_json::from_value(value) {
+                            Ok(valid) => errors = valid,
+                            Err(_) => dropped.push(name),
+                        }
+                        continue;
+                    }
+                    match value.as_str() {
+                        Some(ip) if ip.parse::<IpAddr>().is_ok() => {
+                            ips.insert(name, ip.to_string());
+                        }
+                        _ => dropped.push(name),
+                    }
+                }
+            }
+            _ => dropped.push("<entire file: not a JSON object>".to_string()),
+        }
+
+        let backup = self.backup()?;
+        self.write(&ips, &errors)?;
+
+        Ok(RepairSummary {
+            kept: ips.len(),
+            dropped,
+            backup,
+        })
+    }
+}
+
+impl StateStore for JsonFileStore {
+    fn load(&self) -> LastIps {
+        self.read().0
+    }
+
+    fn save(&self, ips: &LastIps) -> Result<(), Box<dyn Error>> {
+        let _writing = self.writing.lock().unwrap();
+        self.write(ips, &self.read().1)
+    }
+
+    fn errors(&self) -> RecordErrors {
+        self.read().1
+    }
+
+    fn save_error(&self, record: &str, error: Option<&RecordError>) -> Result<(), Box<dyn Error>> {
+        let _writing = self.writing.lock().unwrap();
+        let (ips, mut errors) = self.read();
+        match error {
+            Some(error) => errors.insert(record.to_string(), error.clone()),
+            None => errors.remove(record),
+        };
+        self.write(&ips, &errors)
+    }
+}
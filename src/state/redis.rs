@@ -0,0 +1,270 @@
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use redis::Connection;
+use tracing::{error, warn};
+
+use super::{from_unix_secs, LastIps, RecordError, RecordErrors, StateStore};
+
+const KEY_PREFIX: &str = "ddns";
+/// How long an update lock is held if the owning instance dies mid-update.
+const LOCK_TTL_MS: u64 = 60_000;
+
+/// Sets `KEYS[1]` to `ARGV[2]` and `KEYS[2]` to `ARGV[3]` only if `KEYS[1]`
+/// still holds `ARGV[1]` (empty for a missing key). Returns 1 if the key now
+/// holds the new value, 0 if another instance changed it meanwhile.
+const COMPARE_AND_SET: &str = r"
+local current = redis.call('GET', KEYS[1])
+if current == ARGV[2] then return 1 end
+if (current or '') ~= ARGV[1] then return 0 end
+redis.call('SET', KEYS[1], ARGV[2])
+redis.call('SET', KEYS[2], ARGV[3])
+return 1
+";
+
+/// Shares state between several instances through Redis. Each record is
+/// stored under `ddns:ip:<name>` and `ddns:updated_at:<name>`, its last error
+/// as JSON under `ddns:error:<name>`, and updates are serialized with a
+/// short-lived `ddns:lock:<name>` key.
+pub struct RedisStore {
+    conn: Mutex<Connection>,
+    instance_id: String,
+    /// What this instance last read from or wrote to Redis, so a save only
+    /// writes the records it changed itself.
+    seen: Mutex<LastIps>,
+}
+
+impl RedisStore {
+    pub fn open(url: &str) -> Result<Self, Box<dyn Error>> {
+        let conn = redis::Client::open(url)?.get_connection()?;
+        let instance_id = format!(
+            "{}-{}",
+            std::process::id(),
+            SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos()
+        );
+
+        Ok(RedisStore {
+            conn: Mutex::new(conn),
+            instance_id,
+            seen: Mutex::default(),
+        })
+    }
+
+    fn read_all(&self) -> redis::RedisResult<LastIps> {
+        self.read_kind("ip")
+    }
+
+    /// Every `ddns:<kind>:<name>` value, keyed by name.
+    fn read_kind(&self, kind: &str) -> redis::RedisResult<BTreeMap<String, String>> {
+        let mut conn = self.conn.lock().unwrap();
+        let prefix = format!("{}:{}:", KEY_PREFIX, kind);
+        let mut values = BTreeMap::new();
+        let mut cursor: u64 = 0;
+
+        loop {
+            let (next, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(format!("{}*", prefix))
+                .query(&mut *conn)?;
+
+            for key in keys {
+                let value: Option<String> = redis::cmd("GET").arg(&key).query(&mut *conn)?;
+                if let (Some(name), Some(value)) = (key.strip_prefix(&prefix), value) {
+                    values.insert(name.to_string(), value);
+                }
+            }
+
+            if next == 0 {
+                return Ok(values);
+            }
+            cursor = next;
+        }
+    }
+}
+
+impl StateStore for RedisStore {
+    fn load(&self) -> LastIps {
+        match self.read_all() {
+            Ok(ips) => {
+                *self.seen.lock().unwrap() = ips.clone();
+                ips
+            }
+            Err(e) => {
+                error!("Failed to read Redis state: {:?}", e);
+                LastIps::new()
+            }
+        }
+    }
+
+    fn get(&self, record: &str) -> Option<String> {
+        let ip: Option<String> = {
+            let mut conn = self.conn.lock().unwrap();
+            redis::cmd("GET")
+                .arg(format!("{}:ip:{}", KEY_PREFIX, record))
+                .query(&mut *conn)
+                .unwrap_or_else(|e| {
+                    error!("Failed to read Redis state for {}: {:?}", record, e);
+                    None
+                })
+        };
+
+        if let Some(ip) = &ip {
+            self.seen
+                .lock()
+                .unwrap()
+                .insert(record.to_string(), ip.clone());
+        }
+        ip
+    }
+
+    fn updated_at(&self, record: &str) -> Option<SystemTime> {
+        let mut conn = self.conn.lock().unwrap();
+        let secs: Option<u64> = redis::cmd("GET")
+            .arg(format!("{}:updated_at:{}", KEY_PREFIX, record))
+            .query(&mut *conn)
+            .unwrap_or_else(|e| {
+                error!("Failed to read Redis state for {}: {:?}", record, e);
+                None
+            });
+
+        secs.map(from_unix_secs)
+    }
+
+    /// Writes only the records whose IP differs from what this instance last
+    /// saw in Redis, and each only if no other instance changed it since, so
+    /// a stale local copy never overwrites a newer value.
+    fn save(&self, ips: &LastIps) -> Result<(), Box<dyn Error>> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let script = redis::Script::new(COMPARE_AND_SET);
+        let mut seen = self.seen.lock().unwrap();
+        let mut written = Vec::new();
+
+        {
+            let mut conn = self.conn.lock().unwrap();
+            for (name, ip, expected) in changed(&seen, ips) {
+                let stored: i64 = script
+                    .key(format!("{}:ip:{}", KEY_PREFIX, name))
+                    .key(format!("{}:updated_at:{}", KEY_PREFIX, name))
+                    .arg(expected.map_or("", String::as_str))
+                    .arg(ip)
+                    .arg(now)
+                    .invoke(&mut *conn)?;
+
+                if stored == 1 {
+                    written.push((name.clone(), ip.clone()));
+                } else {
+                    warn!(
+                        "{} was changed in Redis by another instance, keeping its value",
+                        name
+                    );
+                }
+            }
+        }
+
+        seen.extend(written);
+        Ok(())
+    }
+
+    fn lock(&self, record: &str) -> bool {
+        let mut conn = self.conn.lock().unwrap();
+        let acquired: redis::RedisResult<Option<String>> = redis::cmd("SET")
+            .arg(format!("{}:lock:{}", KEY_PREFIX, record))
+            .arg(&self.instance_id)
+            .arg("NX")
+            .arg("PX")
+            .arg(LOCK_TTL_MS)
+            .query(&mut *conn);
+
+        match acquired {
+            Ok(reply) => reply.is_some(),
+            Err(e) => {
+                warn!("Failed to acquire Redis lock for {}: {:?}", record, e);
+                false
+            }
+        }
+    }
+
+    fn unlock(&self, record: &str) {
+        // Only delete the lock if this instance still owns it.
+        let script = redis::Script::new(
+            "if redis.call('GET', KEYS[1]) == ARGV[1] then return redis.call('DEL', KEYS[1]) end return 0",
+        );
+
+        let mut conn = self.conn.lock().unwrap();
+        if let Err(e) = script
+            .key(format!("{}:lock:{}", KEY_PREFIX, record))
+            .arg(&self.instance_id)
+            .invoke::<i64>(&mut *conn)
+        {
+            warn!("Failed to release Redis lock for {}: {:?}", record, e);
+        }
+    }
+
+    fn errors(&self) -> RecordErrors {
+        match self.read_kind("error") {
+            Ok(errors) => errors
+                .into_iter()
+                .filter_map(|(name, error)| Some((name, serde_json::from_str(&error).ok()?)))
+                .collect(),
+            Err(e) => {
+                error!("Failed to read Redis record errors: {:?}", e);
+                RecordErrors::new()
+            }
+        }
+    }
+
+    fn save_error(&self, record: &str, error: Option<&RecordError>) -> Result<(), Box<dyn Error>> {
+        let key = format!("{}:error:{}", KEY_PREFIX, record);
+        let mut conn = self.conn.lock().unwrap();
+        match error {
+            Some(error) => redis::cmd("SET")
+                .arg(key)
+                .arg(serde_json::to_string(error)?)
+                .query::<()>(&mut *conn)?,
+            None => redis::cmd("DEL").arg(key).query::<()>(&mut *conn)?,
+        }
+        Ok(())
+    }
+}
+
+/// Entries of `ips` that differ from what was `seen` in Redis, with the
+/// value seen for them.
+fn changed<'a>(
+    seen: &'a LastIps,
+    ips: &'a LastIps,
+) -> impl Iterator<Item = (&'a String, &'a String, Option<&'a String>)> {
+    ips.iter()
+        .map(|(name, ip)| (name, ip, seen.get(name)))
+        .filter(|(_, ip, seen)| *seen != Some(*ip))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ips(entries: &[(&str, &str)]) -> LastIps {
+        entries
+            .iter()
+            .map(|(name, ip)| (name.to_string(), ip.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn only_records_changed_since_they_were_seen_are_written() {
+        let seen = ips(&[("a", "9.9.9.9"), ("b", "1.1.1.1")]);
+        let local = ips(&[("a", "9.9.9.9"), ("b", "8.8.8.8"), ("c", "2620:fe::9")]);
+
+        let changed: Vec<_> = changed(&seen, &local)
+            .map(|(name, ip, seen)| (name.as_str(), ip.as_str(), seen.map(String::as_str)))
+            .collect();
+        assert_eq!(
+            changed,
+            [("b", "8.8.8.8", Some("1.1.1.1")), ("c", "2620:fe::9", None)]
+        );
+    }
+}
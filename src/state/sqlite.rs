@@ -0,0 +1,319 @@
+use std::{
+    error::Error,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rusqlite::{params, Connection, OptionalExtension};
+use tracing::{error, info};
+
+use super::{
+    from_unix_secs, JsonFileStore, LastIps, RecordError, RecordErrors, RecordIds, StateStore,
+};
+
+/// Keeps one row per record in a SQLite database, which scales better than
+/// rewriting a single JSON file when there are many records. Rows that only
+/// hold resolved IDs or a last error have an empty `ip` until the record is
+/// first pushed.
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    /// Opens (or creates) the database at `path`. If it holds no records yet,
+    /// the existing JSON state from `legacy` is imported once.
+    pub fn open(path: &str, legacy: &JsonFileStore) -> Result<Self, Box<dyn Error>> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS records (
+                name TEXT PRIMARY KEY,
+                ip TEXT NOT NULL,
+                updated_at INTEGER NOT NULL,
+                zone_id TEXT,
+                record_ids TEXT,
+                last_error TEXT,
+                last_error_at INTEGER
+            )",
+        )?;
+        add_columns(&conn)?;
+
+        let count: i64 = conn.query_row("SELECT COUNT(*) FROM records", [], |row| row.get(0))?;
+        let store = SqliteStore {
+            conn: Mutex::new(conn),
+        };
+
+        if count == 0 {
+            let ips = legacy.load();
+            if !ips.is_empty() {
+                info!(
+                    "Migrating {} records from JSON state into {}",
+                    ips.len(),
+                    path
+                );
+                store.save(&ips)?;
+            }
+            for (name, error) in legacy.errors() {
+                store.save_error(&name, Some(&error))?;
+            }
+        }
+
+        Ok(store)
+    }
+
+    fn read_all(&self) -> rusqlite::Result<LastIps> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare("SELECT name, ip FROM records WHERE ip != ''")?;
+        let rows = statement.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+
+        rows.collect()
+    }
+
+    fn read_errors(&self) -> rusqlite::Result<RecordErrors> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare(
+            "SELECT name, last_error, last_error_at FROM records WHERE last_error IS NOT NULL",
+        )?;
+        let rows = statement.query_map([], |row| {
+            let at: i64 = row.get(2)?;
+            Ok((
+                row.get(0)?,
+                RecordError {
+                    error: row.get(1)?,
+                    at: at.max(0) as u64,
+                },
+            ))
+        })?;
+
+        rows.collect()
+    }
+}
+
+impl StateStore for SqliteStore {
+    fn load(&self) -> LastIps {
+        self.read_all().unwrap_or_else(|e| {
+            error!("Failed to read SQLite state: {:?}", e);
+            LastIps::new()
+        })
+    }
+
+    fn updated_at(&self, record: &str) -> Option<SystemTime> {
+        let conn = self.conn.lock().unwrap();
+        let secs: Option<i64> = conn
+            .query_row(
+                "SELECT updated_at FROM records WHERE name = ?1 AND ip != ''",
+                params![record],
+                |row| row.get(0),
+            )
+            .optional()
+            .unwrap_or_else(|e| {
+                error!("Failed to read SQLite state for {}: {:?}", record, e);
+                None
+            });
+
+        secs.map(|secs| from_unix_secs(secs.max(0) as u64))
+    }
+
+    fn save(&self, ips: &LastIps) -> Result<(), Box<dyn Error>> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+
+        for (name, ip) in ips {
+            // Only bump the timestamp when the stored IP actually changes.
+            tx.execute(
+                "INSERT INTO records (name, ip, updated_at) VALUES (?1, ?2, ?3)
+                 ON CONFLICT(name) DO UPDATE SET ip = excluded.ip, updated_at = excluded.updated_at
+                 WHERE records.ip != excluded.ip",
+                params![name, ip, now],
+            )?;
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+    fn ids(&self, record: &str) -> Option<RecordIds> {
+        let conn = self.conn.lock().unwrap();
+        let row: Option<(Option<String>, Option<String>)> = conn
+            .query_row(
+                "SELECT zone_id, record_ids FROM records WHERE name = ?1",
+                params![record],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .unwrap_or_else(|e| {
+                error!("Failed to read SQLite IDs for {}: {:?}", record, e);
+                None
+            });
+
+        let (Some(zone_id), Some(record_ids)) = row? else {
+            return None;
+        };
+        Some(RecordIds {
+            zone_id,
+            record_ids: serde_json::from_str(&record_ids).ok()?,
+        })
+    }
+
+    fn save_ids(&self, record: &str, ids: &RecordIds) -> Result<(), Box<dyn Error>> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO records (name, ip, updated_at, zone_id, record_ids)
+             VALUES (?1, '', 0, ?2, ?3)
+             ON CONFLICT(name) DO UPDATE SET zone_id = excluded.zone_id, record_ids = excluded.record_ids",
+            params![record, ids.zone_id, serde_json::to_string(&ids.record_ids)?],
+        )?;
+        Ok(())
+    }
+
+    fn errors(&self) -> RecordErrors {
+        self.read_errors().unwrap_or_else(|e| {
+            error!("Failed to read SQLite record errors: {:?}", e);
+            RecordErrors::new()
+        })
+    }
+
+    fn save_error(&self, record: &str, error: Option<&RecordError>) -> Result<(), Box<dyn Error>> {
+        let conn = self.conn.lock().unwrap();
+        match error {
+            Some(error) => conn.execute(
+                "INSERT INTO records (name, ip, updated_at, last_error, last_error_at)
+                 VALUES (?1, '', 0, ?2, ?3)
+                 ON CONFLICT(name) DO UPDATE SET last_error = excluded.last_error,
+                     last_error_at = excluded.last_error_at",
+                params![record, error.error, error.at as i64],
+            )?,
+            None => conn.execute(
+                "UPDATE records SET last_error = NULL, last_error_at = NULL WHERE name = ?1",
+                params![record],
+            )?,
+        };
+        Ok(())
+    }
+}
+
+/// Adds the ID and error columns to databases created before they existed.
+fn add_columns(conn: &Connection) -> rusqlite::Result<()> {
+    let columns: Vec<String> = conn
+        .prepare("SELECT name FROM pragma_table_info('records')")?
+        .query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+
+    for (column, kind) in [
+        ("zone_id", "TEXT"),
+        ("record_ids", "TEXT"),
+        ("last_error", "TEXT"),
+        ("last_error_at", "INTEGER"),
+    ] {
+        if !columns.iter().any(|c| c == column) {
+            conn.execute_batch(&format!(
+                "ALTER TABLE records ADD COLUMN {} {}",
+                column, kind
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::StateFormat;
+
+    fn temp_path(name: &str) -> String {
+        let path =
+            std::env::temp_dir().join(format!("ddns-sqlite-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn json_state_is_migrated_into_an_empty_database() {
+        let json_path = temp_path("migrate.json");
+        let legacy = JsonFileStore::new(&json_path, StateFormat::Compact);
+        let ips = LastIps::from([
+            ("home.example.com".to_string(), "9.9.9.9".to_string()),
+            (
+                "home.example.com/AAAA".to_string(),
+                "2620:fe::9".to_string(),
+            ),
+        ]);
+        legacy.save(&ips).unwrap();
+
+        let db_path = temp_path("migrate.db");
+        let store = SqliteStore::open(&db_path, &legacy).unwrap();
+        assert_eq!(store.load(), ips);
+        assert!(store.updated_at("home.example.com").is_some());
+
+        // Once the database holds records, the JSON file is left alone.
+        legacy.save(&LastIps::new()).unwrap();
+        drop(store);
+        assert_eq!(SqliteStore::open(&db_path, &legacy).unwrap().load(), ips);
+    }
+
+    #[test]
+    fn resolved_ids_are_kept_apart_from_pushed_ips() {
+        let legacy = JsonFileStore::new(&temp_path("ids.json"), StateFormat::Compact);
+        let store = SqliteStore::open(&temp_path("ids.db"), &legacy).unwrap();
+        let ids = RecordIds {
+            zone_id: "zone".to_string(),
+            record_ids: vec!["one".to_string(), "two".to_string()],
+        };
+
+        store.save_ids("home.example.com", &ids).unwrap();
+        assert_eq!(store.ids("home.example.com"), Some(ids.clone()));
+        assert!(store.load().is_empty());
+        assert_eq!(store.updated_at("home.example.com"), None);
+
+        let ips = LastIps::from([("home.example.com".to_string(), "9.9.9.9".to_string())]);
+        store.save(&ips).unwrap();
+        assert_eq!(store.load(), ips);
+        assert_eq!(store.ids("home.example.com"), Some(ids));
+    }
+
+    #[test]
+    fn id_columns_are_added_to_older_databases() {
+        let db_path = temp_path("old.db");
+        Connection::open(&db_path)
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE records (
+                    name TEXT PRIMARY KEY,
+                    ip TEXT NOT NULL,
+                    updated_at INTEGER NOT NULL
+                );
+                INSERT INTO records VALUES ('home.example.com', '9.9.9.9', 1700000000);",
+            )
+            .unwrap();
+
+        let legacy = JsonFileStore::new(&temp_path("old.json"), StateFormat::Compact);
+        let store = SqliteStore::open(&db_path, &legacy).unwrap();
+        assert_eq!(store.get("home.example.com").as_deref(), Some("9.9.9.9"));
+        assert_eq!(store.ids("home.example.com"), None);
+        assert!(store.errors().is_empty());
+    }
+
+    #[test]
+    fn last_errors_are_kept_until_cleared() {
+        let json_path = temp_path("errors.json");
+        let legacy = JsonFileStore::new(&json_path, StateFormat::Compact);
+        let failed = RecordError {
+            error: "zone not found".to_string(),
+            at: 1_700_000_000,
+        };
+        legacy.save_error("old.example.com", Some(&failed)).unwrap();
+
+        // Errors in the JSON state come along with the migration.
+        let store = SqliteStore::open(&temp_path("errors.db"), &legacy).unwrap();
+        assert_eq!(
+            store.errors(),
+            RecordErrors::from([("old.example.com".to_string(), failed.clone())])
+        );
+        assert!(store.load().is_empty());
+
+        let ips = LastIps::from([("old.example.com".to_string(), "9.9.9.9".to_string())]);
+        store.save(&ips).unwrap();
+        store.save_error("old.example.com", None).unwrap();
+        assert!(store.errors().is_empty());
+        assert_eq!(store.load(), ips);
+    }
+}
@@ -0,0 +1,253 @@
+use std::{
+    error::Error,
+    hash::{BuildHasher, RandomState},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use tokio::net::{lookup_host, UdpSocket};
+
+use crate::RecordType;
+
+/// Port used when the server is given without one.
+const DEFAULT_PORT: u16 = 3478;
+
+/// How long to wait for an answer before sending the request again; UDP
+/// gives no other sign that a packet was lost.
+const ATTEMPT_TIMEOUT: Duration = Duration::from_secs(2);
+const ATTEMPTS: u32 = 3;
+
+const MAGIC_COOKIE: u32 = 0x2112_a442;
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS: u16 = 0x0101;
+const MAPPED_ADDRESS: u16 = 0x0001;
+const XOR_MAPPED_ADDRESS: u16 = 0x0020;
+
+/// Asks the STUN server at `server` (`host` or `host:port`) which address
+/// our request came from, over the family of `record_type`.
+pub async fn public_ip(server: &str, record_type: RecordType) -> Result<IpAddr, Box<dyn Error>> {
+    let target = if server.rsplit_once(':').is_some_and(|(host, port)| {
+        !host.is_empty()
+            && port.parse::<u16>().is_ok()
+            && (host.ends_with(']') || !host.contains(':'))
+    }) {
+        server.to_string()
+    } else {
+        format!("{}:{}", server, DEFAULT_PORT)
+    };
+
+    let address = lookup_host(&target)
+        .await
+        .map_err(|e| format!("Failed to resolve STUN server {}: {}", server, e))?
+        .find(|address| record_type.accepts(&address.ip().to_string()))
+        .ok_or_else(|| {
+            format!(
+                "STUN server {} has no address usable for {} records",
+                server, record_type
+            )
+        })?;
+
+    let local: SocketAddr = match address {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(address).await?;
+
+    let transaction = transaction_id();
+    let request = binding_request(&transaction);
+    let mut buffer = [0u8; 1024];
+
+    for _ in 0..ATTEMPTS {
+        socket.send(&request).await?;
+
+        let deadline = Instant::now() + ATTEMPT_TIMEOUT;
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            let Ok(received) = tokio::time::timeout(remaining, socket.recv(&mut buffer)).await
+            else {
+                break;
+            };
+            // Anything that isn't the answer to our request is ignored.
+            if let Some(ip) = parse_response(&buffer[..received?], &transaction) {
+                return Ok(ip);
+            }
+        }
+    }
+
+    Err(format!(
+        "STUN server {} did not answer within {}s",
+        server,
+        (ATTEMPT_TIMEOUT * ATTEMPTS).as_secs()
+    )
+    .into())
+}
+
+fn transaction_id() -> [u8; 12] {
+    let state = RandomState::new();
+    let high = state.hash_one(Instant::now()).to_be_bytes();
+    let low = state.hash_one(high).to_be_bytes();
+
+    let mut id = [0u8; 12];
+    id[..8].copy_from_slice(&high);
+    id[8..].copy_from_slice(&low[..4]);
+    id
+}
+
+/// A binding request without attributes (RFC 5389, section 6).
+fn binding_request(transaction: &[u8; 12]) -> Vec<u8> {
+    let mut request = Vec::with_capacity(20);
+    request.extend_from_slice(&BINDING_REQUEST.to_be_bytes());
+    request.extend_from_slice(&0u16.to_be_bytes());
+    request.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    request.extend_from_slice(transaction);
+    request
+}
+
+/// The mapped address from a binding success response to `transaction`,
+/// preferring XOR-MAPPED-ADDRESS over the older MAPPED-ADDRESS.
+fn parse_response(message: &[u8], transaction: &[u8; 12]) -> Option<IpAddr> {
+    let header = message.get(..20)?;
+    let message_type = u16::from_be_bytes([header[0], header[1]]);
+    let length = usize::from(u16::from_be_bytes([header[2], header[3]]));
+    if message_type != BINDING_SUCCESS
+        || header[4..8] != MAGIC_COOKIE.to_be_bytes()
+        || header[8..20] != transaction[..]
+    {
+        return None;
+    }
+
+    let mut attributes = message.get(20..20 + length)?;
+    let mut mapped = None;
+    while attributes.len() >= 4 {
+        let kind = u16::from_be_bytes([attributes[0], attributes[1]]);
+        let len = usize::from(u16::from_be_bytes([attributes[2], attributes[3]]));
+        let value = attributes.get(4..4 + len)?;
+
+        match kind {
+            XOR_MAPPED_ADDRESS => return parse_address(value, Some(transaction)),
+            MAPPED_ADDRESS => mapped = parse_address(value, None),
+            _ => {}
+        }
+
+        // Attribute values are padded to a multiple of four bytes.
+        let padded = (4 + len).div_ceil(4) * 4;
+        attributes = attributes.get(padded.min(attributes.len())..)?;
+    }
+
+    mapped
+}
+
+/// Decodes a (XOR-)MAPPED-ADDRESS value; `transaction` is given for the XOR
+/// variant, whose address is masked with the cookie and transaction ID.
+fn parse_address(value: &[u8], transaction: Option<&[u8; 12]>) -> Option<IpAddr> {
+    let family = *value.get(1)?;
+    let mut mask = [0u8; 16];
+    if let Some(transaction) = transaction {
+        mask[..4].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        mask[4..].copy_from_slice(transaction);
+    }
+
+    match family {
+        0x01 => {
+            let bytes: [u8; 4] = value.get(4..8)?.try_into().ok()?;
+            let address: [u8; 4] = std::array::from_fn(|i| bytes[i] ^ mask[i]);
+            Some(IpAddr::from(address))
+        }
+        0x02 => {
+            let bytes: [u8; 16] = value.get(4..20)?.try_into().ok()?;
+            let address: [u8; 16] = std::array::from_fn(|i| bytes[i] ^ mask[i]);
+            Some(IpAddr::from(address))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Transaction ID of the sample responses in RFC 5769, section 2.
+    const TRANSACTION: [u8; 12] = [
+        0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6, 0x86, 0xfa, 0x87, 0xdf, 0xae,
+    ];
+
+    /// XOR-MAPPED-ADDRESS for 192.0.2.1:32853 (RFC 5769, section 2.2).
+    const XOR_MAPPED_V4: [u8; 12] = [
+        0x00, 0x20, 0x00, 0x08, 0x00, 0x01, 0xa1, 0x47, 0xe1, 0x12, 0xa6, 0x43,
+    ];
+
+    /// XOR-MAPPED-ADDRESS for [2001:db8:1234:5678:11:2233:4455:6677]:32853
+    /// (RFC 5769, section 2.3).
+    const XOR_MAPPED_V6: [u8; 24] = [
+        0x00, 0x20, 0x00, 0x14, 0x00, 0x02, 0xa1, 0x47, 0x01, 0x13, 0xa9, 0xfa, 0xa5, 0xd3, 0xf1,
+        0x79, 0xbc, 0x25, 0xf4, 0xb5, 0xbe, 0xd2, 0xb9, 0xd9,
+    ];
+
+    fn response(transaction: &[u8; 12], attributes: &[u8]) -> Vec<u8> {
+        let mut message = Vec::new();
+        message.extend_from_slice(&BINDING_SUCCESS.to_be_bytes());
+        message.extend_from_slice(&(attributes.len() as u16).to_be_bytes());
+        message.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        message.extend_from_slice(transaction);
+        message.extend_from_slice(attributes);
+        message
+    }
+
+    #[test]
+    fn xor_mapped_addresses_are_decoded() {
+        let v4 = response(&TRANSACTION, &XOR_MAPPED_V4);
+        assert_eq!(
+            parse_response(&v4, &TRANSACTION),
+            Some("192.0.2.1".parse().unwrap())
+        );
+
+        let v6 = response(&TRANSACTION, &XOR_MAPPED_V6);
+        assert_eq!(
+            parse_response(&v6, &TRANSACTION),
+            Some("2001:db8:1234:5678:11:2233:4455:6677".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn xor_mapped_address_is_preferred_over_mapped_address() {
+        let mapped = [
+            0x00, 0x01, 0x00, 0x08, 0x00, 0x01, 0x80, 0x55, 198, 51, 100, 7,
+        ];
+        let attributes = [&mapped[..], &XOR_MAPPED_V4[..]].concat();
+
+        let message = response(&TRANSACTION, &attributes);
+        assert_eq!(
+            parse_response(&message, &TRANSACTION),
+            Some("192.0.2.1".parse().unwrap())
+        );
+
+        let message = response(&TRANSACTION, &mapped);
+        assert_eq!(
+            parse_response(&message, &TRANSACTION),
+            Some("198.51.100.7".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn a_response_to_another_transaction_is_ignored() {
+        let mut other = TRANSACTION;
+        other[11] ^= 0xff;
+
+        let message = response(&other, &XOR_MAPPED_V4);
+        assert_eq!(parse_response(&message, &TRANSACTION), None);
+    }
+
+    #[test]
+    fn truncated_messages_are_rejected() {
+        // The attribute claims eight bytes of value but carries four.
+        let truncated = response(&TRANSACTION, &XOR_MAPPED_V4[..8]);
+        assert_eq!(parse_response(&truncated, &TRANSACTION), None);
+
+        // The header claims more attributes than the message holds.
+        let mut short = response(&TRANSACTION, &XOR_MAPPED_V4);
+        short.truncate(short.len() - 2);
+        assert_eq!(parse_response(&short, &TRANSACTION), None);
+
+        assert_eq!(parse_response(&short[..10], &TRANSACTION), None);
+    }
+}
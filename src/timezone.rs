@@ -0,0 +1,84 @@
+use std::{fmt, sync::Mutex, time::SystemTime};
+
+use chrono::SecondsFormat;
+use chrono_tz::Tz;
+use tracing_subscriber::fmt::{format::Writer, time::FormatTime};
+
+/// Zone of human-facing timestamps, set from `timezone` once the config is
+/// loaded. State files keep UTC regardless.
+static ZONE: Mutex<Tz> = Mutex::new(Tz::UTC);
+
+pub fn parse(name: &str) -> Result<Tz, String> {
+    name.parse().map_err(|_| {
+        format!(
+            "Unknown timezone {:?}, expected an IANA name such as \"Europe/Warsaw\"",
+            name
+        )
+    })
+}
+
+pub fn set(zone: Tz) {
+    *ZONE.lock().unwrap() = zone;
+}
+
+/// `time` as RFC 3339 in the configured zone, e.g.
+/// `2026-10-14T15:37:41+02:00`.
+pub fn format(time: SystemTime) -> String {
+    format_in(time, *ZONE.lock().unwrap())
+}
+
+fn format_in(time: SystemTime, zone: Tz) -> String {
+    chrono::DateTime::<chrono::Utc>::from(time)
+        .with_timezone(&zone)
+        .to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+pub fn now() -> String {
+    format(SystemTime::now())
+}
+
+/// Log timestamps in the configured zone, with the same precision as the
+/// default timer.
+pub struct LogTimer;
+
+impl FormatTime for LogTimer {
+    fn format_time(&self, w: &mut Writer<'_>) -> fmt::Result {
+        let zone = *ZONE.lock().unwrap();
+        write!(
+            w,
+            "{}",
+            chrono::Utc::now()
+                .with_timezone(&zone)
+                .to_rfc3339_opts(SecondsFormat::Micros, true)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+
+    #[test]
+    fn formats_in_the_named_zone() {
+        // 2023-11-14T22:13:20Z
+        let time = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        assert_eq!(format_in(time, Tz::UTC), "2023-11-14T22:13:20Z");
+        assert_eq!(
+            format_in(time, parse("Europe/Warsaw").unwrap()),
+            "2023-11-14T23:13:20+01:00"
+        );
+        assert_eq!(
+            format_in(time, parse("America/New_York").unwrap()),
+            "2023-11-14T17:13:20-05:00"
+        );
+    }
+
+    #[test]
+    fn unknown_zones_are_rejected() {
+        let error = parse("Mars/Olympus").unwrap_err();
+        assert!(error.contains("IANA"), "{}", error);
+    }
+}
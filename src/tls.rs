@@ -0,0 +1,130 @@
+#[cfg(feature = "tls")]
+use std::{fs, sync::Arc};
+
+#[cfg(feature = "tls")]
+use rustls::{
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    server::WebPkiClientVerifier,
+    RootCertStore, ServerConfig,
+};
+use serde::{Deserialize, Serialize};
+
+#[cfg(not(feature = "tls"))]
+const FEATURE_REQUIRED: &str = "TLS settings require building with the tls feature";
+
+/// Certificate served by the webhook, metrics or health listener. With
+/// `client_ca_path` set, clients must also present a certificate signed by
+/// that CA.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ServerTlsConfig {
+    pub cert_path: String,
+    pub key_path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_ca_path: Option<String>,
+}
+
+/// Client certificate presented by the notifier to endpoints requiring mTLS.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ClientTlsConfig {
+    pub cert_path: String,
+    pub key_path: String,
+}
+
+impl ServerTlsConfig {
+    /// Checks that the certificates and key can actually be loaded.
+    pub fn validate(&self) -> Result<(), String> {
+        #[cfg(feature = "tls")]
+        return self.load().map(|_| ());
+        #[cfg(not(feature = "tls"))]
+        Err(FEATURE_REQUIRED.to_string())
+    }
+
+    #[cfg(feature = "tls")]
+    pub fn load(&self) -> Result<Arc<ServerConfig>, String> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| format!("Failed to set up TLS: {}", e))?;
+
+        let builder = match &self.client_ca_path {
+            Some(ca_path) => {
+                let mut roots = RootCertStore::empty();
+                for cert in read_certs(ca_path)? {
+                    roots
+                        .add(cert)
+                        .map_err(|e| format!("Invalid CA certificate in {}: {}", ca_path, e))?;
+                }
+
+                let verifier =
+                    WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                        .build()
+                        .map_err(|e| format!("Invalid client CA {}: {}", ca_path, e))?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+
+        let config = builder
+            .with_single_cert(read_certs(&self.cert_path)?, read_key(&self.key_path)?)
+            .map_err(|e| {
+                format!(
+                    "Certificate {} cannot be used with key {}: {}",
+                    self.cert_path, self.key_path, e
+                )
+            })?;
+
+        Ok(Arc::new(config))
+    }
+}
+
+impl ClientTlsConfig {
+    /// Checks that the certificate and key can actually be loaded.
+    pub fn validate(&self) -> Result<(), String> {
+        #[cfg(feature = "tls")]
+        return self.identity().map(|_| ());
+        #[cfg(not(feature = "tls"))]
+        Err(FEATURE_REQUIRED.to_string())
+    }
+
+    #[cfg(feature = "tls")]
+    pub fn identity(&self) -> Result<reqwest::Identity, String> {
+        // Parse both files first so a malformed one is named in the error.
+        read_certs(&self.cert_path)?;
+        read_key(&self.key_path)?;
+
+        let mut pem = read_file(&self.cert_path)?;
+        pem.push(b'\n');
+        pem.extend(read_file(&self.key_path)?);
+
+        reqwest::Identity::from_pem(&pem).map_err(|e| {
+            format!(
+                "Certificate {} cannot be used with key {}: {}",
+                self.cert_path, self.key_path, e
+            )
+        })
+    }
+}
+
+#[cfg(feature = "tls")]
+fn read_file(path: &str) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))
+}
+
+#[cfg(feature = "tls")]
+fn read_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, String> {
+    let certs = CertificateDer::pem_slice_iter(&read_file(path)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Malformed certificate in {}: {}", path, e))?;
+
+    if certs.is_empty() {
+        return Err(format!("No PEM certificates found in {}", path));
+    }
+
+    Ok(certs)
+}
+
+#[cfg(feature = "tls")]
+fn read_key(path: &str) -> Result<PrivateKeyDer<'static>, String> {
+    PrivateKeyDer::from_pem_slice(&read_file(path)?)
+        .map_err(|e| format!("No usable PEM private key in {}: {}", path, e))
+}
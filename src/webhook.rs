@@ -0,0 +1,141 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    routing::post,
+    Router,
+};
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+use crate::{server, tls::ServerTlsConfig};
+
+pub const TOKEN_HEADER: &str = "X-Webhook-Token";
+
+#[derive(Clone)]
+struct WebhookState {
+    token: String,
+    trigger: Arc<Notify>,
+}
+
+/// Runs an HTTP server that wakes the update loop whenever an authenticated
+/// `POST /update` arrives. With `tls` set it serves HTTPS instead. Metrics
+/// are only served by the `[metrics]` listener, never here.
+pub async fn serve(
+    listen_addr: String,
+    token: String,
+    tls: Option<ServerTlsConfig>,
+    trigger: Arc<Notify>,
+) {
+    let app = Router::new()
+        .route("/update", post(handle_update))
+        .with_state(WebhookState { token, trigger });
+
+    server::serve("update webhooks", listen_addr, tls, app).await;
+}
+
+async fn handle_update(State(state): State<WebhookState>, headers: HeaderMap) -> StatusCode {
+    let provided = headers
+        .get(TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+
+    if !tokens_match(provided.as_bytes(), state.token.as_bytes()) {
+        warn!("Rejected webhook request with a missing or invalid token");
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    info!("Webhook received, triggering an update cycle");
+    state.trigger.notify_one();
+    StatusCode::ACCEPTED
+}
+
+/// Compares tokens in time that depends only on their length, so response
+/// times don't reveal how much of a guessed token was right.
+fn tokens_match(provided: &[u8], expected: &[u8]) -> bool {
+    provided.len() == expected.len()
+        && provided
+            .iter()
+            .zip(expected)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_must_match_exactly() {
+        assert!(tokens_match(b"secret", b"secret"));
+        assert!(!tokens_match(b"secreT", b"secret"));
+        assert!(!tokens_match(b"secret!", b"secret"));
+        assert!(!tokens_match(b"", b"secret"));
+    }
+
+    #[tokio::test]
+    async fn update_requires_the_token() {
+        let trigger = Arc::new(Notify::new());
+        let state = WebhookState {
+            token: "secret".to_string(),
+            trigger: trigger.clone(),
+        };
+        let with_token = |token: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(TOKEN_HEADER, token.parse().unwrap());
+            headers
+        };
+
+        assert_eq!(
+            handle_update(State(state.clone()), HeaderMap::new()).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            handle_update(State(state.clone()), with_token("guess")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            handle_update(State(state), with_token("secret")).await,
+            StatusCode::ACCEPTED
+        );
+        // The accepted request left a permit for the update loop.
+        trigger.notified().await;
+    }
+
+    #[tokio::test]
+    async fn metrics_are_not_served_on_the_webhook_listener() {
+        let addr = {
+            let probe = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            probe.local_addr().unwrap().to_string()
+        };
+        let trigger = Arc::new(Notify::new());
+        let serving = tokio::spawn(serve(
+            addr.clone(),
+            "secret".to_string(),
+            None,
+            trigger.clone(),
+        ));
+
+        let client = reqwest::Client::new();
+        let mut accepted = None;
+        for _ in 0..50 {
+            let request = client
+                .post(format!("http://{}/update", addr))
+                .header(TOKEN_HEADER, "secret");
+            if let Ok(response) = request.send().await {
+                accepted = Some(response.status());
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert_eq!(accepted, Some(reqwest::StatusCode::ACCEPTED));
+
+        let metrics = reqwest::get(format!("http://{}/metrics", addr))
+            .await
+            .unwrap();
+        assert_eq!(metrics.status(), reqwest::StatusCode::NOT_FOUND);
+
+        serving.abort();
+    }
+}